@@ -0,0 +1,25 @@
+//! Benchmark of the parse and dispatch path every command goes through.
+//!
+//! Run it with `cargo bench --bench dispatch`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use my_redis::bench::parse_command;
+
+fn dispatch(c: &mut Criterion) {
+    let commands: [(&str, &[u8]); 3] = [
+        ("get", b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n"),
+        (
+            "set",
+            b"*5\r\n$3\r\nset\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$2\r\nEX\r\n$2\r\n10\r\n",
+        ),
+        ("unknown", b"*2\r\n$7\r\nUNKNOWN\r\n$3\r\nfoo\r\n"),
+    ];
+    for (name, src) in commands {
+        c.bench_function(&format!("dispatch {}", name), |b| {
+            b.iter(|| parse_command(black_box(src)).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,326 @@
+//! The append-only file: the write commands are logged as they are applied, and replayed when the
+//! server starts, so the data survives a restart.
+//!
+//! Commands are logged as received, except that a TTL relative to the time of the command, e.g.
+//! with `SET ... EX`, is logged as an absolute Unix time, so it doesn't start again when the command
+//! is replayed.
+
+use crate::client::Client;
+use crate::cmd::Command;
+use crate::config::Params;
+use crate::db::Db;
+use crate::frame::{self, Frame, Limits};
+use crate::stats::Stats;
+use crate::time_util;
+use anyhow::anyhow;
+use bytes::Bytes;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Mutex, Weak};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// How often the log is synced to the disk, like Redis `appendfsync everysec`.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The log of the write commands, shared by all the connections.
+#[derive(Debug)]
+pub(crate) struct Aof {
+    writer: Mutex<Writer>,
+    /// The same file as the writer, to sync it without holding the lock of the writer.
+    file: File,
+}
+
+#[derive(Debug)]
+struct Writer {
+    /// Commands are buffered, they reach the file when the buffer is full or on [Aof::sync].
+    file: BufWriter<File>,
+    /// Database of the last logged command, `None` until one is logged.
+    db: Option<usize>,
+}
+
+impl Aof {
+    /// Replay the commands logged in the file at `path` on `dbs`, then open it to log the next
+    /// ones. A missing file is created.
+    ///
+    /// A command cut at the end of the file, e.g. by a crash, is dropped from the file. The commands
+    /// are checked against `limits`, like the ones sent by the clients.
+    pub(crate) async fn open(path: &Path, dbs: &[Db], params: &Params, limits: &Limits) -> crate::Result<Aof> {
+        let log = match tokio::fs::read(path).await {
+            Ok(log) => log,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+        let len = replay(&log, dbs, params, limits).await?;
+        if len < log.len() {
+            warn!(path = %path.display(), dropped = log.len() - len, "truncated append-only file");
+        }
+        info!(path = %path.display(), bytes = len, "loaded append-only file");
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.set_len(len as u64)?;
+        Ok(Aof {
+            writer: Mutex::new(Writer {
+                file: BufWriter::new(file.try_clone()?),
+                db: None,
+            }),
+            file,
+        })
+    }
+
+    /// Log `frame`, a write command applied to the database `db`.
+    pub(crate) fn append(&self, db: usize, frame: &Frame) -> io::Result<()> {
+        let now = time_util::instant_to_unix_ms(Instant::now());
+        let absolute = absolute_ttl(frame, now);
+        let frame = absolute.as_ref().unwrap_or(frame);
+        let mut buf = vec![];
+        let mut writer = self.writer.lock().unwrap();
+        // Like Redis, the database is only logged when it changes.
+        if writer.db != Some(db) {
+            select(db).serialize_into(&mut buf);
+            writer.db = Some(db);
+        }
+        frame.serialize_into(&mut buf);
+        writer.file.write_all(&buf)
+    }
+
+    /// Write the buffered commands to the file, and sync it to the disk.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().file.flush()?;
+        self.file.sync_data()
+    }
+}
+
+/// Sync `aof` every [SYNC_INTERVAL], until it's dropped. Up to that long of writes is lost on a
+/// crash, rather than syncing on every command.
+pub(crate) async fn sync_periodically(aof: Weak<Aof>) {
+    let mut interval = tokio::time::interval(SYNC_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(aof) = aof.upgrade() else {
+            return;
+        };
+        // Syncing blocks until the disk is done.
+        match tokio::task::spawn_blocking(move || aof.sync()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => error!(cause = ?err, "failed to sync the append-only file"),
+            Err(err) => error!(cause = ?err, "failed to sync the append-only file"),
+        }
+    }
+}
+
+/// Apply the commands of `log` on `dbs`, and return the length of the complete commands.
+async fn replay(log: &[u8], dbs: &[Db], params: &Params, limits: &Limits) -> crate::Result<usize> {
+    // The commands run as a client of their own, that starts on the first database.
+    let mut client = Client::new(SocketAddr::from(([0, 0, 0, 0], 0)));
+    client.loading = true;
+    // Replayed commands are not counted by INFO.
+    let stats = Stats::new();
+    let mut src = Cursor::new(log);
+    loop {
+        let start = src.position() as usize;
+        if start == log.len() {
+            return Ok(start);
+        }
+        let frame = match Frame::check(&mut src, limits).and_then(|_| {
+            src.set_position(start as u64);
+            Frame::parse(&mut src)
+        }) {
+            Ok(frame) => frame,
+            Err(frame::Error::Incomplete) => return Ok(start),
+            Err(frame::Error::Other(err)) => return Err(err.context("invalid append-only file")),
+        };
+        let cmd = Command::from_frame(frame)?;
+        if !cmd.is_write() && !matches!(cmd, Command::Select(_)) {
+            return Err(anyhow!("invalid append-only file; unexpected '{}' command", cmd.name()));
+        }
+        cmd.execute(dbs, &stats, params, &mut client).await?;
+    }
+}
+
+/// Rewrite a command setting a TTL relative to `now`, a Unix time in milliseconds, with the
+/// deadline instead, like Redis: `EXPIRE`, `PEXPIRE` and `GETEX` with `EX` or `PX` become
+/// `PEXPIREAT`, `SET` gets `PXAT` instead of `EX` or `PX`. `None` if there is nothing to rewrite.
+///
+/// `frame` is a command that was applied, so its arguments are valid.
+fn absolute_ttl(frame: &Frame, now: i64) -> Option<Frame> {
+    let Frame::Array(frames) = frame else {
+        return None;
+    };
+    let mut args = frames
+        .iter()
+        .map(|frame| match frame {
+            Frame::Bulk(arg) => Some(arg.clone()),
+            _ => None,
+        })
+        .collect::<Option<Vec<Bytes>>>()?;
+    let name = args.first()?.to_ascii_lowercase();
+    // The position of the TTL, and its unit in milliseconds.
+    let (at, unit) = match &name[..] {
+        b"expire" => (2, 1000),
+        b"pexpire" => (2, 1),
+        // The options follow the key, and the value for SET.
+        b"set" | b"getex" => {
+            let first = if name == b"set" { 3 } else { 2 };
+            let option = (first..args.len())
+                .find(|&i| args[i].eq_ignore_ascii_case(b"EX") || args[i].eq_ignore_ascii_case(b"PX"))?;
+            let unit = if args[option].eq_ignore_ascii_case(b"EX") {
+                1000
+            } else {
+                1
+            };
+            (option + 1, unit)
+        }
+        _ => return None,
+    };
+    let ttl: i64 = std::str::from_utf8(args.get(at)?).ok()?.parse().ok()?;
+    let deadline = Bytes::from(ttl.saturating_mul(unit).saturating_add(now).to_string());
+    if name == b"set" {
+        args[at - 1] = Bytes::from_static(b"PXAT");
+        args[at] = deadline;
+    } else {
+        args = vec![Bytes::from_static(b"PEXPIREAT"), args[1].clone(), deadline];
+    }
+    Some(Frame::Array(args.into_iter().map(Frame::Bulk).collect()))
+}
+
+/// The `SELECT db` command.
+fn select(db: usize) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::from_static(b"SELECT")),
+        Frame::Bulk(Bytes::from(db.to_string())),
+    ])
+}
+
+#[cfg(test)]
+mod test_aof {
+    use super::*;
+    use crate::config::Config;
+    use crate::db::DbGuard;
+
+    fn command(args: &[&str]) -> Frame {
+        Frame::Array(args.iter().map(|arg| Frame::Bulk(arg.to_string().into())).collect())
+    }
+
+    #[tokio::test]
+    async fn test_append_replay() {
+        let path = std::env::temp_dir().join(format!("my-redis-{}.aof", nanoid::nanoid!()));
+        let params = Params::new(&Config::default());
+        let limits = Config::default().frame_limits();
+        let aof = Aof::open(&path, &DbGuard::new(2).dbs(), &params, &limits)
+            .await
+            .unwrap();
+        aof.append(0, &command(&["SET", "foo", "bar"])).unwrap();
+        aof.append(1, &command(&["SET", "foo", "baz"])).unwrap();
+        aof.append(1, &command(&["RPUSH", "list", "a", "b"])).unwrap();
+        aof.append(0, &command(&["DEL", "foo"])).unwrap();
+        aof.sync().unwrap();
+        drop(aof);
+
+        // A crash in the middle of a command, it's dropped.
+        let mut log = std::fs::read(&path).unwrap();
+        let len = log.len();
+        log.extend_from_slice(b"*2\r\n$3\r\nDEL\r\n$4\r\nli");
+        std::fs::write(&path, log).unwrap();
+
+        let dbs = DbGuard::new(2).dbs();
+        let aof = Aof::open(&path, &dbs, &params, &limits).await.unwrap();
+        assert_eq!(dbs[0].get("foo"), Ok(None));
+        assert_eq!(dbs[1].get("foo"), Ok(Some(Bytes::from("baz"))));
+        assert_eq!(dbs[1].llen("list"), Ok(2));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len as u64);
+
+        // The database is logged again, it's unknown once reopened.
+        aof.append(1, &command(&["DEL", "list"])).unwrap();
+        drop(aof);
+        let dbs = DbGuard::new(2).dbs();
+        Aof::open(&path, &dbs, &params, &limits).await.unwrap();
+        assert_eq!(dbs[1].llen("list"), Ok(0));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_absolute_ttl() {
+        let now = 1_700_000_000_000;
+        let rewrite = |args: &[&str]| absolute_ttl(&command(args), now);
+        assert_eq!(
+            rewrite(&["SET", "foo", "bar", "nx", "ex", "10", "GET"]),
+            Some(command(&["SET", "foo", "bar", "nx", "PXAT", "1700000010000", "GET"]))
+        );
+        assert_eq!(
+            rewrite(&["SET", "foo", "bar", "PX", "10"]),
+            Some(command(&["SET", "foo", "bar", "PXAT", "1700000000010"]))
+        );
+        assert_eq!(
+            rewrite(&["EXPIRE", "foo", "10"]),
+            Some(command(&["PEXPIREAT", "foo", "1700000010000"]))
+        );
+        assert_eq!(
+            rewrite(&["pexpire", "foo", "10"]),
+            Some(command(&["PEXPIREAT", "foo", "1700000000010"]))
+        );
+        assert_eq!(
+            rewrite(&["GETEX", "foo", "EX", "10"]),
+            Some(command(&["PEXPIREAT", "foo", "1700000010000"]))
+        );
+        // The value of SET is not an option, nor are absolute deadlines rewritten.
+        assert_eq!(rewrite(&["SET", "foo", "EX"]), None);
+        assert_eq!(rewrite(&["SET", "foo", "bar", "PXAT", "1700000000010"]), None);
+        assert_eq!(rewrite(&["GETEX", "foo", "PERSIST"]), None);
+        assert_eq!(rewrite(&["DEL", "foo"]), None);
+    }
+
+    #[tokio::test]
+    async fn test_replay_ttl() {
+        let path = std::env::temp_dir().join(format!("my-redis-{}.aof", nanoid::nanoid!()));
+        let params = Params::new(&Config::default());
+        let limits = Config::default().frame_limits();
+        let aof = Aof::open(&path, &DbGuard::new(1).dbs(), &params, &limits)
+            .await
+            .unwrap();
+        aof.append(0, &command(&["SET", "foo", "bar", "PX", "100"])).unwrap();
+        aof.append(0, &command(&["SET", "baz", "qux"])).unwrap();
+        aof.append(0, &command(&["PEXPIRE", "baz", "100"])).unwrap();
+        aof.sync().unwrap();
+        drop(aof);
+
+        // The TTLs don't start again on replay.
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let dbs = DbGuard::new(1).dbs();
+        Aof::open(&path, &dbs, &params, &limits).await.unwrap();
+        assert_eq!(dbs[0].get("foo"), Ok(None));
+        assert_eq!(dbs[0].get("baz"), Ok(None));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_over_maxmemory() {
+        let dbs = DbGuard::new(1).dbs();
+        let params = Params::new(&Config::default());
+        assert!(params.set("maxmemory", "1".to_string()));
+        let limits = Config::default().frame_limits();
+        let log = [command(&["SET", "foo", "bar"]), command(&["SET", "baz", "qux"])];
+        let mut buf = vec![];
+        log.iter().for_each(|frame| frame.serialize_into(&mut buf));
+        // Without eviction, the writes are replayed anyway.
+        replay(&buf, &dbs, &params, &limits).await.unwrap();
+        assert_eq!(dbs[0].get("baz"), Ok(Some(Bytes::from("qux"))));
+    }
+
+    #[tokio::test]
+    async fn test_replay_invalid() {
+        let dbs = DbGuard::new(1).dbs();
+        let params = Params::new(&Config::default());
+        let limits = Config::default().frame_limits();
+        let err = replay(b"*1\r\n$4\r\nPING\r\n", &dbs, &params, &limits)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid append-only file; unexpected 'ping' command");
+        assert!(replay(b"?oops\r\n", &dbs, &params, &limits).await.is_err());
+        let nested = b"*1\r\n".repeat(200_000);
+        assert!(replay(&nested, &dbs, &params, &limits).await.is_err());
+    }
+}
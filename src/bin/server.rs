@@ -1,9 +1,12 @@
-use my_redis::run;
+use my_redis::{run_with_shutdown, Config};
 use tokio::net::TcpListener;
+use tokio::signal;
 
 #[tokio::main]
 async fn main() -> my_redis::Result<()> {
+    // Only the binary installs a subscriber, the library and its tests just emit events.
+    tracing_subscriber::fmt::init();
     let listener = TcpListener::bind("127.0.0.1:6379").await?;
-    run(listener).await;
+    run_with_shutdown(listener, Config::default(), signal::ctrl_c()).await;
     Ok(())
 }
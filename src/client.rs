@@ -0,0 +1,126 @@
+//! Connection-local state of a client, e.g. its id and name.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::Instant;
+
+/// Ids are unique for the lifetime of the process, the first client gets 1.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug)]
+pub(crate) struct Client {
+    id: u64,
+    addr: SocketAddr,
+    /// Set by `CLIENT SETNAME`.
+    pub(crate) name: Option<String>,
+    created_at: Instant,
+    /// Set by `CLIENT NO-EVICT`, the client is protected from client eviction.
+    pub(crate) no_evict: bool,
+    /// Set by `CLIENT NO-TOUCH`, the commands of the client don't update the access time of keys.
+    pub(crate) no_touch: bool,
+    /// Index of the database the commands of the client apply to, set by `SELECT`.
+    pub(crate) db: usize,
+    /// Set once the connection must be closed, the replies already written are not taken back.
+    pub(crate) closing: bool,
+    /// Set for the client replaying the append-only file. Like Redis while loading, its writes are
+    /// neither refused nor followed by evictions for lack of memory.
+    pub(crate) loading: bool,
+    /// Set by [Client::reset], so the handler also resets the state it holds, e.g. the
+    /// authentication, whether `RESET` ran in the subscribed mode or not.
+    pub(crate) was_reset: bool,
+}
+
+impl Client {
+    pub(crate) fn new(addr: SocketAddr) -> Self {
+        Client {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            addr,
+            name: None,
+            created_at: Instant::now(),
+            no_evict: false,
+            no_touch: false,
+            db: 0,
+            closing: false,
+            loading: false,
+            was_reset: false,
+        }
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Restore the connection state a client starts with, as `RESET` does. The name is kept.
+    pub(crate) fn reset(&mut self) {
+        self.no_evict = false;
+        self.no_touch = false;
+        self.db = 0;
+        self.was_reset = true;
+    }
+
+    /// Describe the client in the `CLIENT LIST` format, terminated by a newline.
+    pub(crate) fn info(&self) -> String {
+        let mut flags = String::new();
+        if self.no_evict {
+            flags.push('e');
+        }
+        if self.no_touch {
+            flags.push('T');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={} addr={} name={} age={} db={} flags={}\n",
+            self.id,
+            self.addr,
+            self.name.as_deref().unwrap_or_default(),
+            self.created_at.elapsed().as_secs(),
+            self.db,
+            flags
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_client {
+    use super::*;
+
+    #[test]
+    fn test_unique_id() {
+        let addr = "127.0.0.1:6379".parse().unwrap();
+        let a = Client::new(addr);
+        let b = Client::new(addr);
+        assert_ne!(a.id(), b.id());
+    }
+
+    #[test]
+    fn test_info() {
+        let mut client = Client::new("127.0.0.1:6379".parse().unwrap());
+        assert_eq!(
+            client.info(),
+            format!("id={} addr=127.0.0.1:6379 name= age=0 db=0 flags=N\n", client.id())
+        );
+        client.name = Some("foo".to_string());
+        client.no_touch = true;
+        assert!(client.info().contains(" name=foo "));
+        assert!(client.info().ends_with(" flags=T\n"));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut client = Client::new("127.0.0.1:6379".parse().unwrap());
+        client.name = Some("foo".to_string());
+        client.no_evict = true;
+        client.db = 3;
+        client.reset();
+        assert_eq!(client.name.as_deref(), Some("foo"));
+        assert!(!client.no_evict);
+        assert_eq!(client.db, 0);
+        assert!(client.was_reset);
+    }
+}
@@ -0,0 +1,28 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `APPEND key value`, reply with the length of the value after the append.
+pub struct Append {
+    key: String,
+    value: Bytes,
+}
+
+impl Append {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+        Ok(Append { key, value })
+    }
+
+    /// Append, unless the value would get longer than `max_len`.
+    pub async fn apply(self, db: &Db, max_len: usize) -> crate::Result<Frame> {
+        let frame = match db.append(&self.key, &self.value, max_len) {
+            Ok(Some(len)) => Frame::Integer(len as i64),
+            Ok(None) => Frame::string_too_long(),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
@@ -0,0 +1,33 @@
+use crate::config::Params;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `AUTH password`, authenticate the connection with the `requirepass` password.
+pub struct Auth {
+    password: Bytes,
+}
+
+impl Auth {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let password = parse.next_bytes()?;
+        Ok(Auth { password })
+    }
+
+    /// Check the password, and mark the connection as `authenticated` if it's the right one.
+    pub async fn apply(self, params: &Params, authenticated: &mut bool) -> crate::Result<Frame> {
+        let frame = match params.requirepass() {
+            None => Frame::Error(
+                "ERR AUTH <password> called without any password configured for the default user. \
+                 Are you sure your configuration is correct?"
+                    .to_string(),
+            ),
+            Some(password) if password.as_bytes() == self.password => {
+                *authenticated = true;
+                Frame::Simple("OK".to_string())
+            }
+            Some(_) => Frame::Error("ERR invalid password".to_string()),
+        };
+        Ok(frame)
+    }
+}
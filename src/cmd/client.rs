@@ -0,0 +1,76 @@
+use crate::client::Client as ClientState;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `CLIENT <subcommand>`, inspect or change the state of the current connection.
+pub enum Client {
+    Id,
+    Info,
+    GetName,
+    SetName(String),
+    NoEvict(bool),
+    NoTouch(bool),
+    Unpause,
+    Unknown(String),
+}
+
+impl Client {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let client = match subcommand.as_str() {
+            "id" => Client::Id,
+            "info" => Client::Info,
+            "getname" => Client::GetName,
+            "setname" => Client::SetName(parse.next_string()?),
+            "no-evict" => Client::NoEvict(parse_switch(parse)?),
+            "no-touch" => Client::NoTouch(parse_switch(parse)?),
+            "unpause" => Client::Unpause,
+            _ => Client::Unknown(subcommand),
+        };
+        Ok(client)
+    }
+
+    pub async fn apply(self, client: &mut ClientState) -> crate::Result<Frame> {
+        let ok = || Frame::Simple("OK".to_string());
+        let frame = match self {
+            Client::Id => Frame::Integer(client.id() as i64),
+            Client::Info => Frame::Bulk(Bytes::from(client.info())),
+            Client::GetName => match &client.name {
+                Some(name) => Frame::Bulk(Bytes::from(name.clone())),
+                None => Frame::Null,
+            },
+            Client::SetName(name) if name.chars().any(|c| !c.is_ascii_graphic()) => {
+                Frame::Error("ERR Client names cannot contain spaces, newlines or special characters.".to_string())
+            }
+            Client::SetName(name) => {
+                // An empty name removes the name.
+                client.name = Some(name).filter(|name| !name.is_empty());
+                ok()
+            }
+            Client::NoEvict(on) => {
+                client.no_evict = on;
+                ok()
+            }
+            Client::NoTouch(on) => {
+                client.no_touch = on;
+                ok()
+            }
+            // Clients are never paused, there is nothing to resume.
+            Client::Unpause => ok(),
+            Client::Unknown(subcommand) => {
+                Frame::Error(format!("ERR unknown subcommand '{}'. Try CLIENT HELP.", subcommand))
+            }
+        };
+        Ok(frame)
+    }
+}
+
+/// Parse an `on`/`off` argument.
+fn parse_switch(parse: &mut Parse) -> crate::Result<bool> {
+    match parse.next_string()?.to_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(parse.syntax_error().into()),
+    }
+}
@@ -0,0 +1,58 @@
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+use bytes::Bytes;
+
+/// `COMMAND [subcommand]`, introspect the known commands.
+///
+/// Only enough for `redis-cli` to connect without errors, the command docs are left empty.
+pub enum CommandInfo {
+    List,
+    Count,
+    Docs,
+    /// `COMMAND GETKEYS command [arg ...]`, the keys the command would access, e.g. for a proxy
+    /// to route it.
+    GetKeys(Vec<Bytes>),
+    Unknown(String),
+}
+
+impl CommandInfo {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        if parse.len() == 1 {
+            return Ok(CommandInfo::List);
+        }
+        let subcommand = parse.next_string()?.to_lowercase();
+        let command = match subcommand.as_str() {
+            "count" => CommandInfo::Count,
+            "docs" => {
+                // The names of the commands to document, there are no docs for any of them.
+                parse.remaining_strings()?;
+                CommandInfo::Docs
+            }
+            "getkeys" => {
+                let args = parse.remaining_bytes()?;
+                if args.is_empty() {
+                    return Err(anyhow!("wrong number of arguments for 'command|getkeys' command"));
+                }
+                CommandInfo::GetKeys(args)
+            }
+            _ => CommandInfo::Unknown(subcommand),
+        };
+        Ok(command)
+    }
+
+    pub async fn apply(self) -> crate::Result<Frame> {
+        let frame = match self {
+            CommandInfo::List | CommandInfo::Docs => Frame::Array(vec![]),
+            CommandInfo::Count => Frame::Integer(super::COMMAND_NAMES.len() as i64),
+            CommandInfo::GetKeys(args) => match super::command_keys(&args) {
+                Ok(keys) => Frame::Array(keys.into_iter().map(Frame::Bulk).collect()),
+                Err(reason) => Frame::Error(reason.to_string()),
+            },
+            CommandInfo::Unknown(subcommand) => {
+                Frame::Error(format!("ERR unknown subcommand '{}'. Try COMMAND HELP.", subcommand))
+            }
+        };
+        Ok(frame)
+    }
+}
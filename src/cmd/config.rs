@@ -0,0 +1,87 @@
+use crate::config::Params;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+use bytes::Bytes;
+
+/// `CONFIG GET pattern [pattern ...]` and `CONFIG SET parameter value [parameter value ...]`.
+pub enum Config {
+    Get(Vec<String>),
+    Set(Vec<(String, String)>),
+    Unknown(String),
+}
+
+impl Config {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let config = match subcommand.as_str() {
+            "get" => {
+                let patterns = parse.remaining_strings()?;
+                if patterns.is_empty() {
+                    return Err(anyhow!("wrong number of arguments for 'config|get' command"));
+                }
+                Config::Get(patterns)
+            }
+            "set" => {
+                let args = parse.remaining_strings()?;
+                if args.is_empty() || args.len() % 2 != 0 {
+                    return Err(anyhow!("wrong number of arguments for 'config|set' command"));
+                }
+                let mut args = args.into_iter();
+                let mut pairs = vec![];
+                while let (Some(name), Some(value)) = (args.next(), args.next()) {
+                    pairs.push((name, value));
+                }
+                Config::Set(pairs)
+            }
+            _ => Config::Unknown(subcommand),
+        };
+        Ok(config)
+    }
+
+    pub async fn apply(self, params: &Params) -> crate::Result<Frame> {
+        let frame = match self {
+            Config::Get(patterns) => {
+                let mut found = vec![];
+                for pattern in patterns {
+                    for param in params.get(&pattern) {
+                        // Patterns may overlap, report each parameter once.
+                        if !found.contains(&param) {
+                            found.push(param);
+                        }
+                    }
+                }
+                let frames = found
+                    .into_iter()
+                    .flat_map(|(name, value)| [Frame::Bulk(Bytes::from(name)), Frame::Bulk(Bytes::from(value))]);
+                Frame::Array(frames.collect())
+            }
+            Config::Set(pairs) => {
+                // Checked first, so either all the parameters are set, or none.
+                let invalid = pairs
+                    .iter()
+                    .find_map(|(name, value)| Params::check(name, value).err().map(|reason| (name, reason)));
+                if let Some((name, _)) = pairs.iter().find(|(name, _)| !params.contains(name)) {
+                    Frame::Error(format!(
+                        "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    ))
+                } else if let Some((name, reason)) = invalid {
+                    Frame::Error(format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                        name, reason
+                    ))
+                } else {
+                    for (name, value) in pairs {
+                        params.set(&name, value);
+                    }
+                    Frame::Simple("OK".to_string())
+                }
+            }
+            Config::Unknown(subcommand) => {
+                Frame::Error(format!("ERR unknown subcommand '{}'. Try CONFIG HELP.", subcommand))
+            }
+        };
+        Ok(frame)
+    }
+}
@@ -0,0 +1,30 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+
+/// `COPY source destination [REPLACE]`, reply 1 if the value was copied and 0 otherwise.
+pub struct Copy {
+    src: String,
+    dst: String,
+    /// Overwrite the destination if it exists.
+    replace: bool,
+}
+
+impl Copy {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let src = parse.next_string()?;
+        let dst = parse.next_string()?;
+        let replace = match parse.next_string() {
+            Ok(option) if option.eq_ignore_ascii_case("REPLACE") => true,
+            Ok(_) => return Err(parse.syntax_error().into()),
+            Err(ParseError::EndOfStream) => false,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Copy { src, dst, replace })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let copied = db.copy(&self.src, &self.dst, self.replace);
+        Ok(Frame::Integer(copied as i64))
+    }
+}
@@ -0,0 +1,15 @@
+use crate::db::Db;
+use crate::frame::Frame;
+
+/// `DBSIZE`, reply with the number of keys.
+pub struct DbSize {}
+
+impl DbSize {
+    pub fn from_parse() -> Self {
+        DbSize {}
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        Ok(Frame::Integer(db.len() as i64))
+    }
+}
@@ -0,0 +1,50 @@
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+use std::time::Duration;
+
+/// `DEBUG <subcommand>`, helpers to test clients and the server itself.
+pub enum Debug {
+    /// `DEBUG SLEEP seconds`, block the connection, e.g. to test the timeouts of a client.
+    Sleep(Duration),
+    /// `DEBUG JMAP`, a Java heap dump in Redis, there is nothing to dump.
+    Jmap,
+    Unknown(String),
+}
+
+impl Debug {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let debug = match subcommand.as_str() {
+            "sleep" => {
+                let seconds = parse
+                    .next_string()?
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| anyhow!("value is not a valid float"))?;
+                Debug::Sleep(seconds)
+            }
+            "jmap" => Debug::Jmap,
+            _ => Debug::Unknown(subcommand),
+        };
+        Ok(debug)
+    }
+
+    /// `DEBUG SLEEP` has no keys, so on its own only the connection of the client sleeps and the
+    /// others are served in the meantime. In a transaction it sleeps with every key locked, like
+    /// the rest of `EXEC`.
+    pub async fn apply(self) -> crate::Result<Frame> {
+        let frame = match self {
+            Debug::Sleep(duration) => {
+                tokio::time::sleep(duration).await;
+                Frame::Simple("OK".to_string())
+            }
+            Debug::Jmap => Frame::Simple("OK".to_string()),
+            Debug::Unknown(subcommand) => {
+                Frame::Error(format!("ERR unknown subcommand '{}'. Try DEBUG HELP.", subcommand))
+            }
+        };
+        Ok(frame)
+    }
+}
@@ -0,0 +1,20 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `DEL key [key ...]`, also used for `UNLINK` as values are dropped right away anyway.
+pub struct Del {
+    keys: Vec<String>,
+}
+
+impl Del {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let keys = parse.remaining_strings()?;
+        Ok(Del { keys })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let count = self.keys.iter().filter(|key| db.del(key)).count();
+        Ok(Frame::Integer(count as i64))
+    }
+}
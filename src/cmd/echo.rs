@@ -0,0 +1,19 @@
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `ECHO message`, reply with the message, byte for byte.
+pub struct Echo {
+    msg: Bytes,
+}
+
+impl Echo {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let msg = parse.next_bytes()?;
+        Ok(Echo { msg })
+    }
+
+    pub async fn apply(self) -> crate::Result<Frame> {
+        Ok(Frame::Bulk(self.msg))
+    }
+}
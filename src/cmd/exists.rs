@@ -0,0 +1,20 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `EXISTS key [key ...]`, a key given several times is counted each time.
+pub struct Exists {
+    keys: Vec<String>,
+}
+
+impl Exists {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let keys = parse.remaining_strings()?;
+        Ok(Exists { keys })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let count = self.keys.iter().filter(|key| db.exists(key)).count();
+        Ok(Frame::Integer(count as i64))
+    }
+}
@@ -0,0 +1,151 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use crate::time_util;
+use anyhow::anyhow;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// `EXPIRE key seconds` and `PEXPIRE key milliseconds`, or with an absolute Unix time `EXPIREAT key
+/// unix-time-seconds` and `PEXPIREAT key unix-time-milliseconds`. Reply 1 if the key exists and 0
+/// otherwise.
+pub struct Expire {
+    name: &'static str,
+    key: String,
+    /// `None` if the deadline is already past, the key is deleted.
+    ttl: Option<Duration>,
+}
+
+impl Expire {
+    pub fn from_parse(parse: &mut Parse, millis: bool) -> crate::Result<Self> {
+        let name = if millis { "pexpire" } else { "expire" };
+        let key = parse.next_string()?;
+        let ttl = next_ttl(parse, name, if millis { 1 } else { 1000 }, false)?;
+        Ok(Expire { name, key, ttl })
+    }
+
+    pub fn from_parse_at(parse: &mut Parse, millis: bool) -> crate::Result<Self> {
+        let name = if millis { "pexpireat" } else { "expireat" };
+        let key = parse.next_string()?;
+        let ttl = next_ttl(parse, name, if millis { 1 } else { 1000 }, true)?;
+        Ok(Expire { name, key, ttl })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let applied = match self.ttl {
+            Some(ttl) => db.expire(&self.key, ttl),
+            // Like Redis, a deadline in the past deletes the key.
+            None => db.del(&self.key),
+        };
+        Ok(Frame::Integer(applied as i64))
+    }
+}
+
+/// Read the expiration argument of `command`, a TTL or an absolute Unix time if `absolute`, in
+/// `unit` milliseconds. Return the time left until the deadline, `None` if it's not in the future.
+///
+/// Like Redis, the deadline must fit in milliseconds. A TTL may not be longer than
+/// [time_util::MAX_TTL] either, rather than being clamped silently. Absolute times are clamped.
+pub(crate) fn next_ttl(parse: &mut Parse, command: &str, unit: i64, absolute: bool) -> crate::Result<Option<Duration>> {
+    let value = parse.next_signed_int()?;
+    ttl(value, command, unit, absolute)
+}
+
+/// Like [next_ttl], for the expiration options of SET and GETEX, whose value must be positive.
+pub(crate) fn next_ttl_option(parse: &mut Parse, command: &str, unit: i64, absolute: bool) -> crate::Result<Duration> {
+    let value = parse.next_signed_int()?;
+    if value <= 0 {
+        return Err(invalid_expire_time(command));
+    }
+    // An absolute time in the past expires the key right away.
+    Ok(ttl(value, command, unit, absolute)?.unwrap_or_default())
+}
+
+fn ttl(value: i64, command: &str, unit: i64, absolute: bool) -> crate::Result<Option<Duration>> {
+    let ms = value.checked_mul(unit).ok_or_else(|| invalid_expire_time(command))?;
+    if absolute {
+        return Ok(time_util::unix_ms_to_instant(ms).map(|when| when.saturating_duration_since(Instant::now())));
+    }
+    // Bounded, the deadline `now + ms` can't overflow either.
+    if ms > time_util::MAX_TTL.as_millis() as i64 {
+        return Err(invalid_expire_time(command));
+    }
+    Ok((ms > 0).then(|| Duration::from_millis(ms as u64)))
+}
+
+fn invalid_expire_time(command: &str) -> anyhow::Error {
+    anyhow!("invalid expire time in '{}' command", command)
+}
+
+#[cfg(test)]
+mod test_expire {
+    use super::*;
+
+    fn parse_expire(args: &[&str]) -> crate::Result<Expire> {
+        let frame = Frame::Array(args.iter().map(|arg| Frame::Bulk(arg.to_string().into())).collect());
+        let mut parse = Parse::new(frame)?;
+        let name = parse.next_string()?.to_lowercase();
+        match name.as_str() {
+            "expire" | "pexpire" => Expire::from_parse(&mut parse, name == "pexpire"),
+            _ => Expire::from_parse_at(&mut parse, name == "pexpireat"),
+        }
+    }
+
+    #[test]
+    fn test_ttl() {
+        assert_eq!(
+            parse_expire(&["EXPIRE", "key", "10"]).unwrap().ttl,
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            parse_expire(&["PEXPIRE", "key", "10"]).unwrap().ttl,
+            Some(Duration::from_millis(10))
+        );
+        // Not in the future, the key is deleted.
+        assert_eq!(parse_expire(&["EXPIRE", "key", "0"]).unwrap().ttl, None);
+        assert_eq!(parse_expire(&["PEXPIRE", "key", "-10"]).unwrap().ttl, None);
+        assert_eq!(parse_expire(&["PEXPIREAT", "key", "1"]).unwrap().ttl, None);
+        let err = parse_expire(&["EXPIREAT", "key", "-9223372036854775807"])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "invalid expire time in 'expireat' command");
+    }
+
+    #[test]
+    fn test_ttl_too_large() {
+        let max_secs = time_util::MAX_TTL.as_secs().to_string();
+        assert!(parse_expire(&["EXPIRE", "key", &max_secs]).is_ok());
+        for (name, value) in [
+            ("expire", "9223372036854775807"),
+            ("expire", &(time_util::MAX_TTL.as_secs() + 1).to_string()),
+            ("pexpire", "9223372036854775807"),
+            ("expireat", "9223372036854776"),
+        ] {
+            let err = parse_expire(&[name, "key", value]).err().unwrap();
+            assert_eq!(err.to_string(), format!("invalid expire time in '{}' command", name));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_past() {
+        let db = Db::new();
+        db.set("key".to_string(), "value".into(), None);
+        let reply = parse_expire(&["EXPIRE", "key", "-1"])
+            .unwrap()
+            .apply(&db)
+            .await
+            .unwrap();
+        assert_eq!(reply, Frame::Integer(1));
+        assert!(!db.exists("key"));
+        let reply = parse_expire(&["EXPIRE", "key", "-1"])
+            .unwrap()
+            .apply(&db)
+            .await
+            .unwrap();
+        assert_eq!(reply, Frame::Integer(0));
+    }
+}
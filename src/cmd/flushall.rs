@@ -0,0 +1,18 @@
+use crate::db::Db;
+use crate::frame::Frame;
+
+/// `FLUSHALL`, remove every key of every database.
+pub struct FlushAll {}
+
+impl FlushAll {
+    pub fn from_parse() -> Self {
+        FlushAll {}
+    }
+
+    pub async fn apply(self, dbs: &[Db]) -> crate::Result<Frame> {
+        for db in dbs {
+            db.flush();
+        }
+        Ok(Frame::Simple("OK".to_string()))
+    }
+}
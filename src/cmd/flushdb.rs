@@ -0,0 +1,16 @@
+use crate::db::Db;
+use crate::frame::Frame;
+
+/// `FLUSHDB`, remove every key.
+pub struct FlushDb {}
+
+impl FlushDb {
+    pub fn from_parse() -> Self {
+        FlushDb {}
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        db.flush();
+        Ok(Frame::Simple("OK".to_string()))
+    }
+}
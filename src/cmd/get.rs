@@ -1,4 +1,3 @@
-use crate::connection::Connection;
 use crate::db::Db;
 use crate::frame::Frame;
 use crate::parse::Parse;
@@ -13,13 +12,12 @@ impl Get {
         Ok(Get { key })
     }
 
-    pub async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
-        let frame = if let Some(value) = db.get(&self.key) {
-            Frame::Bulk(value)
-        } else {
-            Frame::Null
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.get(&self.key) {
+            Ok(Some(value)) => Frame::Bulk(value),
+            Ok(None) => Frame::Null,
+            Err(_) => Frame::wrong_type(),
         };
-        dst.write_frame(&frame).await?;
-        Ok(())
+        Ok(frame)
     }
 }
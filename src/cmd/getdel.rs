@@ -0,0 +1,23 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `GETDEL key`, get the value and delete the key.
+pub struct GetDel {
+    key: String,
+}
+
+impl GetDel {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(GetDel { key })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.getdel(&self.key) {
+            Ok(value) => value.map_or(Frame::Null, Frame::Bulk),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
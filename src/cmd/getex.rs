@@ -0,0 +1,78 @@
+use crate::cmd::expire;
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use std::time::Duration;
+
+/// `GETEX key [EX seconds | PX milliseconds | PERSIST]`, get the value and optionally change its TTL.
+pub struct GetEx {
+    key: String,
+    /// `None` leaves the TTL unchanged, `Some(None)` removes it.
+    expire: Option<Option<Duration>>,
+}
+
+impl GetEx {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let option = match parse.next_string() {
+            Ok(option) => option.to_uppercase(),
+            Err(ParseError::EndOfStream) => return Ok(GetEx { key, expire: None }),
+            Err(err) => return Err(err.into()),
+        };
+        let expire = match option.as_str() {
+            "EX" => Some(expire::next_ttl_option(parse, "getex", 1000, false)?),
+            "PX" => Some(expire::next_ttl_option(parse, "getex", 1, false)?),
+            "PERSIST" => None,
+            _ => return Err(parse.syntax_error().into()),
+        };
+        Ok(GetEx {
+            key,
+            expire: Some(expire),
+        })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.getex(&self.key, self.expire) {
+            Ok(value) => value.map_or(Frame::Null, Frame::Bulk),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod test_getex {
+    use super::*;
+
+    fn parse_getex(args: &[&str]) -> crate::Result<GetEx> {
+        let frame = Frame::Array(args.iter().map(|arg| Frame::Bulk(arg.to_string().into())).collect());
+        let mut parse = Parse::new(frame)?;
+        parse.next_string()?;
+        let getex = GetEx::from_parse(&mut parse)?;
+        parse.finish()?;
+        Ok(getex)
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(parse_getex(&["GETEX", "key"]).unwrap().expire, None);
+        assert_eq!(
+            parse_getex(&["GETEX", "key", "ex", "10"]).unwrap().expire,
+            Some(Some(Duration::from_secs(10)))
+        );
+        assert_eq!(
+            parse_getex(&["GETEX", "key", "PX", "10"]).unwrap().expire,
+            Some(Some(Duration::from_millis(10)))
+        );
+        assert_eq!(parse_getex(&["GETEX", "key", "PERSIST"]).unwrap().expire, Some(None));
+
+        let err = parse_getex(&["GETEX", "key", "KEEPTTL"]).err().unwrap();
+        assert_eq!(err.to_string(), "syntax error near argument 2");
+        assert!(parse_getex(&["GETEX", "key", "PERSIST", "EX", "10"]).is_err());
+
+        for args in [["EX", "0"], ["PX", "-1"], ["PX", "9223372036854775807"]] {
+            let err = parse_getex(&["GETEX", "key", args[0], args[1]]).err().unwrap();
+            assert_eq!(err.to_string(), "invalid expire time in 'getex' command", "{:?}", args);
+        }
+    }
+}
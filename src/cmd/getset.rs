@@ -0,0 +1,27 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `GETSET key value`, set the key and reply with its previous value, nil if it didn't exist.
+pub struct GetSet {
+    key: String,
+    value: Bytes,
+}
+
+impl GetSet {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+        Ok(GetSet { key, value })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.getset(self.key, self.value) {
+            Ok(Some(prev)) => Frame::Bulk(prev),
+            Ok(None) => Frame::Null,
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
@@ -0,0 +1,25 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `INCR key` and `DECR key`, the stored value must be a 64-bit signed integer.
+pub struct Incr {
+    key: String,
+    delta: i64,
+}
+
+impl Incr {
+    pub fn from_parse(parse: &mut Parse, delta: i64) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(Incr { key, delta })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.incr_by(&self.key, self.delta) {
+            Ok(Some(value)) => Frame::Integer(value),
+            Ok(None) => Frame::not_an_integer(),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
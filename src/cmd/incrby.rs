@@ -0,0 +1,103 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+
+/// `INCRBY key increment` and `DECRBY key decrement`.
+pub struct IncrBy {
+    key: String,
+    delta: i64,
+}
+
+impl IncrBy {
+    pub fn from_parse(parse: &mut Parse, negate: bool) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let delta = parse
+            .next_signed_int()
+            .ok()
+            .and_then(|delta| if negate { delta.checked_neg() } else { Some(delta) })
+            .ok_or_else(|| anyhow!("value is not an integer or out of range"))?;
+        Ok(IncrBy { key, delta })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.incr_by(&self.key, self.delta) {
+            Ok(Some(value)) => Frame::Integer(value),
+            Ok(None) => Frame::not_an_integer(),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `INCRBYFLOAT key increment`, reply with the new value as a bulk string.
+pub struct IncrByFloat {
+    key: String,
+    delta: f64,
+}
+
+impl IncrByFloat {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let delta = parse
+            .next_string()?
+            .parse::<f64>()
+            .ok()
+            .filter(|delta| delta.is_finite())
+            .ok_or_else(|| anyhow!("value is not a valid float"))?;
+        Ok(IncrByFloat { key, delta })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.incr_by_float(&self.key, self.delta) {
+            Ok(Some(value)) => Frame::Bulk(value),
+            Ok(None) => Frame::Error("ERR value is not a valid float or the result is not finite".to_string()),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod test_incrby {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Parse {
+        let frame = Frame::Array(args.iter().map(|arg| Frame::Bulk(arg.to_string().into())).collect());
+        let mut parse = Parse::new(frame).unwrap();
+        parse.next_string().unwrap();
+        parse
+    }
+
+    #[test]
+    fn test_delta() {
+        assert_eq!(
+            IncrBy::from_parse(&mut parse(&["INCRBY", "key", "-5"]), false)
+                .unwrap()
+                .delta,
+            -5
+        );
+        assert_eq!(
+            IncrBy::from_parse(&mut parse(&["DECRBY", "key", "5"]), true)
+                .unwrap()
+                .delta,
+            -5
+        );
+        let err = IncrBy::from_parse(&mut parse(&["DECRBY", "key", &i64::MIN.to_string()]), true)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "value is not an integer or out of range");
+        assert!(IncrBy::from_parse(&mut parse(&["INCRBY", "key", "1.5"]), false).is_err());
+
+        assert_eq!(
+            IncrByFloat::from_parse(&mut parse(&["INCRBYFLOAT", "key", "-1.5"]))
+                .unwrap()
+                .delta,
+            -1.5
+        );
+        let err = IncrByFloat::from_parse(&mut parse(&["INCRBYFLOAT", "key", "inf"]))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "value is not a valid float");
+    }
+}
@@ -0,0 +1,82 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use crate::stats::Stats;
+use bytes::Bytes;
+use std::fmt::Write;
+
+/// Sections of the INFO report, in order.
+const SECTIONS: [&str; 4] = ["server", "clients", "commandstats", "keyspace"];
+
+/// Sections reported when none is requested, like Redis the command stats are left out.
+const DEFAULT_SECTIONS: [&str; 3] = ["server", "clients", "keyspace"];
+
+/// `INFO [section ...]`, a report about the server, in the `field:value` text format of Redis.
+pub struct Info {
+    /// The requested sections, in report order.
+    sections: Vec<&'static str>,
+}
+
+impl Info {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let mut requested = parse.remaining_strings()?;
+        for section in &mut requested {
+            section.make_ascii_lowercase();
+        }
+        let is_requested = |section: &str| requested.iter().any(|s| s == section);
+        let all = is_requested("all") || is_requested("everything");
+        let default = requested.is_empty() || is_requested("default");
+        let sections = SECTIONS
+            .into_iter()
+            .filter(|&section| all || is_requested(section) || (default && DEFAULT_SECTIONS.contains(&section)))
+            .collect();
+        Ok(Info { sections })
+    }
+
+    pub async fn apply(self, dbs: &[Db], stats: &Stats) -> crate::Result<Frame> {
+        let mut report = String::new();
+        for section in self.sections {
+            if !report.is_empty() {
+                report.push_str("\r\n");
+            }
+            write_section(&mut report, section, dbs, stats);
+        }
+        Ok(Frame::Bulk(Bytes::from(report)))
+    }
+}
+
+/// Append a section, titled like `# Server`, to the report.
+fn write_section(report: &mut String, section: &str, dbs: &[Db], stats: &Stats) {
+    // Writing to a `String` can't fail.
+    match section {
+        "server" => {
+            let uptime = stats.uptime().as_secs();
+            report.push_str("# Server\r\n");
+            let _ = write!(report, "redis_version:{}\r\n", env!("CARGO_PKG_VERSION"));
+            let _ = write!(report, "process_id:{}\r\n", std::process::id());
+            let _ = write!(report, "uptime_in_seconds:{}\r\n", uptime);
+            let _ = write!(report, "uptime_in_days:{}\r\n", uptime / (24 * 60 * 60));
+        }
+        "clients" => {
+            report.push_str("# Clients\r\n");
+            let _ = write!(report, "connected_clients:{}\r\n", stats.connected_clients());
+        }
+        "commandstats" => {
+            report.push_str("# Commandstats\r\n");
+            for (name, calls) in stats.command_calls() {
+                let _ = write!(report, "cmdstat_{}:calls={}\r\n", name, calls);
+            }
+        }
+        "keyspace" => {
+            report.push_str("# Keyspace\r\n");
+            for (index, db) in dbs.iter().enumerate() {
+                let (keys, expires) = db.key_counts();
+                // Like Redis, empty databases are left out.
+                if keys > 0 {
+                    let _ = write!(report, "db{}:keys={},expires={}\r\n", index, keys, expires);
+                }
+            }
+        }
+        _ => unreachable!("unknown INFO section {}", section),
+    }
+}
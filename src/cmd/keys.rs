@@ -0,0 +1,41 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use bytes::Bytes;
+
+/// `KEYS pattern [LIMIT count]`, reply with the keys matching a glob-style pattern.
+///
+/// `LIMIT` is not in Redis: the scan stops after `count` keys, so tooling can sample a large
+/// keyspace without blocking the server for long. Like the `LIMIT` of `SINTERCARD`, 0 means no
+/// limit.
+pub struct Keys {
+    pattern: Bytes,
+    limit: usize,
+}
+
+impl Keys {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let pattern = parse.next_bytes()?;
+        let limit = match parse.next_string() {
+            Ok(option) if option.eq_ignore_ascii_case("limit") => match parse.next_int() {
+                Ok(0) => usize::MAX,
+                Ok(limit) => usize::try_from(limit).unwrap_or(usize::MAX),
+                Err(ParseError::EndOfStream) => return Err(parse.syntax_error().into()),
+                Err(err) => return Err(err.into()),
+            },
+            Ok(_) => return Err(parse.syntax_error().into()),
+            Err(ParseError::EndOfStream) => usize::MAX,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Keys { pattern, limit })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let keys = db
+            .keys_limited(&self.pattern, self.limit)
+            .into_iter()
+            .map(|key| Frame::Bulk(Bytes::from(key)))
+            .collect();
+        Ok(Frame::Array(keys))
+    }
+}
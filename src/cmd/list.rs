@@ -0,0 +1,134 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+use bytes::Bytes;
+
+/// `LPUSH key element [element ...]` and `RPUSH key element [element ...]`, reply with the length
+/// of the list. Elements are pushed one by one, so `LPUSH` inserts them in reverse order.
+///
+/// `LPUSHCAPPED key maxlen element [element ...]` and `RPUSHCAPPED` are not in Redis: they push,
+/// then trim the list to its `maxlen` newest elements, for a capped log without a separate `LTRIM`.
+pub struct Push {
+    key: String,
+    values: Vec<Bytes>,
+    front: bool,
+    /// Most elements kept in the list, `None` for no limit.
+    maxlen: Option<usize>,
+}
+
+impl Push {
+    pub fn from_parse(parse: &mut Parse, front: bool, capped: bool) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let maxlen = if capped {
+            let maxlen = parse.next_int()?;
+            if maxlen == 0 {
+                return Err(anyhow!("maxlen is out of range, must be positive"));
+            }
+            Some(usize::try_from(maxlen).unwrap_or(usize::MAX))
+        } else {
+            None
+        };
+        let values = parse.remaining_bytes()?;
+        Ok(Push {
+            key,
+            values,
+            front,
+            maxlen,
+        })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match (self.front, self.maxlen.is_some()) {
+            (true, false) => "lpush",
+            (false, false) => "rpush",
+            (true, true) => "lpushcapped",
+            (false, true) => "rpushcapped",
+        }
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let pushed = match self.maxlen {
+            Some(maxlen) => db.list_push_capped(&self.key, self.values, self.front, maxlen),
+            None => db.push(&self.key, self.values, self.front),
+        };
+        let frame = match pushed {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `LPOP key` and `RPOP key`, reply with the popped element, or Null if the list doesn't exist.
+pub struct Pop {
+    key: String,
+    front: bool,
+}
+
+impl Pop {
+    pub fn from_parse(parse: &mut Parse, front: bool) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(Pop { key, front })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        if self.front {
+            "lpop"
+        } else {
+            "rpop"
+        }
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.pop(&self.key, self.front) {
+            Ok(value) => value.map_or(Frame::Null, Frame::Bulk),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `LRANGE key start stop`, reply with the elements between the two indexes, both included.
+pub struct LRange {
+    key: String,
+    start: i64,
+    stop: i64,
+}
+
+impl LRange {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let start = parse.next_signed_int()?;
+        let stop = parse.next_signed_int()?;
+        Ok(LRange { key, start, stop })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.lrange(&self.key, self.start, self.stop) {
+            Ok(values) => Frame::Array(values.into_iter().map(Frame::Bulk).collect()),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `LLEN key`, reply with the length of the list, 0 if the key doesn't exist.
+pub struct LLen {
+    key: String,
+}
+
+impl LLen {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(LLen { key })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.llen(&self.key) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
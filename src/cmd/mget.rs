@@ -0,0 +1,24 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `MGET key [key ...]`, reply with the values in the order of the keys, nil for the missing ones.
+pub struct Mget {
+    keys: Vec<String>,
+}
+
+impl Mget {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let keys = parse.remaining_strings()?;
+        Ok(Mget { keys })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let values = db
+            .mget(&self.keys)
+            .into_iter()
+            .map(|value| value.map_or(Frame::Null, Frame::Bulk))
+            .collect();
+        Ok(Frame::Array(values))
+    }
+}
@@ -1,36 +1,490 @@
+mod append;
+mod auth;
+mod client;
+mod command;
+mod config;
+mod copy;
+mod dbsize;
+mod debug;
+mod del;
+mod echo;
+mod exists;
+mod expire;
+mod flushall;
+mod flushdb;
 mod get;
+mod getdel;
+mod getex;
+mod getset;
+mod incr;
+mod incrby;
+mod info;
+mod keys;
+mod list;
+mod mget;
+mod monitor;
+mod mset;
+mod object;
+mod persist;
 mod ping;
+mod publish;
+mod quit;
+mod range;
+mod reset;
+mod scan;
+mod select;
 mod set;
+mod set_type;
+mod setnx;
+mod strlen;
+mod subscribe;
+mod transaction;
+mod ttl;
+mod r#type;
 mod unknown;
 
+use crate::client::Client as ClientState;
+use crate::cmd::append::Append;
+use crate::cmd::auth::Auth;
+use crate::cmd::client::Client;
+use crate::cmd::command::CommandInfo;
+use crate::cmd::config::Config;
+use crate::cmd::copy::Copy;
+use crate::cmd::dbsize::DbSize;
+use crate::cmd::debug::Debug;
+use crate::cmd::del::Del;
+use crate::cmd::echo::Echo;
+use crate::cmd::exists::Exists;
+use crate::cmd::expire::Expire;
+use crate::cmd::flushall::FlushAll;
+use crate::cmd::flushdb::FlushDb;
 use crate::cmd::get::Get;
+use crate::cmd::getdel::GetDel;
+use crate::cmd::getex::GetEx;
+use crate::cmd::getset::GetSet;
+use crate::cmd::incr::Incr;
+use crate::cmd::incrby::{IncrBy, IncrByFloat};
+use crate::cmd::info::Info;
+use crate::cmd::keys::Keys;
+use crate::cmd::list::{LLen, LRange, Pop, Push};
+use crate::cmd::mget::Mget;
+use crate::cmd::monitor::Monitor;
+use crate::cmd::mset::Mset;
+use crate::cmd::object::Object;
+use crate::cmd::persist::Persist;
 use crate::cmd::ping::Ping;
+use crate::cmd::publish::Publish;
+use crate::cmd::quit::Quit;
+use crate::cmd::r#type::Type;
+use crate::cmd::range::{GetRange, SetRange};
+use crate::cmd::reset::Reset;
+use crate::cmd::scan::Scan;
+use crate::cmd::select::Select;
 use crate::cmd::set::Set;
+use crate::cmd::set_type::{SAdd, SIsMember, SMembers, SRem};
+use crate::cmd::setnx::SetNx;
+use crate::cmd::strlen::Strlen;
+use crate::cmd::subscribe::{Subscribe, Unsubscribe};
+use crate::cmd::transaction::{Discard, Exec, Multi};
+use crate::cmd::ttl::Ttl;
 use crate::cmd::unknown::Unknown;
+use crate::config::{MaxMemoryPolicy, Params};
 use crate::connection::Connection;
-use crate::db::Db;
+use crate::db::{self, Db};
 use crate::frame::Frame;
+use crate::key_locks::Footprint;
 use crate::parse::Parse;
+use crate::shutdown::Shutdown;
+use crate::stats::Stats;
+use anyhow::anyhow;
+use bytes::Bytes;
+use tracing::{debug, debug_span, Instrument};
+
+pub(crate) use crate::cmd::monitor::feed_monitors;
+pub(crate) use crate::cmd::transaction::Transaction;
 
 pub enum Command {
     Get(Get),
+    GetRange(GetRange),
     Set(Set),
+    SetNx(SetNx),
+    Del(Del),
+    Exists(Exists),
+    Incr(Incr),
+    Ttl(Ttl),
+    Expire(Expire),
+    Persist(Persist),
+    Mget(Mget),
+    Mset(Mset),
+    Append(Append),
+    Strlen(Strlen),
+    GetDel(GetDel),
+    GetEx(GetEx),
+    GetSet(GetSet),
+    Type(Type),
+    Object(Object),
+    DbSize(DbSize),
+    FlushDb(FlushDb),
+    FlushAll(FlushAll),
+    Keys(Keys),
+    Scan(Scan),
+    IncrBy(IncrBy),
+    IncrByFloat(IncrByFloat),
+    SetRange(SetRange),
+    Copy(Copy),
     Ping(Ping),
+    Echo(Echo),
+    Monitor(Monitor),
+    Client(Client),
+    Info(Info),
+    Config(Config),
+    Publish(Publish),
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    Select(Select),
+    Push(Push),
+    Pop(Pop),
+    LRange(LRange),
+    LLen(LLen),
+    SAdd(SAdd),
+    SRem(SRem),
+    SMembers(SMembers),
+    SIsMember(SIsMember),
+    Reset(Reset),
+    Multi(Multi),
+    Exec(Exec),
+    Discard(Discard),
+    Auth(Auth),
+    Quit(Quit),
+    Debug(Debug),
+    CommandInfo(CommandInfo),
     Unknown(Unknown),
 }
 
+/// Number of arguments accepted by a command, the command name included, as Redis counts them.
+enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    /// Between the two bounds, both included.
+    Between(usize, usize),
+}
+
+/// Positions of the keys among the arguments of a command, the command name being argument 0, as
+/// the first key, last key and step of Redis `COMMAND INFO`.
+#[derive(Debug, PartialEq)]
+struct KeySpec {
+    first: usize,
+    /// Negative counts from the end, -1 is the last argument.
+    last: isize,
+    step: usize,
+}
+
+/// Names of the known commands, aliases included, as counted by COMMAND COUNT.
+const COMMAND_NAMES: &[&str] = &[
+    "get",
+    "getrange",
+    "substr",
+    "set",
+    "setnx",
+    "del",
+    "unlink",
+    "exists",
+    "incr",
+    "decr",
+    "ttl",
+    "pttl",
+    "expire",
+    "pexpire",
+    "expireat",
+    "pexpireat",
+    "persist",
+    "mget",
+    "mset",
+    "append",
+    "strlen",
+    "getdel",
+    "getex",
+    "getset",
+    "type",
+    "object",
+    "dbsize",
+    "flushdb",
+    "flushall",
+    "keys",
+    "scan",
+    "incrby",
+    "decrby",
+    "incrbyfloat",
+    "setrange",
+    "copy",
+    "ping",
+    "echo",
+    "monitor",
+    "client",
+    "command",
+    "select",
+    "info",
+    "config",
+    "publish",
+    "subscribe",
+    "unsubscribe",
+    "lpush",
+    "lpushcapped",
+    "rpushcapped",
+    "rpush",
+    "lpop",
+    "rpop",
+    "lrange",
+    "llen",
+    "sadd",
+    "srem",
+    "smembers",
+    "sismember",
+    "reset",
+    "multi",
+    "exec",
+    "discard",
+    "auth",
+    "quit",
+    "debug",
+];
+
+/// Longest name of a known command, so that names can be lowercased on the stack.
+const MAX_NAME_LEN: usize = 16;
+
+impl Arity {
+    /// Arity of a known command, given its lowercase name, `None` for unknown commands.
+    fn of(command_name: &[u8]) -> Option<Arity> {
+        use Arity::*;
+        let arity = match command_name {
+            b"get" => Exact(2),
+            b"getrange" | b"substr" => Exact(4),
+            b"set" => AtLeast(3),
+            b"setnx" => Exact(3),
+            b"del" | b"unlink" => AtLeast(2),
+            b"exists" => AtLeast(2),
+            b"incr" | b"decr" => Exact(2),
+            b"ttl" | b"pttl" => Exact(2),
+            b"expire" | b"pexpire" | b"expireat" | b"pexpireat" => Exact(3),
+            b"persist" => Exact(2),
+            b"mget" => AtLeast(2),
+            b"mset" => AtLeast(3),
+            b"append" => Exact(3),
+            b"strlen" => Exact(2),
+            b"getdel" => Exact(2),
+            b"getex" => AtLeast(2),
+            b"getset" => Exact(3),
+            b"type" => Exact(2),
+            b"object" => AtLeast(2),
+            b"dbsize" => Exact(1),
+            b"flushdb" => Exact(1),
+            b"flushall" => Exact(1),
+            b"keys" => Between(2, 4),
+            b"scan" => AtLeast(2),
+            b"incrby" | b"decrby" | b"incrbyfloat" => Exact(3),
+            b"setrange" => Exact(4),
+            b"copy" => AtLeast(3),
+            b"ping" => Between(1, 2),
+            b"echo" => Exact(2),
+            b"monitor" => Exact(1),
+            b"client" => AtLeast(2),
+            b"command" => AtLeast(1),
+            b"select" => Exact(2),
+            b"info" => AtLeast(1),
+            b"config" => AtLeast(2),
+            b"publish" => Exact(3),
+            b"subscribe" => AtLeast(2),
+            b"unsubscribe" => AtLeast(1),
+            b"lpush" | b"rpush" => AtLeast(3),
+            b"lpushcapped" | b"rpushcapped" => AtLeast(4),
+            b"lpop" | b"rpop" => Exact(2),
+            b"lrange" => Exact(4),
+            b"llen" => Exact(2),
+            b"sadd" | b"srem" => AtLeast(3),
+            b"smembers" => Exact(2),
+            b"sismember" => Exact(3),
+            b"reset" => Exact(1),
+            b"multi" | b"exec" | b"discard" => Exact(1),
+            b"auth" => Exact(2),
+            b"quit" => Exact(1),
+            b"debug" => AtLeast(2),
+            _ => return None,
+        };
+        Some(arity)
+    }
+
+    fn accepts(&self, len: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => len == n,
+            Arity::AtLeast(n) => len >= n,
+            Arity::Between(min, max) => (min..=max).contains(&len),
+        }
+    }
+}
+
+impl KeySpec {
+    /// Key positions of a known command, given its lowercase name, `None` if it takes no key.
+    fn of(command_name: &[u8]) -> Option<KeySpec> {
+        let (first, last, step) = match command_name {
+            b"del" | b"unlink" | b"exists" | b"mget" => (1, -1, 1),
+            b"mset" => (1, -1, 2),
+            b"copy" => (1, 2, 1),
+            b"object" => (2, 2, 1),
+            b"get" | b"getrange" | b"substr" | b"set" | b"setnx" | b"getset" | b"incr" | b"decr" | b"ttl" | b"pttl"
+            | b"expire" | b"pexpire" | b"expireat" | b"pexpireat" | b"persist" | b"append" | b"strlen" | b"getdel"
+            | b"getex" | b"type" | b"incrby" | b"decrby" | b"incrbyfloat" | b"setrange" | b"lpush" | b"rpush"
+            | b"lpushcapped" | b"rpushcapped" | b"lpop" | b"rpop" | b"lrange" | b"llen" | b"sadd" | b"srem"
+            | b"smembers" | b"sismember" => (1, 1, 1),
+            _ => return None,
+        };
+        Some(KeySpec { first, last, step })
+    }
+
+    /// The keys among `args`, the command name included, whose number suits the arity.
+    fn keys(&self, args: &[Bytes]) -> Vec<Bytes> {
+        let last = if self.last < 0 {
+            args.len() as isize + self.last
+        } else {
+            self.last
+        };
+        let last = usize::try_from(last).map_or(0, |last| last.min(args.len() - 1));
+        args.iter()
+            .take(last + 1)
+            .skip(self.first)
+            .step_by(self.step)
+            .cloned()
+            .collect()
+    }
+}
+
+/// `COMMAND GETKEYS`, the keys a command would access given its arguments `args`, the command name
+/// included. Err is the reason, as an error reply.
+fn command_keys(args: &[Bytes]) -> Result<Vec<Bytes>, &'static str> {
+    let name = args.first().map(|name| name.to_ascii_lowercase()).unwrap_or_default();
+    let arity = Arity::of(&name).ok_or("ERR Invalid command specified")?;
+    if !arity.accepts(args.len()) {
+        return Err("ERR Invalid number of arguments specified for command");
+    }
+    let spec = KeySpec::of(&name).ok_or("ERR The command has no key arguments")?;
+    Ok(spec.keys(args))
+}
+
+/// The keys the command in `frame` applies to, see [KeyLocks](crate::key_locks::KeyLocks). An
+/// invalid command applies to none.
+pub(crate) fn footprint(frame: &Frame) -> Footprint {
+    let Frame::Array(frames) = frame else {
+        return Footprint::Keys(vec![]);
+    };
+    let args: Vec<Bytes> = frames
+        .iter()
+        .filter_map(|frame| match frame {
+            Frame::Bulk(arg) => Some(arg.clone()),
+            _ => None,
+        })
+        .collect();
+    let name = args.first().map(|name| name.to_ascii_lowercase()).unwrap_or_default();
+    match &name[..] {
+        b"keys" | b"scan" | b"dbsize" | b"flushdb" | b"flushall" => Footprint::All,
+        _ => Footprint::Keys(command_keys(&args).unwrap_or_default()),
+    }
+}
+
 impl Command {
     pub(crate) fn from_frame(frame: Frame) -> crate::Result<Command> {
         let mut parse = Parse::new(frame)?;
-        let command_name = parse.next_string()?.to_lowercase();
+        let raw_name = parse.next_bytes()?;
+        // This runs for every single command, so avoid allocating a lowercase copy of the name.
+        let mut buf = [0u8; MAX_NAME_LEN];
+        let command_name = match buf.get_mut(..raw_name.len()) {
+            Some(name) => {
+                name.copy_from_slice(&raw_name);
+                name.make_ascii_lowercase();
+                &*name
+            }
+            // Too long to be a known command.
+            None => &[],
+        };
+
+        match Arity::of(command_name) {
+            Some(arity) if !arity.accepts(parse.len()) => {
+                return Err(anyhow!(
+                    "wrong number of arguments for '{}' command",
+                    String::from_utf8_lossy(command_name)
+                ));
+            }
+            Some(_) => {}
+            // The arguments of an unknown command are irrelevant.
+            None => return Ok(Command::Unknown(Unknown::new(unknown_name(&raw_name))?)),
+        }
 
         // All cmd should implement from_parse method
         // this method will parse the remaining of the frame as it expects
-        let command = match command_name.as_str() {
-            "get" => Command::Get(Get::from_parse(&mut parse)?),
-            "set" => Command::Set(Set::from_parse(&mut parse)?),
-            "ping" => Command::Ping(Ping::from_parse()),
-            _ => Command::Unknown(Unknown::new(&command_name)?),
+        let command = match command_name {
+            b"get" => Command::Get(Get::from_parse(&mut parse)?),
+            b"getrange" | b"substr" => Command::GetRange(GetRange::from_parse(&mut parse)?),
+            b"set" => Command::Set(Set::from_parse(&mut parse)?),
+            b"setnx" => Command::SetNx(SetNx::from_parse(&mut parse)?),
+            b"del" | b"unlink" => Command::Del(Del::from_parse(&mut parse)?),
+            b"exists" => Command::Exists(Exists::from_parse(&mut parse)?),
+            b"incr" => Command::Incr(Incr::from_parse(&mut parse, 1)?),
+            b"decr" => Command::Incr(Incr::from_parse(&mut parse, -1)?),
+            b"ttl" => Command::Ttl(Ttl::from_parse(&mut parse, false)?),
+            b"pttl" => Command::Ttl(Ttl::from_parse(&mut parse, true)?),
+            b"expire" => Command::Expire(Expire::from_parse(&mut parse, false)?),
+            b"pexpire" => Command::Expire(Expire::from_parse(&mut parse, true)?),
+            b"expireat" => Command::Expire(Expire::from_parse_at(&mut parse, false)?),
+            b"pexpireat" => Command::Expire(Expire::from_parse_at(&mut parse, true)?),
+            b"persist" => Command::Persist(Persist::from_parse(&mut parse)?),
+            b"mget" => Command::Mget(Mget::from_parse(&mut parse)?),
+            b"mset" => Command::Mset(Mset::from_parse(&mut parse)?),
+            b"append" => Command::Append(Append::from_parse(&mut parse)?),
+            b"strlen" => Command::Strlen(Strlen::from_parse(&mut parse)?),
+            b"getdel" => Command::GetDel(GetDel::from_parse(&mut parse)?),
+            b"getex" => Command::GetEx(GetEx::from_parse(&mut parse)?),
+            b"getset" => Command::GetSet(GetSet::from_parse(&mut parse)?),
+            b"type" => Command::Type(Type::from_parse(&mut parse)?),
+            b"object" => Command::Object(Object::from_parse(&mut parse)?),
+            b"dbsize" => Command::DbSize(DbSize::from_parse()),
+            b"flushdb" => Command::FlushDb(FlushDb::from_parse()),
+            b"flushall" => Command::FlushAll(FlushAll::from_parse()),
+            b"keys" => Command::Keys(Keys::from_parse(&mut parse)?),
+            b"scan" => Command::Scan(Scan::from_parse(&mut parse)?),
+            b"incrby" => Command::IncrBy(IncrBy::from_parse(&mut parse, false)?),
+            b"decrby" => Command::IncrBy(IncrBy::from_parse(&mut parse, true)?),
+            b"incrbyfloat" => Command::IncrByFloat(IncrByFloat::from_parse(&mut parse)?),
+            b"setrange" => Command::SetRange(SetRange::from_parse(&mut parse)?),
+            b"copy" => Command::Copy(Copy::from_parse(&mut parse)?),
+            b"ping" => Command::Ping(Ping::from_parse(&mut parse)?),
+            b"echo" => Command::Echo(Echo::from_parse(&mut parse)?),
+            b"monitor" => Command::Monitor(Monitor::from_parse()),
+            b"client" => Command::Client(Client::from_parse(&mut parse)?),
+            b"info" => Command::Info(Info::from_parse(&mut parse)?),
+            b"config" => Command::Config(Config::from_parse(&mut parse)?),
+            b"publish" => Command::Publish(Publish::from_parse(&mut parse)?),
+            b"subscribe" => Command::Subscribe(Subscribe::from_parse(&mut parse)?),
+            b"unsubscribe" => Command::Unsubscribe(Unsubscribe::from_parse(&mut parse)?),
+            b"select" => Command::Select(Select::from_parse(&mut parse)?),
+            b"lpush" => Command::Push(Push::from_parse(&mut parse, true, false)?),
+            b"rpush" => Command::Push(Push::from_parse(&mut parse, false, false)?),
+            b"lpushcapped" => Command::Push(Push::from_parse(&mut parse, true, true)?),
+            b"rpushcapped" => Command::Push(Push::from_parse(&mut parse, false, true)?),
+            b"lpop" => Command::Pop(Pop::from_parse(&mut parse, true)?),
+            b"rpop" => Command::Pop(Pop::from_parse(&mut parse, false)?),
+            b"lrange" => Command::LRange(LRange::from_parse(&mut parse)?),
+            b"llen" => Command::LLen(LLen::from_parse(&mut parse)?),
+            b"sadd" => Command::SAdd(SAdd::from_parse(&mut parse)?),
+            b"srem" => Command::SRem(SRem::from_parse(&mut parse)?),
+            b"smembers" => Command::SMembers(SMembers::from_parse(&mut parse)?),
+            b"sismember" => Command::SIsMember(SIsMember::from_parse(&mut parse)?),
+            b"reset" => Command::Reset(Reset::from_parse()),
+            b"multi" => Command::Multi(Multi::from_parse()),
+            b"exec" => Command::Exec(Exec::from_parse()),
+            b"discard" => Command::Discard(Discard::from_parse()),
+            b"auth" => Command::Auth(Auth::from_parse(&mut parse)?),
+            b"quit" => Command::Quit(Quit::from_parse()),
+            b"debug" => Command::Debug(Debug::from_parse(&mut parse)?),
+            b"command" => Command::CommandInfo(CommandInfo::from_parse(&mut parse)?),
+            _ => Command::Unknown(Unknown::new(unknown_name(&raw_name))?),
         };
         // If there are any remaining bytes in the frame, then the frame is malformed.
         parse.finish()?;
@@ -39,14 +493,390 @@ impl Command {
         Ok(command)
     }
 
-    /// Apply the command to the specified `Db` instance.
-    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+    /// Name of the command, as logged. Aliases, like DECR for INCR, share the name of their command.
+    pub(crate) fn name(&self) -> &'static str {
         use Command::*;
         match self {
-            Get(cmd) => cmd.apply(db, dst).await,
-            Set(cmd) => cmd.apply(db, dst).await,
-            Ping(cmd) => cmd.apply(dst).await,
-            Unknown(cmd) => cmd.apply(dst).await,
+            Get(_) => "get",
+            GetRange(_) => "getrange",
+            Set(_) => "set",
+            SetNx(_) => "setnx",
+            Del(_) => "del",
+            Exists(_) => "exists",
+            Incr(_) => "incr",
+            Ttl(_) => "ttl",
+            Expire(cmd) => cmd.name(),
+            Persist(_) => "persist",
+            Mget(_) => "mget",
+            Mset(_) => "mset",
+            Append(_) => "append",
+            Strlen(_) => "strlen",
+            GetDel(_) => "getdel",
+            GetEx(_) => "getex",
+            GetSet(_) => "getset",
+            Type(_) => "type",
+            Object(_) => "object",
+            DbSize(_) => "dbsize",
+            FlushDb(_) => "flushdb",
+            FlushAll(_) => "flushall",
+            Keys(_) => "keys",
+            Scan(_) => "scan",
+            IncrBy(_) => "incrby",
+            IncrByFloat(_) => "incrbyfloat",
+            SetRange(_) => "setrange",
+            Copy(_) => "copy",
+            Ping(_) => "ping",
+            Echo(_) => "echo",
+            Monitor(_) => "monitor",
+            Client(_) => "client",
+            Select(_) => "select",
+            Info(_) => "info",
+            Config(_) => "config",
+            Publish(_) => "publish",
+            Subscribe(_) => "subscribe",
+            Unsubscribe(_) => "unsubscribe",
+            Push(cmd) => cmd.name(),
+            Pop(cmd) => cmd.name(),
+            LRange(_) => "lrange",
+            LLen(_) => "llen",
+            SAdd(_) => "sadd",
+            SRem(_) => "srem",
+            SMembers(_) => "smembers",
+            SIsMember(_) => "sismember",
+            Reset(_) => "reset",
+            Multi(_) => "multi",
+            Exec(_) => "exec",
+            Discard(_) => "discard",
+            Auth(_) => "auth",
+            Quit(_) => "quit",
+            Debug(_) => "debug",
+            CommandInfo(_) => "command",
+            Unknown(_) => "unknown",
+        }
+    }
+
+    /// Check if the command keeps using the connection once applied, to stream replies or to read
+    /// the next commands itself. Such a command writes its replies itself, and can't be queued in a
+    /// transaction.
+    pub(crate) fn is_connection_bound(&self) -> bool {
+        matches!(
+            self,
+            Command::Monitor(_) | Command::Subscribe(_) | Command::Unsubscribe(_)
+        )
+    }
+
+    /// Check if the command may store more data. Once `maxmemory` is reached, these are refused
+    /// under the `noeviction` policy, or make room by evicting keys under the others.
+    pub(crate) fn uses_memory(&self) -> bool {
+        use Command::*;
+        matches!(
+            self,
+            Set(_)
+                | SetNx(_)
+                | GetSet(_)
+                | Mset(_)
+                | Append(_)
+                | Incr(_)
+                | IncrBy(_)
+                | IncrByFloat(_)
+                | SetRange(_)
+                | Copy(_)
+                | Push(_)
+                | SAdd(_)
+        )
+    }
+
+    /// Check if the command may change the data, these are logged to the append-only file.
+    pub(crate) fn is_write(&self) -> bool {
+        use Command::*;
+        self.uses_memory()
+            || matches!(
+                self,
+                Del(_) | Expire(_) | Persist(_) | GetDel(_) | GetEx(_) | Pop(_) | SRem(_) | FlushDb(_) | FlushAll(_)
+            )
+    }
+
+    /// Apply a [connection bound](Command::is_connection_bound) command on behalf of `client`, to
+    /// the database it selected among `dbs`. The command writes its replies to `dst` itself.
+    ///
+    /// Long-running commands, like MONITOR, return early when `shutdown` fires.
+    pub(crate) async fn apply(
+        self,
+        dbs: &[Db],
+        stats: &Stats,
+        dst: &mut Connection,
+        client: &mut ClientState,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        use Command::*;
+        let db = &dbs[client.db];
+        let name = self.name();
+        let span = debug_span!("command", name);
+        debug!(parent: &span, "dispatch");
+        let result = match self {
+            Monitor(cmd) => cmd.apply(db, dst, shutdown).instrument(span).await,
+            Subscribe(cmd) => cmd.apply(db, client, dst, shutdown).instrument(span).await,
+            Unsubscribe(cmd) => cmd.apply(dst).instrument(span).await,
+            _ => unreachable!("not connection bound"),
+        };
+        stats.record_call(name);
+        result
+    }
+
+    /// Apply the command on behalf of `client`, to the database it selected among `dbs`, and return
+    /// the reply. The command must not be [connection bound](Command::is_connection_bound), see
+    /// [Command::apply] for these.
+    pub(crate) async fn execute(
+        self,
+        dbs: &[Db],
+        stats: &Stats,
+        params: &Params,
+        client: &mut ClientState,
+    ) -> crate::Result<Frame> {
+        use Command::*;
+        // Without eviction, the write that goes over `maxmemory` is the last one accepted.
+        let memory_limit = (self.uses_memory() && !client.loading)
+            .then(|| params.memory_limit())
+            .flatten();
+        if let Some((maxmemory, MaxMemoryPolicy::NoEviction)) = memory_limit {
+            if db::used_memory(dbs) > maxmemory {
+                return Ok(Frame::out_of_memory());
+            }
+        }
+        // The reads of a `CLIENT NO-TOUCH` client don't count as accesses for the eviction.
+        let untouched;
+        let db = if client.no_touch {
+            untouched = dbs[client.db].without_touch();
+            &untouched
+        } else {
+            &dbs[client.db]
+        };
+        let name = self.name();
+        // Unknown commands are not counted.
+        let known = !matches!(self, Unknown(_));
+        let span = debug_span!("command", name);
+        debug!(parent: &span, "dispatch");
+        let result = match self {
+            Get(cmd) => cmd.apply(db).instrument(span).await,
+            GetRange(cmd) => cmd.apply(db).instrument(span).await,
+            Set(cmd) => cmd.apply(db).instrument(span).await,
+            SetNx(cmd) => cmd.apply(db).instrument(span).await,
+            Del(cmd) => cmd.apply(db).instrument(span).await,
+            Exists(cmd) => cmd.apply(db).instrument(span).await,
+            Incr(cmd) => cmd.apply(db).instrument(span).await,
+            Ttl(cmd) => cmd.apply(db).instrument(span).await,
+            Expire(cmd) => cmd.apply(db).instrument(span).await,
+            Persist(cmd) => cmd.apply(db).instrument(span).await,
+            Mget(cmd) => cmd.apply(db).instrument(span).await,
+            Mset(cmd) => cmd.apply(db).instrument(span).await,
+            Append(cmd) => cmd.apply(db, params.max_bulk_len()).instrument(span).await,
+            Strlen(cmd) => cmd.apply(db).instrument(span).await,
+            GetDel(cmd) => cmd.apply(db).instrument(span).await,
+            GetEx(cmd) => cmd.apply(db).instrument(span).await,
+            GetSet(cmd) => cmd.apply(db).instrument(span).await,
+            Type(cmd) => cmd.apply(db).instrument(span).await,
+            Object(cmd) => cmd.apply(db, params).instrument(span).await,
+            DbSize(cmd) => cmd.apply(db).instrument(span).await,
+            FlushDb(cmd) => cmd.apply(db).instrument(span).await,
+            FlushAll(cmd) => cmd.apply(dbs).instrument(span).await,
+            Keys(cmd) => cmd.apply(db).instrument(span).await,
+            Scan(cmd) => cmd.apply(db).instrument(span).await,
+            IncrBy(cmd) => cmd.apply(db).instrument(span).await,
+            IncrByFloat(cmd) => cmd.apply(db).instrument(span).await,
+            SetRange(cmd) => cmd.apply(db, params.max_bulk_len()).instrument(span).await,
+            Copy(cmd) => cmd.apply(db).instrument(span).await,
+            Ping(cmd) => cmd.apply().instrument(span).await,
+            Echo(cmd) => cmd.apply().instrument(span).await,
+            Client(cmd) => cmd.apply(client).instrument(span).await,
+            Select(cmd) => cmd.apply(dbs.len(), client).instrument(span).await,
+            Info(cmd) => cmd.apply(dbs, stats).instrument(span).await,
+            Config(cmd) => cmd.apply(params).instrument(span).await,
+            Publish(cmd) => cmd.apply(db).instrument(span).await,
+            Push(cmd) => cmd.apply(db).instrument(span).await,
+            Pop(cmd) => cmd.apply(db).instrument(span).await,
+            LRange(cmd) => cmd.apply(db).instrument(span).await,
+            LLen(cmd) => cmd.apply(db).instrument(span).await,
+            SAdd(cmd) => cmd.apply(db).instrument(span).await,
+            SRem(cmd) => cmd.apply(db).instrument(span).await,
+            SMembers(cmd) => cmd.apply(db).instrument(span).await,
+            SIsMember(cmd) => cmd.apply(db).instrument(span).await,
+            Reset(cmd) => cmd.apply(client).instrument(span).await,
+            Multi(_) | Exec(_) | Discard(_) => unreachable!("transactions are handled by the connection handler"),
+            Auth(_) | Quit(_) => unreachable!("handled by the connection handler"),
+            Monitor(_) | Subscribe(_) | Unsubscribe(_) => unreachable!("connection bound, see Command::apply"),
+            Debug(cmd) => cmd.apply().instrument(span).await,
+            CommandInfo(cmd) => cmd.apply().instrument(span).await,
+            Unknown(cmd) => cmd.apply().instrument(span).await,
+        };
+        // The keys just written are the most recently used, they are evicted last.
+        if let Some((maxmemory, MaxMemoryPolicy::AllKeysLru)) = memory_limit {
+            db::evict_lru(dbs, maxmemory);
+        }
+        // Counted once done, like Redis, so `INFO commandstats` doesn't report its own call.
+        if known {
+            stats.record_call(name);
+        }
+        result
+    }
+}
+
+/// Name of an unknown command as reported to the client, lowercase like Redis does.
+fn unknown_name(raw_name: &[u8]) -> String {
+    String::from_utf8_lossy(raw_name).to_lowercase()
+}
+
+#[cfg(test)]
+mod test_command {
+    use super::*;
+    use crate::db::DbGuard;
+
+    fn from_args(args: &[&str]) -> crate::Result<Command> {
+        Command::from_frame(Frame::Array(
+            args.iter().map(|arg| Frame::Bulk(arg.to_string().into())).collect(),
+        ))
+    }
+
+    #[test]
+    fn test_arity() {
+        let err = from_args(&["GET"]).err().unwrap();
+        assert_eq!(err.to_string(), "wrong number of arguments for 'get' command");
+        let err = from_args(&["PING", "hello", "world"]).err().unwrap();
+        assert_eq!(err.to_string(), "wrong number of arguments for 'ping' command");
+        let err = from_args(&["del"]).err().unwrap();
+        assert_eq!(err.to_string(), "wrong number of arguments for 'del' command");
+
+        assert!(matches!(from_args(&["GET", "foo"]), Ok(Command::Get(_))));
+        assert!(matches!(from_args(&["DEL", "a", "b", "c"]), Ok(Command::Del(_))));
+    }
+
+    #[test]
+    fn test_command_names() {
+        for name in COMMAND_NAMES {
+            assert!(Arity::of(name.as_bytes()).is_some(), "{} has no arity", name);
+            assert!(name.len() <= MAX_NAME_LEN);
         }
     }
+
+    #[test]
+    fn test_key_spec() {
+        let keys = |args: &[&str]| {
+            let args: Vec<_> = args.iter().map(|arg| Bytes::from(arg.to_string())).collect();
+            command_keys(&args).map(|keys| {
+                keys.into_iter()
+                    .map(|key| String::from_utf8(key.to_vec()).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(keys(&["GET", "foo"]), Ok(vec!["foo".to_string()]));
+        assert_eq!(keys(&["set", "foo", "bar", "EX", "10"]), Ok(vec!["foo".to_string()]));
+        assert_eq!(
+            keys(&["MSET", "a", "1", "b", "2"]),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            keys(&["DEL", "a", "b", "c"]),
+            Ok(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+        );
+        assert_eq!(
+            keys(&["COPY", "src", "dst", "REPLACE"]),
+            Ok(vec!["src".to_string(), "dst".to_string()])
+        );
+        assert_eq!(keys(&["PING"]), Err("ERR The command has no key arguments"));
+        assert_eq!(
+            keys(&["GET"]),
+            Err("ERR Invalid number of arguments specified for command")
+        );
+        assert_eq!(keys(&["NOPE", "foo"]), Err("ERR Invalid command specified"));
+
+        for name in COMMAND_NAMES {
+            if let Some(spec) = KeySpec::of(name.as_bytes()) {
+                assert!(spec.first >= 1 && spec.step >= 1, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mixed_case() {
+        assert!(matches!(from_args(&["get", "foo"]), Ok(Command::Get(_))));
+        assert!(matches!(from_args(&["GeT", "foo"]), Ok(Command::Get(_))));
+        assert!(matches!(
+            from_args(&["GETRANGE", "foo", "0", "1"]),
+            Ok(Command::GetRange(_))
+        ));
+        assert!(matches!(from_args(&["Monitor"]), Ok(Command::Monitor(_))));
+        let err = from_args(&["SeT", "foo"]).err().unwrap();
+        assert_eq!(err.to_string(), "wrong number of arguments for 'set' command");
+        // Longer than any known command.
+        assert!(matches!(
+            from_args(&["averyveryverylongcommandname"]),
+            Ok(Command::Unknown(_))
+        ));
+    }
+
+    #[test]
+    fn test_unknown_with_args() {
+        assert!(matches!(from_args(&["FOO", "bar"]), Ok(Command::Unknown(_))));
+    }
+
+    /// What a connection handler provides to the commands, without the connection itself.
+    struct Context {
+        dbs: Vec<Db>,
+        stats: Stats,
+        params: Params,
+        client: ClientState,
+    }
+
+    impl Context {
+        fn new() -> Self {
+            Context {
+                dbs: DbGuard::new(2).dbs(),
+                stats: Stats::new(),
+                params: Params::new(&crate::Config::default()),
+                client: ClientState::new("127.0.0.1:6379".parse().unwrap()),
+            }
+        }
+
+        async fn execute(&mut self, args: &[&str]) -> Frame {
+            let cmd = from_args(args).unwrap();
+            cmd.execute(&self.dbs, &self.stats, &self.params, &mut self.client)
+                .await
+                .unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute() {
+        let mut context = Context::new();
+        assert_eq!(
+            context.execute(&["SET", "foo", "bar"]).await,
+            Frame::Simple("OK".to_string())
+        );
+        assert_eq!(context.execute(&["GET", "foo"]).await, Frame::Bulk("bar".into()));
+        assert_eq!(context.execute(&["SELECT", "1"]).await, Frame::Simple("OK".to_string()));
+        assert_eq!(context.execute(&["GET", "foo"]).await, Frame::Null);
+        assert_eq!(
+            context.execute(&["NOSUCHCOMMAND"]).await,
+            Frame::Error("ERR unknown command 'nosuchcommand'".to_string())
+        );
+        assert_eq!(context.client.db, 1);
+        assert_eq!(context.stats.command_calls(), [("get", 2), ("select", 1), ("set", 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_list() {
+        let mut context = Context::new();
+        assert_eq!(
+            context.execute(&["RPUSH", "list", "a", "b", "c"]).await,
+            Frame::Integer(3)
+        );
+        assert_eq!(
+            context.execute(&["LRANGE", "list", "1", "-1"]).await,
+            Frame::Array(vec![Frame::Bulk("b".into()), Frame::Bulk("c".into())])
+        );
+        assert_eq!(context.execute(&["LPOP", "list"]).await, Frame::Bulk("a".into()));
+        assert_eq!(
+            context.execute(&["INCR", "list"]).await,
+            Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+        );
+    }
 }
@@ -0,0 +1,103 @@
+use crate::client::Client;
+use crate::connection::{self, Connection};
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::shutdown::Shutdown;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+
+/// `MONITOR` streams every command processed by the server until the client disconnects.
+pub struct Monitor {}
+
+impl Monitor {
+    pub fn from_parse() -> Self {
+        Monitor {}
+    }
+
+    pub async fn apply(self, db: &Db, dst: &mut Connection, shutdown: &mut Shutdown) -> crate::Result<()> {
+        // Subscribe before replying, so no command issued after the `OK` is missed.
+        let mut feed = db.monitor();
+        dst.write_frame(&Frame::Simple("OK".to_string())).await?;
+        loop {
+            tokio::select! {
+                line = feed.recv() => match line {
+                    Ok(line) => dst.write_frame(&Frame::Simple(line)).await?,
+                    // The client is too slow, some lines were dropped.
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                // Commands sent by a monitor client are not executed, nor fed to the monitors,
+                // we only care about the client going away.
+                frame = dst.read_frame() => match frame {
+                    Ok(Some(_)) => {}
+                    Ok(None) => return Ok(()),
+                    Err(err) if connection::is_disconnect(&err) => return Ok(()),
+                    Err(err) => return Err(err),
+                },
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Feed a command received from `client` to the MONITOR clients, if any.
+///
+/// The line is formatted as Redis does: `<timestamp> [<db> <addr>] "CMD" "arg"...`.
+pub(crate) fn feed_monitors(db: &Db, frame: &Frame, client: &Client) {
+    if !db.is_monitored() {
+        return;
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} [{} {}]",
+        now.as_secs(),
+        now.subsec_micros(),
+        client.db,
+        client.addr()
+    );
+    if let Frame::Array(args) = frame {
+        // Like Redis, the password of `AUTH` is not shown.
+        let redacted = matches!(args.first(), Some(Frame::Bulk(name)) if name.eq_ignore_ascii_case(b"auth"));
+        for (i, arg) in args.iter().enumerate() {
+            line.push(' ');
+            match arg {
+                _ if redacted && i > 0 => line.push_str("\"(redacted)\""),
+                Frame::Simple(s) => line.push_str(&repr(s.as_bytes())),
+                Frame::Bulk(b) => line.push_str(&repr(b)),
+                frame => line.push_str(&repr(format!("{:?}", frame).as_bytes())),
+            }
+        }
+    }
+    db.publish_monitor(line);
+}
+
+/// Quote an argument, escaping special and non-printable bytes.
+fn repr(arg: &[u8]) -> String {
+    let mut s = String::with_capacity(arg.len() + 2);
+    s.push('"');
+    for &b in arg {
+        match b {
+            b'\\' => s.push_str("\\\\"),
+            b'"' => s.push_str("\\\""),
+            b'\n' => s.push_str("\\n"),
+            b'\r' => s.push_str("\\r"),
+            b'\t' => s.push_str("\\t"),
+            b if b.is_ascii_graphic() || b == b' ' => s.push(b as char),
+            b => s.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    s.push('"');
+    s
+}
+
+#[cfg(test)]
+mod test_repr {
+    use super::*;
+
+    #[test]
+    fn test_repr() {
+        assert_eq!(repr(b"set"), "\"set\"");
+        assert_eq!(repr(b"a \"b\"\r\n"), "\"a \\\"b\\\"\\r\\n\"");
+        assert_eq!(repr(&[0xff, 0x00]), "\"\\xff\\x00\"");
+    }
+}
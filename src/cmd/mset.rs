@@ -0,0 +1,60 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use anyhow::anyhow;
+use bytes::Bytes;
+
+/// `MSET key value [key value ...]`, all the pairs are set atomically.
+pub struct Mset {
+    pairs: Vec<(String, Bytes)>,
+}
+
+impl Mset {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let mut pairs = vec![];
+        loop {
+            let key = match parse.next_string() {
+                Ok(key) => key,
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            };
+            let value = match parse.next_bytes() {
+                Ok(value) => value,
+                // A key without its value.
+                Err(ParseError::EndOfStream) => return Err(anyhow!("wrong number of arguments for 'mset' command")),
+                Err(err) => return Err(err.into()),
+            };
+            pairs.push((key, value));
+        }
+        Ok(Mset { pairs })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        db.mset(self.pairs);
+        Ok(Frame::Simple("OK".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test_mset {
+    use super::*;
+
+    fn parse_mset(args: &[&str]) -> crate::Result<Mset> {
+        let frame = Frame::Array(args.iter().map(|arg| Frame::Bulk(arg.to_string().into())).collect());
+        let mut parse = Parse::new(frame)?;
+        parse.next_string()?;
+        Mset::from_parse(&mut parse)
+    }
+
+    #[test]
+    fn test_pairs() {
+        let mset = parse_mset(&["MSET", "a", "1", "b", "2"]).unwrap();
+        assert_eq!(
+            mset.pairs,
+            vec![("a".to_string(), Bytes::from("1")), ("b".to_string(), Bytes::from("2"))]
+        );
+
+        let err = parse_mset(&["MSET", "a", "1", "b"]).err().unwrap();
+        assert_eq!(err.to_string(), "wrong number of arguments for 'mset' command");
+    }
+}
@@ -0,0 +1,48 @@
+use crate::config::Params;
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+use bytes::Bytes;
+
+/// `OBJECT ENCODING key`, reply with the encoding Redis would use for the value, nil if the key
+/// doesn't exist. The limits of the compact encodings are read from the parameters, e.g.
+/// `list-max-listpack-size`.
+pub enum Object {
+    Encoding(String),
+    Unknown(String),
+}
+
+impl Object {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let object = match subcommand.as_str() {
+            "encoding" => {
+                let mut keys = parse.remaining_strings()?;
+                if keys.len() != 1 {
+                    return Err(anyhow!("wrong number of arguments for 'object|encoding' command"));
+                }
+                Object::Encoding(keys.remove(0))
+            }
+            _ => {
+                // The arguments of an unknown subcommand are irrelevant.
+                parse.remaining_bytes()?;
+                Object::Unknown(subcommand)
+            }
+        };
+        Ok(object)
+    }
+
+    pub async fn apply(self, db: &Db, params: &Params) -> crate::Result<Frame> {
+        let frame = match self {
+            Object::Encoding(key) => match db.object_encoding(&key, &params.encoding_limits()) {
+                Some(encoding) => Frame::Bulk(Bytes::from_static(encoding.as_bytes())),
+                None => Frame::Null,
+            },
+            Object::Unknown(subcommand) => {
+                Frame::Error(format!("ERR unknown subcommand '{}'. Try OBJECT HELP.", subcommand))
+            }
+        };
+        Ok(frame)
+    }
+}
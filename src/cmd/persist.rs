@@ -0,0 +1,20 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `PERSIST key`, reply 1 if a TTL was removed and 0 otherwise.
+pub struct Persist {
+    key: String,
+}
+
+impl Persist {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(Persist { key })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let removed = db.persist(&self.key);
+        Ok(Frame::Integer(removed as i64))
+    }
+}
@@ -1,15 +1,35 @@
-use crate::connection::Connection;
 use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use bytes::Bytes;
 
-pub struct Ping {}
+/// `PING [message]`, reply PONG, or echo the message back.
+pub struct Ping {
+    msg: Option<Bytes>,
+}
 
 impl Ping {
-    pub fn from_parse() -> Self {
-        Ping {}
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let msg = match parse.next_bytes() {
+            Ok(msg) => Some(msg),
+            Err(ParseError::EndOfStream) => None,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Ping { msg })
+    }
+
+    pub async fn apply(self) -> crate::Result<Frame> {
+        let response = match self.msg {
+            Some(msg) => Frame::Bulk(msg),
+            None => Frame::Simple("PONG".to_string()),
+        };
+        Ok(response)
     }
 
-    pub async fn apply(&self, dst: &mut Connection) -> crate::Result<()> {
-        dst.write_frame(&Frame::Simple("PONG".to_string())).await?;
-        Ok(())
+    /// The reply of a subscribed client, like Redis it's an array of `pong` and the message.
+    pub fn subscribed_reply(self) -> Frame {
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"pong")),
+            Frame::Bulk(self.msg.unwrap_or_default()),
+        ])
     }
 }
@@ -0,0 +1,23 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `PUBLISH channel message`, reply with the number of subscribers that got the message.
+pub struct Publish {
+    channel: String,
+    message: Bytes,
+}
+
+impl Publish {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let channel = parse.next_string()?;
+        let message = parse.next_bytes()?;
+        Ok(Publish { channel, message })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let receivers = db.publish(&self.channel, self.message);
+        Ok(Frame::Integer(receivers as i64))
+    }
+}
@@ -0,0 +1,15 @@
+use crate::frame::Frame;
+
+/// `QUIT`, close the connection once the reply is sent.
+pub struct Quit {}
+
+impl Quit {
+    pub fn from_parse() -> Self {
+        Quit {}
+    }
+
+    /// The reply sent before closing, the connection handler closes the connection itself.
+    pub async fn apply(self) -> crate::Result<Frame> {
+        Ok(Frame::Simple("OK".to_string()))
+    }
+}
@@ -0,0 +1,108 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+use bytes::Bytes;
+
+/// `GETRANGE key start end`, also known as `SUBSTR` for backward compatibility.
+pub struct GetRange {
+    key: String,
+    start: i64,
+    end: i64,
+}
+
+impl GetRange {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let start = parse.next_signed_int()?;
+        let end = parse.next_signed_int()?;
+        Ok(GetRange { key, start, end })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.get(&self.key) {
+            // A missing key is treated as an empty string.
+            Ok(value) => Frame::Bulk(slice(&value.unwrap_or_default(), self.start, self.end)),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `SETRANGE key offset value`, overwrite part of the value starting at `offset`.
+pub struct SetRange {
+    key: String,
+    offset: u64,
+    value: Bytes,
+}
+
+impl SetRange {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let offset = parse.next_int().map_err(|_| anyhow!("offset is out of range"))?;
+        let value = parse.next_bytes()?;
+        Ok(SetRange { key, offset, value })
+    }
+
+    /// Overwrite the range, unless the value would get longer than `max_len`, checked before
+    /// anything is allocated.
+    pub async fn apply(self, db: &Db, max_len: usize) -> crate::Result<Frame> {
+        // The offset comes from the client, the end may not even fit in a u64. Like Redis, an empty
+        // write is not checked, it changes nothing.
+        match self.offset.checked_add(self.value.len() as u64) {
+            Some(end) if end <= max_len as u64 => {}
+            _ if self.value.is_empty() => {}
+            _ => return Ok(Frame::string_too_long()),
+        }
+        let offset = usize::try_from(self.offset).unwrap_or(usize::MAX);
+        let frame = match db.setrange(&self.key, offset, &self.value) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// Slice the raw bytes of `value` between `start` and `end`, both inclusive.
+///
+/// Negative indices count from the end, -1 is the last byte. Out of range indices are clamped,
+/// and an empty range results in an empty value.
+pub(crate) fn slice(value: &Bytes, start: i64, end: i64) -> Bytes {
+    let len = value.len() as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+    if len == 0 || start > end {
+        return Bytes::new();
+    }
+    value.slice(start as usize..=end as usize)
+}
+
+#[cfg(test)]
+mod test_slice {
+    use super::*;
+
+    #[test]
+    fn test_slice() {
+        let value = Bytes::from("This is a string");
+        assert_eq!(slice(&value, 0, 3), Bytes::from("This"));
+        assert_eq!(slice(&value, -3, -1), Bytes::from("ing"));
+        assert_eq!(slice(&value, 0, -1), value);
+        assert_eq!(slice(&value, 10, 100), Bytes::from("string"));
+    }
+
+    #[test]
+    fn test_slice_empty() {
+        let value = Bytes::from("hello");
+        assert_eq!(slice(&value, 3, 1), Bytes::new());
+        assert_eq!(slice(&value, 5, 10), Bytes::new());
+        assert_eq!(slice(&Bytes::new(), 0, -1), Bytes::new());
+    }
+
+    #[test]
+    fn test_slice_binary() {
+        // Not valid UTF-8, and "é" is two bytes long.
+        let value = Bytes::from(vec![0xff, 0xfe, 0x00, 0xc3, 0xa9, b'!']);
+        assert_eq!(slice(&value, 1, 3), Bytes::from(vec![0xfe, 0x00, 0xc3]));
+        assert_eq!(slice(&value, -2, -1), Bytes::from(vec![0xa9, b'!']));
+    }
+}
@@ -0,0 +1,19 @@
+use crate::client::Client;
+use crate::frame::Frame;
+
+/// `RESET`, bring the connection back to the state it starts with: database 0, no subscription,
+/// and the `CLIENT` flags cleared.
+pub struct Reset {}
+
+impl Reset {
+    pub fn from_parse() -> Self {
+        Reset {}
+    }
+
+    /// Reset the state held by `client`. The subscribed mode handles `RESET` itself, as it owns
+    /// the subscriptions.
+    pub async fn apply(self, client: &mut Client) -> crate::Result<Frame> {
+        client.reset();
+        Ok(Frame::Simple("RESET".to_string()))
+    }
+}
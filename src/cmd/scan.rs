@@ -0,0 +1,87 @@
+use crate::db::{Db, KeyType};
+use crate::frame::Frame;
+use crate::glob;
+use crate::parse::{Parse, ParseError};
+use anyhow::anyhow;
+use bytes::Bytes;
+
+/// Number of keys returned by a call of `SCAN` without `COUNT`, like Redis.
+const DEFAULT_COUNT: usize = 10;
+
+/// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`, reply with the cursor of the next call
+/// and a page of keys, see [Db::scan]. The scan starts at cursor 0 and is done once the returned
+/// cursor is 0 again.
+///
+/// Like Redis, `COUNT` is the number of keys looked at: `MATCH` and `TYPE` filter the page, which
+/// may end up empty while the scan is not done.
+pub struct Scan {
+    cursor: u64,
+    pattern: Option<Bytes>,
+    count: usize,
+    key_type: Option<String>,
+}
+
+/// The types `TYPE` accepts, the ones this server doesn't store match no key.
+const TYPE_NAMES: &[&str] = &["string", "list", "set", "zset", "hash", "stream"];
+
+impl Scan {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let cursor = parse.next_string()?.parse().map_err(|_| anyhow!("invalid cursor"))?;
+        let mut scan = Scan {
+            cursor,
+            pattern: None,
+            count: DEFAULT_COUNT,
+            key_type: None,
+        };
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option.to_lowercase(),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            };
+            let result = match option.as_str() {
+                "match" => parse.next_bytes().map(|pattern| scan.pattern = Some(pattern)),
+                "count" => match parse.next_int() {
+                    Ok(0) => return Err(parse.syntax_error().into()),
+                    count => count.map(|count| scan.count = usize::try_from(count).unwrap_or(usize::MAX)),
+                },
+                "type" => parse
+                    .next_string()
+                    .map(|name| scan.key_type = Some(name.to_lowercase())),
+                _ => return Err(parse.syntax_error().into()),
+            };
+            match result {
+                Ok(()) => {}
+                Err(ParseError::EndOfStream) => return Err(parse.syntax_error().into()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if let Some(name) = scan.key_type.as_deref().filter(|name| !TYPE_NAMES.contains(name)) {
+            return Err(anyhow!("unknown type name '{}'", name));
+        }
+        Ok(scan)
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let (next, keys) = db.scan(self.cursor, self.count);
+        let keys = keys
+            .into_iter()
+            .filter(|key| {
+                self.pattern
+                    .as_ref()
+                    .is_none_or(|pattern| glob::matches(pattern, key.as_bytes()))
+            })
+            // The type is checked last, a key removed since the page was read is left out.
+            .filter(|key| {
+                self.key_type
+                    .as_deref()
+                    .is_none_or(|name| db.key_type(key).map(KeyType::name) == Some(name))
+            })
+            .map(|key| Frame::Bulk(Bytes::from(key)))
+            .collect();
+        Ok(Frame::Array(vec![
+            Frame::Bulk(Bytes::from(next.to_string())),
+            Frame::Array(keys),
+        ]))
+    }
+}
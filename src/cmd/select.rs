@@ -0,0 +1,30 @@
+use crate::client::Client;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use anyhow::anyhow;
+
+/// `SELECT index`, switch the client to another database.
+pub struct Select {
+    index: i64,
+}
+
+impl Select {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let index = parse
+            .next_signed_int()
+            .map_err(|_| anyhow!("value is not an integer or out of range"))?;
+        Ok(Select { index })
+    }
+
+    /// Select the database, `databases` being the number of databases of the server.
+    pub async fn apply(self, databases: usize, client: &mut Client) -> crate::Result<Frame> {
+        let frame = match usize::try_from(self.index) {
+            Ok(index) if index < databases => {
+                client.db = index;
+                Frame::Simple("OK".to_string())
+            }
+            _ => Frame::Error("ERR DB index is out of range".to_string()),
+        };
+        Ok(frame)
+    }
+}
@@ -1,40 +1,181 @@
-use crate::connection::Connection;
+use crate::cmd::expire;
 use crate::db::Db;
+use crate::frame::Frame;
 use crate::parse::{Parse, ParseError};
-use anyhow::anyhow;
 use bytes::Bytes;
 use std::time::Duration;
 
 pub struct Set {
     key: String,
-    value: String,
+    value: Bytes,
     expire: Option<Duration>,
+    /// Keep the TTL of the previous value, instead of clearing it.
+    keep_ttl: bool,
+    /// Only set the key if it does not exist.
+    nx: bool,
+    /// Only set the key if it already exists.
+    xx: bool,
+    /// Reply with the previous value instead of `OK`.
+    get: bool,
 }
 
 impl Set {
     pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
         let key = parse.next_string()?;
-        let value = parse.next_string()?;
+        let value = parse.next_bytes()?;
         let mut expire: Option<Duration> = None;
-        match parse.next_string() {
-            // An expiration is specified in seconds. The next value is an integer
-            Ok(s) if s.to_uppercase() == "EX" => {
-                let secs = parse.next_int()?;
-                expire = Some(Duration::from_secs(secs));
+        let mut keep_ttl = false;
+        let (mut nx, mut xx, mut get) = (false, false, false);
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option.to_uppercase(),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            };
+            match option.as_str() {
+                // Only one of the expiration options may be given.
+                "EX" | "PX" | "EXAT" | "PXAT" | "KEEPTTL" if expire.is_some() || keep_ttl => {
+                    return Err(parse.syntax_error().into())
+                }
+                // A TTL in seconds or milliseconds.
+                "EX" => expire = Some(expire::next_ttl_option(parse, "set", 1000, false)?),
+                "PX" => expire = Some(expire::next_ttl_option(parse, "set", 1, false)?),
+                // An absolute Unix time in seconds or milliseconds.
+                "EXAT" => expire = Some(expire::next_ttl_option(parse, "set", 1000, true)?),
+                "PXAT" => expire = Some(expire::next_ttl_option(parse, "set", 1, true)?),
+                "KEEPTTL" => keep_ttl = true,
+                // NX and XX are mutually exclusive too.
+                "NX" | "XX" if nx || xx => return Err(parse.syntax_error().into()),
+                "NX" => nx = true,
+                "XX" => xx = true,
+                "GET" => get = true,
+                _ => return Err(parse.syntax_error().into()),
             }
-            Ok(s) if s.to_uppercase() == "PX" => {
-                let ms = parse.next_int()?;
-                expire = Some(Duration::from_millis(ms));
-            }
-            Err(ParseError::EndOfStream) => {}
-            _ => return Err(anyhow!("Invalid set command")),
         }
-        Ok(Set { key, value, expire })
+        Ok(Set {
+            key,
+            value,
+            expire,
+            keep_ttl,
+            nx,
+            xx,
+            get,
+        })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let expire = (!self.keep_ttl).then_some(self.expire);
+        let (set, prev) = match db.set_conditional(self.key, self.value, expire, self.nx, self.xx, self.get) {
+            Ok(result) => result,
+            Err(_) => return Ok(Frame::wrong_type()),
+        };
+        let frame = match prev {
+            // With GET the previous value is returned, whether the NX or XX condition was met or not.
+            Some(prev) if self.get => Frame::Bulk(prev),
+            _ if self.get => Frame::Null,
+            _ if set => Frame::Simple("OK".to_string()),
+            // The NX or XX condition was not met.
+            _ => Frame::Null,
+        };
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod test_set {
+    use super::*;
+
+    fn parse_set(args: &[&str]) -> crate::Result<Set> {
+        let frame = Frame::Array(args.iter().map(|arg| Frame::Bulk(arg.to_string().into())).collect());
+        let mut parse = Parse::new(frame)?;
+        parse.next_string()?;
+        Set::from_parse(&mut parse)
+    }
+
+    #[test]
+    fn test_error_position() {
+        let err = parse_set(&["SET", "foo", "bar", "XY", "10"]).err().unwrap();
+        assert_eq!(err.to_string(), "syntax error near argument 3");
+
+        let err = parse_set(&["SET", "foo", "bar", "PX", "ten"]).err().unwrap();
+        assert_eq!(err.to_string(), "protocol error; invalid number at argument 4");
     }
 
-    pub async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
-        db.set(self.key, Bytes::from(self.value), self.expire);
-        dst.write_frame(&crate::frame::Frame::Simple("OK".to_string())).await?;
-        Ok(())
+    #[test]
+    fn test_options() {
+        let set = parse_set(&["SET", "foo", "bar", "px", "100"]).unwrap();
+        assert_eq!(set.expire, Some(Duration::from_millis(100)));
+        assert!(!set.keep_ttl);
+
+        let set = parse_set(&["SET", "foo", "bar", "KEEPTTL"]).unwrap();
+        assert_eq!(set.expire, None);
+        assert!(set.keep_ttl);
+
+        // Expiration options are mutually exclusive.
+        let err = parse_set(&["SET", "foo", "bar", "KEEPTTL", "EX", "10"]).err().unwrap();
+        assert_eq!(err.to_string(), "syntax error near argument 4");
+        let err = parse_set(&["SET", "foo", "bar", "EX", "10", "PX", "10"]).err().unwrap();
+        assert_eq!(err.to_string(), "syntax error near argument 5");
+    }
+
+    #[test]
+    fn test_conditions() {
+        let set = parse_set(&["SET", "foo", "bar", "nx", "PX", "100"]).unwrap();
+        assert!(set.nx && !set.xx);
+        assert_eq!(set.expire, Some(Duration::from_millis(100)));
+
+        let set = parse_set(&["SET", "foo", "bar", "EX", "10", "XX"]).unwrap();
+        assert!(!set.nx && set.xx);
+        assert_eq!(set.expire, Some(Duration::from_secs(10)));
+
+        let err = parse_set(&["SET", "foo", "bar", "NX", "XX"]).err().unwrap();
+        assert_eq!(err.to_string(), "syntax error near argument 4");
+
+        let set = parse_set(&["SET", "foo", "bar", "get", "NX"]).unwrap();
+        assert!(set.get && set.nx);
+    }
+
+    #[test]
+    fn test_invalid_expire() {
+        for args in [
+            ["PX", "0"],
+            ["EX", "-10"],
+            ["EXAT", "0"],
+            ["PXAT", "-1"],
+            // Too large once in milliseconds.
+            ["EX", "9223372036854775807"],
+            // Past the deadlines that can be represented.
+            ["PX", "9223372036854775807"],
+            ["EX", "3153600001"],
+        ] {
+            let err = parse_set(&["SET", "foo", "bar", args[0], args[1]]).err().unwrap();
+            assert_eq!(err.to_string(), "invalid expire time in 'set' command", "{:?}", args);
+        }
+        let set = parse_set(&["SET", "foo", "bar", "EX", "1"]).unwrap();
+        assert_eq!(set.expire, Some(Duration::from_secs(1)));
+        let set = parse_set(&["SET", "foo", "bar", "PX", "1"]).unwrap();
+        assert_eq!(set.expire, Some(Duration::from_millis(1)));
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let db = Db::new();
+        let set = |args: &[&str]| parse_set(args).unwrap().apply(&db);
+        assert_eq!(
+            set(&["SET", "foo", "bar"]).await.unwrap(),
+            Frame::Simple("OK".to_string())
+        );
+        assert_eq!(set(&["SET", "foo", "baz", "NX"]).await.unwrap(), Frame::Null);
+        assert_eq!(
+            set(&["SET", "foo", "baz", "GET"]).await.unwrap(),
+            Frame::Bulk(Bytes::from("bar"))
+        );
+        assert_eq!(set(&["SET", "new", "baz", "GET"]).await.unwrap(), Frame::Null);
+
+        db.push("list", vec![Bytes::from("a")], true).unwrap();
+        assert_eq!(
+            set(&["SET", "list", "baz", "GET"]).await.unwrap(),
+            Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+        );
     }
 }
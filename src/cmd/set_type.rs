@@ -0,0 +1,90 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `SADD key member [member ...]`, reply with the number of members that were not in the set yet.
+pub struct SAdd {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+impl SAdd {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let members = parse.remaining_bytes()?;
+        Ok(SAdd { key, members })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.sadd(&self.key, self.members) {
+            Ok(added) => Frame::Integer(added as i64),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `SREM key member [member ...]`, reply with the number of members that were in the set.
+pub struct SRem {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+impl SRem {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let members = parse.remaining_bytes()?;
+        Ok(SRem { key, members })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.srem(&self.key, &self.members) {
+            Ok(removed) => Frame::Integer(removed as i64),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `SMEMBERS key`, reply with the members of the set, in no particular order.
+pub struct SMembers {
+    key: String,
+}
+
+impl SMembers {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(SMembers { key })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.smembers(&self.key) {
+            Ok(members) => Frame::Array(members.into_iter().map(Frame::Bulk).collect()),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
+
+/// `SISMEMBER key member`, reply 1 if `member` belongs to the set, 0 otherwise.
+pub struct SIsMember {
+    key: String,
+    member: Bytes,
+}
+
+impl SIsMember {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+        Ok(SIsMember { key, member })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.sismember(&self.key, &self.member) {
+            Ok(found) => Frame::Integer(found as i64),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
@@ -0,0 +1,23 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use bytes::Bytes;
+
+/// `SETNX key value`, reply with 1 if the key was set, or 0 if it already existed.
+pub struct SetNx {
+    key: String,
+    value: Bytes,
+}
+
+impl SetNx {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+        Ok(SetNx { key, value })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let set = db.set_nx(self.key, self.value);
+        Ok(Frame::Integer(set as i64))
+    }
+}
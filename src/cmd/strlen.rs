@@ -0,0 +1,23 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `STRLEN key`, the length in bytes of the value, 0 if the key doesn't exist.
+pub struct Strlen {
+    key: String,
+}
+
+impl Strlen {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(Strlen { key })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let frame = match db.strlen(&self.key) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(_) => Frame::wrong_type(),
+        };
+        Ok(frame)
+    }
+}
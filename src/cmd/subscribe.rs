@@ -0,0 +1,221 @@
+use crate::client::Client;
+use crate::cmd::Command;
+use crate::connection::{self, Connection};
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use crate::shutdown::Shutdown;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
+use tokio::task::{AbortHandle, JoinSet};
+use tracing::warn;
+
+/// `SUBSCRIBE channel [channel ...]`, then push the messages published to the channels until the
+/// client unsubscribes from all of them.
+pub struct Subscribe {
+    channels: Vec<String>,
+}
+
+/// `UNSUBSCRIBE [channel ...]`, from every channel if none is given.
+pub struct Unsubscribe {
+    channels: Vec<String>,
+}
+
+/// Number of messages waiting to be written to a subscriber, for all its channels. A subscriber
+/// falling further behind is disconnected, as `client-output-buffer-limit pubsub` does.
+const PENDING_MESSAGES: usize = 1024;
+
+/// The channels of a subscribed client.
+///
+/// A task per channel forwards its messages to a single queue, so the client waits on one queue
+/// whatever the number of channels.
+struct Subscriptions {
+    forwarders: JoinSet<()>,
+    channels: HashMap<String, AbortHandle>,
+    messages_tx: mpsc::Sender<(String, Bytes)>,
+    messages_rx: mpsc::Receiver<(String, Bytes)>,
+    /// Notified by a forwarder once messages are lost, the client is too slow.
+    overflow: Arc<Notify>,
+}
+
+impl Subscribe {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let channels = parse.remaining_strings()?;
+        Ok(Subscribe { channels })
+    }
+
+    /// Subscribe, then serve the client in the subscribed mode: only the pub/sub commands, PING and
+    /// QUIT are accepted, until the client is subscribed to no channel anymore, resets the
+    /// connection or quits.
+    pub async fn apply(
+        self,
+        db: &Db,
+        client: &mut Client,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.subscribe(db, self.channels, dst).await?;
+        while !subscriptions.channels.is_empty() && !client.closing {
+            tokio::select! {
+                Some((channel, message)) = subscriptions.messages_rx.recv() => {
+                    let frame = Frame::Array(vec![
+                        Frame::Bulk(Bytes::from_static(b"message")),
+                        Frame::Bulk(Bytes::from(channel)),
+                        Frame::Bulk(message),
+                    ]);
+                    // A client that doesn't read blocks the write, it must not block the disconnection.
+                    tokio::select! {
+                        written = dst.write_frame(&frame) => written?,
+                        _ = subscriptions.overflow.notified() => return disconnect(client),
+                    }
+                }
+                _ = subscriptions.overflow.notified() => return disconnect(client),
+                frame = dst.read_frame() => {
+                    let frame = match frame {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => return Ok(()),
+                        Err(err) if connection::is_disconnect(&err) => return Ok(()),
+                        Err(err) => return Err(err),
+                    };
+                    subscriptions.handle_command(db, client, frame, dst).await?;
+                }
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Close the connection of a subscriber that doesn't keep up with the messages published, rather
+/// than silently dropping messages or buffering them without bound.
+fn disconnect(client: &mut Client) -> crate::Result<()> {
+    warn!(pending = PENDING_MESSAGES, "closing a subscriber that is too slow");
+    client.closing = true;
+    Ok(())
+}
+
+impl Unsubscribe {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let channels = parse.remaining_strings()?;
+        Ok(Unsubscribe { channels })
+    }
+
+    /// Unsubscribe a client that is not subscribed to anything, only the replies are sent.
+    pub async fn apply(self, dst: &mut Connection) -> crate::Result<()> {
+        Subscriptions::new().unsubscribe(self.channels, dst).await
+    }
+}
+
+impl Subscriptions {
+    fn new() -> Self {
+        let (messages_tx, messages_rx) = mpsc::channel(PENDING_MESSAGES);
+        Subscriptions {
+            forwarders: JoinSet::new(),
+            channels: HashMap::new(),
+            messages_tx,
+            messages_rx,
+            overflow: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Subscribe to `channels`, replying with the number of subscriptions after each of them.
+    async fn subscribe(&mut self, db: &Db, channels: Vec<String>, dst: &mut Connection) -> crate::Result<()> {
+        for channel in channels {
+            if !self.channels.contains_key(&channel) {
+                let mut receiver = db.subscribe(channel.clone());
+                let messages_tx = self.messages_tx.clone();
+                let overflow = self.overflow.clone();
+                let name = channel.clone();
+                let forwarder = self.forwarders.spawn(async move {
+                    loop {
+                        match receiver.recv().await {
+                            Ok(message) => match messages_tx.try_send((name.clone(), message)) {
+                                Ok(()) => {}
+                                Err(TrySendError::Full(_)) => return overflow.notify_one(),
+                                Err(TrySendError::Closed(_)) => return,
+                            },
+                            // The client is too slow, some messages were dropped.
+                            Err(RecvError::Lagged(_)) => return overflow.notify_one(),
+                            Err(RecvError::Closed) => return,
+                        }
+                    }
+                });
+                self.channels.insert(channel.clone(), forwarder);
+            }
+            dst.write_frame(&self.reply("subscribe", Some(channel))).await?;
+        }
+        Ok(())
+    }
+
+    /// Unsubscribe from `channels`, or from all the channels if empty, replying with the number of
+    /// subscriptions left after each of them.
+    async fn unsubscribe(&mut self, mut channels: Vec<String>, dst: &mut Connection) -> crate::Result<()> {
+        if channels.is_empty() {
+            if self.channels.is_empty() {
+                return Ok(dst.write_frame(&self.reply("unsubscribe", None)).await?);
+            }
+            channels = self.channels.keys().cloned().collect();
+        }
+        for channel in channels {
+            if let Some(forwarder) = self.channels.remove(&channel) {
+                forwarder.abort();
+            }
+            dst.write_frame(&self.reply("unsubscribe", Some(channel))).await?;
+        }
+        Ok(())
+    }
+
+    /// Apply a command received in the subscribed mode.
+    async fn handle_command(
+        &mut self,
+        db: &Db,
+        client: &mut Client,
+        frame: Frame,
+        dst: &mut Connection,
+    ) -> crate::Result<()> {
+        let cmd = match Command::from_frame(frame) {
+            Ok(cmd) => cmd,
+            Err(err) => return Ok(dst.write_frame(&Frame::Error(format!("ERR {}", err))).await?),
+        };
+        match cmd {
+            Command::Subscribe(cmd) => self.subscribe(db, cmd.channels, dst).await,
+            Command::Unsubscribe(cmd) => self.unsubscribe(cmd.channels, dst).await,
+            Command::Ping(cmd) => Ok(dst.write_frame(&cmd.subscribed_reply()).await?),
+            Command::Quit(cmd) => {
+                dst.write_frame(&cmd.apply().await?).await?;
+                client.closing = true;
+                Ok(())
+            }
+            Command::Reset(cmd) => {
+                // Unsubscribe silently, which ends the subscribed mode.
+                for (_, forwarder) in self.channels.drain() {
+                    forwarder.abort();
+                }
+                let frame = cmd.apply(client).await?;
+                Ok(dst.write_frame(&frame).await?)
+            }
+            cmd => {
+                let err = format!(
+                    "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET \
+                     are allowed in this context",
+                    cmd.name()
+                );
+                Ok(dst.write_frame(&Frame::Error(err)).await?)
+            }
+        }
+    }
+
+    /// Confirmation of a (un)subscription: its kind, the channel and the number of subscriptions.
+    fn reply(&self, kind: &'static str, channel: Option<String>) -> Frame {
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(kind.as_bytes())),
+            channel.map_or(Frame::Null, |channel| Frame::Bulk(Bytes::from(channel))),
+            Frame::Integer(self.channels.len() as i64),
+        ])
+    }
+}
@@ -0,0 +1,80 @@
+use crate::cmd::Command;
+use crate::frame::Frame;
+use std::fmt;
+
+/// `MULTI`, queue the next commands of the client until `EXEC` or `DISCARD`.
+pub struct Multi {}
+
+/// `EXEC`, run the queued commands in order and reply with their replies, in one array. The
+/// commands of other clients wait until they are all run.
+pub struct Exec {}
+
+/// `DISCARD`, drop the queued commands.
+pub struct Discard {}
+
+impl Multi {
+    pub fn from_parse() -> Self {
+        Multi {}
+    }
+}
+
+impl Exec {
+    pub fn from_parse() -> Self {
+        Exec {}
+    }
+}
+
+impl Discard {
+    pub fn from_parse() -> Self {
+        Discard {}
+    }
+}
+
+/// The commands queued by a client since `MULTI`. The connection handler owns it, as these
+/// commands drive how the next ones are handled.
+#[derive(Default)]
+pub(crate) struct Transaction {
+    /// Each command along with its frame, to log to the append-only file once applied if any.
+    commands: Vec<(Command, Option<Frame>)>,
+    /// Set once a command could not be queued, then `EXEC` discards the transaction.
+    failed: bool,
+}
+
+// Commands don't implement `Debug`, only their names are shown.
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.commands.iter().map(|(cmd, _)| cmd.name()).collect();
+        f.debug_struct("Transaction")
+            .field("commands", &names)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl Transaction {
+    /// Queue `cmd` along with the `frame` to log once it's applied, and return the reply to send
+    /// right away.
+    pub(crate) fn queue(&mut self, cmd: Command, frame: Option<Frame>) -> Frame {
+        if cmd.is_connection_bound() {
+            self.failed = true;
+            return Frame::Error("ERR Command not allowed inside a transaction".to_string());
+        }
+        self.commands.push((cmd, frame));
+        Frame::Simple("QUEUED".to_string())
+    }
+
+    /// A command was rejected while queuing, e.g. it's unknown or has the wrong number of arguments.
+    pub(crate) fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// The commands to run on `EXEC`, or the error to reply with if one of them was rejected.
+    pub(crate) fn into_commands(self) -> Result<Vec<(Command, Option<Frame>)>, Frame> {
+        if self.failed {
+            return Err(Frame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            ));
+        }
+        Ok(self.commands)
+    }
+}
@@ -0,0 +1,36 @@
+use crate::db::Db;
+use crate::frame::Frame;
+use crate::parse::Parse;
+use std::time::Duration;
+
+/// `TTL key` and `PTTL key`, reply -2 if the key doesn't exist and -1 if it has no expiration.
+pub struct Ttl {
+    key: String,
+    millis: bool,
+}
+
+impl Ttl {
+    pub fn from_parse(parse: &mut Parse, millis: bool) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(Ttl { key, millis })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let ttl = match db.ttl(&self.key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(ttl)) => self.resolution(ttl),
+        };
+        Ok(Frame::Integer(ttl))
+    }
+
+    /// Convert the remaining time to the unit of the command, seconds are rounded like Redis does.
+    fn resolution(&self, ttl: Duration) -> i64 {
+        let ms = ttl.as_millis() as i64;
+        if self.millis {
+            ms
+        } else {
+            (ms + 500) / 1000
+        }
+    }
+}
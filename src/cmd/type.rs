@@ -0,0 +1,20 @@
+use crate::db::{Db, KeyType};
+use crate::frame::Frame;
+use crate::parse::Parse;
+
+/// `TYPE key`, reply with the type of the value, `none` if the key doesn't exist.
+pub struct Type {
+    key: String,
+}
+
+impl Type {
+    pub fn from_parse(parse: &mut Parse) -> crate::Result<Self> {
+        let key = parse.next_string()?;
+        Ok(Type { key })
+    }
+
+    pub async fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let name = db.key_type(&self.key).map_or("none", KeyType::name);
+        Ok(Frame::Simple(name.to_string()))
+    }
+}
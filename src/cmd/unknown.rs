@@ -1,4 +1,3 @@
-use crate::connection::Connection;
 use crate::frame::Frame;
 
 pub struct Unknown {
@@ -12,9 +11,8 @@ impl Unknown {
         })
     }
 
-    pub async fn apply(self, dst: &mut Connection) -> crate::Result<()> {
+    pub async fn apply(self) -> crate::Result<Frame> {
         let response = Frame::Error(format!("ERR unknown command '{}'", self.command_name));
-        dst.write_frame(&response).await?;
-        Ok(())
+        Ok(response)
     }
 }
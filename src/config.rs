@@ -0,0 +1,332 @@
+//! Server configuration, set once when the server starts, and the parameters exposed to clients
+//! through `CONFIG GET` and `CONFIG SET`.
+
+use crate::db::EncodingLimits;
+use crate::frame::Limits;
+use crate::glob;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Interval of the TCP keepalive probes sent on idle client sockets, `None` disables them.
+    ///
+    /// It detects dead peers, so half-open connections don't linger forever.
+    pub tcp_keepalive: Option<Duration>,
+    /// Maximum number of connected clients, once reached new connections wait to be accepted.
+    pub max_connections: usize,
+    /// Close the connection of a client that sent nothing for this long, `None` keeps it open.
+    pub idle_timeout: Option<Duration>,
+    /// Longest bulk string accepted from a client, in bytes. Longer ones are a protocol error.
+    pub max_bulk_len: usize,
+    /// Most elements in an array accepted from a client. Longer ones are a protocol error.
+    pub max_multibulk_len: usize,
+    /// Number of databases, selected by index with `SELECT`.
+    pub databases: usize,
+    /// Log the write commands to this file, and replay it when the server starts, so the data
+    /// survives a restart. `None` keeps the data in memory only.
+    pub aof_path: Option<PathBuf>,
+    /// Password clients must send with `AUTH` before any other command, `None` lets them in
+    /// right away.
+    pub requirepass: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            // Same as Redis `tcp-keepalive 300`.
+            tcp_keepalive: Some(Duration::from_secs(300)),
+            // Same as Redis `maxclients 10000`.
+            max_connections: 10000,
+            // Same as Redis `timeout 0`.
+            idle_timeout: None,
+            // Same as Redis `proto-max-bulk-len 512mb`.
+            max_bulk_len: 512 * 1024 * 1024,
+            // The fixed limit of Redis.
+            max_multibulk_len: 1024 * 1024,
+            // Same as Redis `databases 16`.
+            databases: 16,
+            // Same as Redis `appendonly no`.
+            aof_path: None,
+            // Same as Redis `requirepass ""`.
+            requirepass: None,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn frame_limits(&self) -> Limits {
+        Limits {
+            max_bulk_len: self.max_bulk_len,
+            max_multibulk_len: self.max_multibulk_len,
+            // Commands are flat arrays, a little slack for clients that nest anyway.
+            max_depth: 8,
+        }
+    }
+}
+
+/// The parameters of `CONFIG GET` and `CONFIG SET`, by lowercase name.
+///
+/// Clients probe them when they connect. Only the values are stored, setting one doesn't change
+/// how the server behaves, except for `maxmemory` and `maxmemory-policy`, see [Params::memory_limit],
+/// `requirepass`, see [Params::requirepass], and the limits of the encodings, see
+/// [Params::encoding_limits].
+#[derive(Debug)]
+pub(crate) struct Params {
+    params: Mutex<BTreeMap<String, String>>,
+    /// See [Config::max_bulk_len]. Like the limit of the frames, it's fixed once the server started,
+    /// whatever `CONFIG SET proto-max-bulk-len`.
+    max_bulk_len: usize,
+}
+
+impl Params {
+    /// Seed the parameters with the values of `config`, and the Redis defaults of the ones we don't
+    /// support.
+    pub(crate) fn new(config: &Config) -> Self {
+        let seconds = |duration: Option<Duration>| duration.map_or(0, |d| d.as_secs()).to_string();
+        let params = [
+            ("maxclients", config.max_connections.to_string()),
+            ("timeout", seconds(config.idle_timeout)),
+            ("tcp-keepalive", seconds(config.tcp_keepalive)),
+            ("proto-max-bulk-len", config.max_bulk_len.to_string()),
+            ("databases", config.databases.to_string()),
+            ("maxmemory", "0".to_string()),
+            ("maxmemory-policy", "noeviction".to_string()),
+            ("save", String::new()),
+            (
+                "appendonly",
+                if config.aof_path.is_some() { "yes" } else { "no" }.to_string(),
+            ),
+            ("requirepass", config.requirepass.clone().unwrap_or_default()),
+            ("list-max-listpack-size", "-2".to_string()),
+            ("set-max-intset-entries", "512".to_string()),
+            ("set-max-listpack-entries", "128".to_string()),
+            ("set-max-listpack-value", "64".to_string()),
+        ];
+        Params {
+            params: Mutex::new(params.into_iter().map(|(k, v)| (k.to_string(), v)).collect()),
+            max_bulk_len: config.max_bulk_len,
+        }
+    }
+
+    /// The parameters whose name matches the glob `pattern`, sorted by name.
+    pub(crate) fn get(&self, pattern: &str) -> Vec<(String, String)> {
+        let pattern = pattern.to_lowercase();
+        let params = self.params.lock().unwrap();
+        params
+            .iter()
+            .filter(|(name, _)| glob::matches(pattern.as_bytes(), name.as_bytes()))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.params.lock().unwrap().contains_key(&name.to_lowercase())
+    }
+
+    /// The `maxmemory` limit in bytes along with the `maxmemory-policy`, `None` without limit.
+    pub(crate) fn memory_limit(&self) -> Option<(usize, MaxMemoryPolicy)> {
+        let params = self.params.lock().unwrap();
+        let maxmemory = params.get("maxmemory").and_then(|value| parse_memory(value))?;
+        let policy = params
+            .get("maxmemory-policy")
+            .and_then(|value| MaxMemoryPolicy::parse(value));
+        (maxmemory > 0).then_some((maxmemory, policy.unwrap_or(MaxMemoryPolicy::NoEviction)))
+    }
+
+    /// The longest string a write may produce, e.g. with `APPEND` or `SETRANGE`.
+    pub(crate) fn max_bulk_len(&self) -> usize {
+        self.max_bulk_len
+    }
+
+    /// The password of `AUTH`, `None` if clients don't have to authenticate. An empty `requirepass`
+    /// means no password, like Redis.
+    pub(crate) fn requirepass(&self) -> Option<String> {
+        let params = self.params.lock().unwrap();
+        params
+            .get("requirepass")
+            .filter(|password| !password.is_empty())
+            .cloned()
+    }
+
+    /// The limits of the encodings reported by `OBJECT ENCODING`.
+    pub(crate) fn encoding_limits(&self) -> EncodingLimits {
+        let params = self.params.lock().unwrap();
+        // Checked when set, the values are numbers.
+        let number = |name: &str| {
+            params
+                .get(name)
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(0)
+        };
+        let count = |name: &str| usize::try_from(number(name)).unwrap_or(0);
+        EncodingLimits {
+            list_max_listpack_size: number("list-max-listpack-size"),
+            set_max_intset_entries: count("set-max-intset-entries"),
+            set_max_listpack_entries: count("set-max-listpack-entries"),
+            set_max_listpack_value: count("set-max-listpack-value"),
+        }
+    }
+
+    /// Check that `value` suits the parameter `name`, or return why it doesn't.
+    pub(crate) fn check(name: &str, value: &str) -> Result<(), &'static str> {
+        match name.to_lowercase().as_str() {
+            "list-max-listpack-size" if value.parse::<i64>().is_err() => {
+                Err("argument couldn't be parsed into an integer")
+            }
+            "set-max-intset-entries" | "set-max-listpack-entries" | "set-max-listpack-value"
+                if value.parse::<usize>().is_err() =>
+            {
+                Err("argument couldn't be parsed into an integer")
+            }
+            "maxmemory" if parse_memory(value).is_none() => Err("argument must be a memory value"),
+            "maxmemory-policy" if MaxMemoryPolicy::parse(value).is_none() => {
+                Err("argument(s) must be one of the following: noeviction, allkeys-lru")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Set the parameter `name`, return false if there is no such parameter.
+    pub(crate) fn set(&self, name: &str, value: String) -> bool {
+        let mut params = self.params.lock().unwrap();
+        match params.get_mut(&name.to_lowercase()) {
+            Some(param) => {
+                *param = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// What to do once `maxmemory` is reached, the `maxmemory-policy` parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MaxMemoryPolicy {
+    /// Refuse the commands that may use more memory.
+    NoEviction,
+    /// Evict the least recently used keys, see [crate::db::evict_lru].
+    AllKeysLru,
+}
+
+impl MaxMemoryPolicy {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "noeviction" => Some(MaxMemoryPolicy::NoEviction),
+            "allkeys-lru" => Some(MaxMemoryPolicy::AllKeysLru),
+            _ => None,
+        }
+    }
+}
+
+/// Parse an amount of memory the way Redis does in its configuration, e.g. `100`, `1k` or `10mb`.
+/// A `k` is 1000 bytes, a `kb` is 1024 bytes, and so on for `m`, `mb`, `g` and `gb`.
+fn parse_memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let unit = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+#[cfg(test)]
+mod test_params {
+    use super::*;
+
+    #[test]
+    fn test_get_set() {
+        let params = Params::new(&Config::default());
+        assert_eq!(
+            params.get("maxclients"),
+            vec![("maxclients".to_string(), "10000".to_string())]
+        );
+        assert_eq!(params.get("MAXMEMORY*").len(), 2);
+        assert!(params.get("nothing").is_empty());
+
+        assert!(params.set("MaxMemory", "100mb".to_string()));
+        assert_eq!(
+            params.get("maxmemory"),
+            vec![("maxmemory".to_string(), "100mb".to_string())]
+        );
+        assert!(params.contains("MAXMEMORY"));
+        assert!(!params.contains("maxmemory*"));
+        assert!(!params.set("nothing", "1".to_string()));
+        assert!(params.get("nothing").is_empty());
+    }
+    #[test]
+    fn test_memory_limit() {
+        let params = Params::new(&Config::default());
+        assert_eq!(params.memory_limit(), None);
+        params.set("maxmemory", "1kb".to_string());
+        assert_eq!(params.memory_limit(), Some((1024, MaxMemoryPolicy::NoEviction)));
+        params.set("maxmemory-policy", "AllKeys-LRU".to_string());
+        assert_eq!(params.memory_limit(), Some((1024, MaxMemoryPolicy::AllKeysLru)));
+        params.set("maxmemory", "0".to_string());
+        assert_eq!(params.memory_limit(), None);
+    }
+
+    #[test]
+    fn test_requirepass() {
+        let params = Params::new(&Config::default());
+        assert_eq!(params.requirepass(), None);
+        params.set("requirepass", "secret".to_string());
+        assert_eq!(params.requirepass(), Some("secret".to_string()));
+        params.set("requirepass", String::new());
+        assert_eq!(params.requirepass(), None);
+
+        let params = Params::new(&Config {
+            requirepass: Some("secret".to_string()),
+            ..Config::default()
+        });
+        assert_eq!(params.requirepass(), Some("secret".to_string()));
+    }
+
+    #[test]
+    fn test_encoding_limits() {
+        let params = Params::new(&Config::default());
+        assert_eq!(params.encoding_limits().list_max_listpack_size, -2);
+        assert_eq!(params.encoding_limits().set_max_intset_entries, 512);
+        params.set("list-max-listpack-size", "100".to_string());
+        assert_eq!(params.encoding_limits().list_max_listpack_size, 100);
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("100"), Some(100));
+        assert_eq!(parse_memory("100b"), Some(100));
+        assert_eq!(parse_memory("2k"), Some(2000));
+        assert_eq!(parse_memory("2KB"), Some(2048));
+        assert_eq!(parse_memory("3mb"), Some(3 * 1024 * 1024));
+        assert_eq!(parse_memory("1g"), Some(1000 * 1000 * 1000));
+        assert_eq!(parse_memory(""), None);
+        assert_eq!(parse_memory("mb"), None);
+        assert_eq!(parse_memory("-1"), None);
+        assert_eq!(parse_memory("1tb"), None);
+        assert_eq!(parse_memory("1.5mb"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(Params::check("maxmemory", "100mb"), Ok(()));
+        assert_eq!(
+            Params::check("MAXMEMORY", "lots"),
+            Err("argument must be a memory value")
+        );
+        assert_eq!(Params::check("maxmemory-policy", "allkeys-lru"), Ok(()));
+        assert!(Params::check("maxmemory-policy", "volatile-ttl").is_err());
+        assert_eq!(Params::check("list-max-listpack-size", "-2"), Ok(()));
+        assert!(Params::check("set-max-intset-entries", "-2").is_err());
+        // The other parameters take any value.
+        assert_eq!(Params::check("timeout", "lots"), Ok(()));
+    }
+}
@@ -1,22 +1,31 @@
-use crate::frame::Frame;
+use crate::frame::{one_line, Frame, Limits};
+use anyhow::anyhow;
 use bytes::{Buf, BytesMut};
 use std::io;
 use std::io::Cursor;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
 use tokio::net::TcpStream;
 
+/// Longest inline command, the same as Redis. Inline commands are typed by hand, they are short.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Frames read from and written to a stream, a [TcpStream] for the server, or e.g. an in-memory
+/// [tokio::io::DuplexStream] in the tests.
 #[derive(Debug)]
-pub struct Connection {
-    stream: BufWriter<TcpStream>,
+pub struct Connection<S = TcpStream> {
+    stream: BufWriter<S>,
     buf: BytesMut,
+    /// Bounds on the frames read from the peer.
+    limits: Limits,
 }
 
-impl Connection {
-    pub fn new(stream: TcpStream) -> Self {
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub(crate) fn new(stream: S, limits: Limits) -> Self {
         Connection {
             stream: BufWriter::new(stream),
             // Allocate 4KB of capacity for the buffer.
             buf: BytesMut::with_capacity(4 * 1024),
+            limits,
         }
     }
 
@@ -24,6 +33,9 @@ impl Connection {
     ///
     /// This function will read from the stream until a full RESP line is read.
     /// There may be additional data left in the buffer after the call to this
+    ///
+    /// If the peer closes the socket in the middle of a frame, an `io::Error` with
+    /// kind `ConnectionReset` is returned, see [is_disconnect].
     pub(crate) async fn read_frame(&mut self) -> crate::Result<Option<Frame>> {
         loop {
             if let Some(frame) = self.parse_frame()? {
@@ -34,16 +46,38 @@ impl Connection {
                 return if self.buf.is_empty() {
                     Ok(None)
                 } else {
-                    Err(anyhow::anyhow!("connection reset by peer"))
+                    // The peer closed the socket while sending a frame.
+                    Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by peer").into())
                 };
             }
         }
     }
 
+    /// Get the next frame if it's already buffered, without reading from the stream. The frames of
+    /// a pipeline, sent by the client before reading any reply, are read this way.
+    pub(crate) fn read_buffered_frame(&mut self) -> crate::Result<Option<Frame>> {
+        self.parse_frame()
+    }
+
     fn parse_frame(&mut self) -> crate::Result<Option<Frame>> {
         use crate::frame::Error::Incomplete;
+        loop {
+            // Tolerate blank lines between commands, as redis-cli and telnet may send them.
+            let blank = self.buf.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+            self.buf.advance(blank);
+            match self.buf.first() {
+                None => return Ok(None),
+                Some(b'+' | b'-' | b':' | b'$' | b'*') => break,
+                // Not a RESP frame, but a command typed by hand, e.g. in telnet.
+                Some(_) => match self.parse_inline()? {
+                    // Only spaces, skip the line like a blank one.
+                    Some(args) if args.is_empty() => continue,
+                    args => return Ok(args.map(Frame::Array)),
+                },
+            }
+        }
         let mut buf = Cursor::new(&self.buf[..]);
-        match Frame::check(&mut buf) {
+        match Frame::check(&mut buf, &self.limits) {
             Ok(_) => {
                 let len = buf.position() as usize;
                 buf.set_position(0);
@@ -56,11 +90,270 @@ impl Connection {
         }
     }
 
+    /// Parse an inline command, a line of space separated arguments, into bulk strings, as if the
+    /// client sent it in the RESP form. `None` if the line is incomplete.
+    fn parse_inline(&mut self) -> crate::Result<Option<Vec<Frame>>> {
+        let Some(end) = self.buf.iter().position(|&b| b == b'\n') else {
+            if self.buf.len() > MAX_INLINE_LEN {
+                return Err(anyhow!("protocol error; too big inline request"));
+            }
+            return Ok(None);
+        };
+        let line = self.buf.split_to(end + 1).freeze();
+        let args = line[..end]
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| Frame::Bulk(line.slice_ref(arg)))
+            .collect();
+        Ok(Some(args))
+    }
+
+    /// Write a frame to the stream and flush it, see [Connection::feed_frame].
     pub(crate) async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-        self.stream.write_all(frame.serialize().as_bytes()).await?;
-        // Ensure the encoded frame is written to the socket. The calls above
-        // are to the buffered stream and writes. Calling `flush` writes the
-        // remaining contents of the buffer to the socket.
+        self.feed_frame(frame).await?;
+        self.flush().await
+    }
+
+    /// Write the frames fed so far to the stream.
+    pub(crate) async fn flush(&mut self) -> io::Result<()> {
         self.stream.flush().await
     }
+
+    /// Write a frame to the stream, piece by piece, so memory use is bounded by the capacity of the
+    /// `BufWriter` rather than the size of the reply.
+    ///
+    /// The end of the frame may stay in the buffer until [Connection::flush], so the replies of
+    /// pipelined commands are sent together.
+    pub(crate) async fn feed_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        // Nested arrays are walked with an explicit stack, async functions can't recurse without
+        // boxing every level.
+        let mut stack = vec![std::slice::from_ref(frame).iter()];
+        while let Some(frames) = stack.last_mut() {
+            match frames.next() {
+                Some(Frame::Array(frames)) => {
+                    self.write_decimal(b'*', frames.len() as i64).await?;
+                    stack.push(frames.iter());
+                }
+                Some(frame) => self.write_value(frame).await?,
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a frame that is not an array.
+    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
+        match frame {
+            Frame::Simple(s) => {
+                self.stream.write_u8(b'+').await?;
+                self.stream.write_all(one_line(s).as_bytes()).await?;
+                self.stream.write_all(b"\r\n").await?;
+            }
+            Frame::Error(s) => {
+                self.stream.write_u8(b'-').await?;
+                self.stream.write_all(one_line(s).as_bytes()).await?;
+                self.stream.write_all(b"\r\n").await?;
+            }
+            Frame::Integer(i) => self.write_decimal(b':', *i).await?,
+            Frame::Bulk(b) => {
+                self.write_decimal(b'$', b.len() as i64).await?;
+                self.stream.write_all(b).await?;
+                self.stream.write_all(b"\r\n").await?;
+            }
+            Frame::Null => self.stream.write_all(b"$-1\r\n").await?,
+            Frame::NullArray => self.stream.write_all(b"*-1\r\n").await?,
+            Frame::Array(_) => unreachable!("arrays are written by write_frame"),
+        }
+        Ok(())
+    }
+
+    /// Write the type byte, a decimal number and the CRLF.
+    async fn write_decimal(&mut self, prefix: u8, val: i64) -> io::Result<()> {
+        use std::io::Write;
+        // Room for the prefix, the sign and 19 digits, then the CRLF.
+        let mut buf = Cursor::new([0u8; 24]);
+        write!(&mut buf, "{}{}\r\n", prefix as char, val)?;
+        let len = buf.position() as usize;
+        self.stream.write_all(&buf.get_ref()[..len]).await
+    }
+}
+
+#[cfg(test)]
+mod test_connection {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn connection_pair() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        (Connection::new(stream, crate::Config::default().frame_limits()), client)
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_skip_blank_lines() {
+        let (mut connection, mut client) = connection_pair().await;
+        client.write_all(b"\r\n*1\r\n$4\r\nPING\r\n\r\n\n").await.unwrap();
+        drop(client);
+        let frame = connection.read_frame().await.unwrap();
+        assert_eq!(frame, Some(Frame::Array(vec![Frame::Bulk("PING".into())])));
+        // Trailing blank lines are not an incomplete frame.
+        assert_eq!(connection.read_frame().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_write_frame_nested() {
+        let (mut connection, mut client) = connection_pair().await;
+        let large = Frame::Bulk(vec![b'x'; 64 * 1024].into());
+        let frame = Frame::Array(vec![
+            Frame::Simple("OK".to_string()),
+            Frame::Array(vec![large.clone(), Frame::Integer(i64::MIN), Frame::Array(vec![])]),
+            Frame::Error("ERR oops".to_string()),
+            Frame::Array((0..1000).map(Frame::Integer).collect()),
+            Frame::Null,
+            Frame::NullArray,
+            large,
+        ]);
+        let expected = frame.serialize();
+        connection.write_frame(&frame).await.unwrap();
+        drop(connection);
+
+        let mut received = vec![];
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_read_inline_command() {
+        let (mut connection, mut client) = connection_pair().await;
+        client
+            .write_all(b"PING\r\n*1\r\n$4\r\nPING\r\nSET  foo\tbar\n  \r\nGET foo\r\n")
+            .await
+            .unwrap();
+        drop(client);
+        let inline = connection.read_frame().await.unwrap();
+        let array = connection.read_frame().await.unwrap();
+        assert_eq!(inline, Some(Frame::Array(vec![Frame::Bulk("PING".into())])));
+        assert_eq!(inline, array);
+        assert_eq!(
+            connection.read_frame().await.unwrap(),
+            Some(Frame::Array(vec![
+                Frame::Bulk("SET".into()),
+                Frame::Bulk("foo".into()),
+                Frame::Bulk("bar".into())
+            ]))
+        );
+        assert_eq!(
+            connection.read_frame().await.unwrap(),
+            Some(Frame::Array(vec![Frame::Bulk("GET".into()), Frame::Bulk("foo".into())]))
+        );
+        assert_eq!(connection.read_frame().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_many_space_lines() {
+        let (mut connection, _client) = connection_pair().await;
+        connection.buf.extend_from_slice(&b" \n".repeat(2_000_000));
+        connection.buf.extend_from_slice(b"PING\r\n");
+        assert_eq!(
+            connection.read_buffered_frame().unwrap(),
+            Some(Frame::Array(vec![Frame::Bulk("PING".into())]))
+        );
+        assert!(connection.buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_too_large() {
+        let (mut connection, mut client) = connection_pair().await;
+        connection.limits.max_bulk_len = 4;
+        // Only the header is sent, the payload is never waited for.
+        client.write_all(b"*2\r\n$3\r\nGET\r\n$5\r\n").await.unwrap();
+        let err = connection.read_frame().await.unwrap_err();
+        assert_eq!(err.to_string(), "protocol error; invalid bulk length");
+    }
+
+    #[tokio::test]
+    async fn test_read_buffered_frame() {
+        let (mut connection, mut client) = connection_pair().await;
+        client.write_all(b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPI").await.unwrap();
+        assert_eq!(connection.read_buffered_frame().unwrap(), None);
+        let ping = Some(Frame::Array(vec![Frame::Bulk("PING".into())]));
+        assert_eq!(connection.read_frame().await.unwrap(), ping);
+        // The second frame is incomplete, the stream isn't read to get the rest.
+        assert_eq!(connection.read_buffered_frame().unwrap(), None);
+        client.write_all(b"NG\r\n").await.unwrap();
+        assert_eq!(connection.read_frame().await.unwrap(), ping);
+    }
+
+    #[tokio::test]
+    async fn test_duplex() {
+        let (stream, mut client) = tokio::io::duplex(64);
+        let mut connection = Connection::new(stream, crate::Config::default().frame_limits());
+        let frame = Frame::Array(vec![Frame::Bulk("SET".into()), Frame::Bulk(vec![b'x'; 100].into())]);
+        // Larger than the duplex buffer, it's read while it's written.
+        let command = frame.serialize();
+        let (written, read) = tokio::join!(client.write_all(&command), connection.read_frame());
+        written.unwrap();
+        assert_eq!(read.unwrap(), Some(frame));
+
+        let reply = Frame::Array(vec![Frame::Simple("OK".to_string()), Frame::Integer(-1)]);
+        connection.write_frame(&reply).await.unwrap();
+        let mut received = vec![0; reply.serialize().len()];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, reply.serialize());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_split() {
+        let (mut connection, mut client) = connection_pair().await;
+        let reader = tokio::spawn(async move { connection.read_frame().await.unwrap() });
+        // The bulk string is cut in the middle, in two separate writes.
+        client.write_all(b"*2\r\n$3\r\nGET\r\n$11\r\nhello").await.unwrap();
+        client.flush().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        client.write_all(b" world\r\n").await.unwrap();
+        let frame = reader.await.unwrap();
+        assert_eq!(
+            frame,
+            Some(Frame::Array(vec![
+                Frame::Bulk("GET".into()),
+                Frame::Bulk("hello world".into())
+            ]))
+        );
+    }
+}
+
+/// Check if the error means the peer went away, e.g. closed the socket in the middle of a frame.
+///
+/// This is a normal situation (connection pools drop sockets all the time), not a protocol error.
+pub(crate) fn is_disconnect(err: &crate::Error) -> bool {
+    match err.downcast_ref::<io::Error>() {
+        Some(err) => matches!(
+            err.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+        ),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test_is_disconnect {
+    use super::*;
+
+    #[test]
+    fn test_is_disconnect() {
+        let err: crate::Error = io::Error::from(io::ErrorKind::ConnectionReset).into();
+        assert!(is_disconnect(&err));
+        let err: crate::Error = io::Error::from(io::ErrorKind::UnexpectedEof).into();
+        assert!(is_disconnect(&err));
+        let err: crate::Error = io::Error::from(io::ErrorKind::InvalidData).into();
+        assert!(!is_disconnect(&err));
+        let err = anyhow::anyhow!("protocol error; invalid frame format");
+        assert!(!is_disconnect(&err));
+    }
 }
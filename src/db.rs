@@ -1,13 +1,24 @@
-use bytes::Bytes;
-use std::collections::{BTreeSet, HashMap};
-use std::sync::{Arc, Mutex};
-use tokio::sync::Notify;
+use crate::{glob, time_util};
+use bytes::{Bytes, BytesMut};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::{broadcast, Notify};
 use tokio::time::{self, Duration, Instant};
 
-/// A wrapper around a `Db` instance.
+/// The databases of the server, selected by index with `SELECT`.
 #[derive(Debug)]
 pub(crate) struct DbGuard {
-    db: Db,
+    dbs: Vec<Db>,
+    /// Wakes up the purge task of each shard index, see [purge_expired_keys].
+    purge_notify: Vec<Arc<Notify>>,
+    /// Set once the guard is dropped, to stop the purge tasks.
+    purge_stop: Arc<AtomicBool>,
 }
 
 /// The main database struct.
@@ -18,172 +29,2252 @@ pub(crate) struct DbGuard {
 #[derive(Debug, Clone)]
 pub(crate) struct Db {
     shared: Arc<Shared>,
+    /// Reads leave the access time of keys alone, see [Db::without_touch].
+    no_touch: bool,
+}
+
+/// Number of shards of the key space, see [Shard].
+const SHARDS: usize = 16;
+
+/// Number of lines buffered for each MONITOR client, a slower client misses lines.
+const MONITOR_CAPACITY: usize = 1024;
+
+/// Number of messages buffered for each subscriber of a channel, a slower subscriber misses messages.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Number of keys sampled in each shard to find the least recently used one, like Redis
+/// `maxmemory-samples` but per shard.
+const EVICTION_SAMPLES: usize = 5;
+
+/// A random number, from the random keys std seeds every `RandomState` with.
+fn random() -> usize {
+    RandomState::new().build_hasher().finish() as usize
+}
+
+/// Longest string Redis stores along with its object header, the `embstr` encoding.
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// Logical clock of the key accesses, shared by all the databases so their keys compare for eviction.
+///
+/// Unlike an `Instant`, two accesses never get the same time, and it's updated with the shard
+/// only locked for reading.
+static ACCESS_CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// Create a new `DB` instance. All handlers will share the same instance.
+#[derive(Debug)]
+struct Shared {
+    /// Keys are spread over the shards by hash, so connections using different keys rarely wait
+    /// for each other.
+    shards: Box<[Shard]>,
+    /// Shared by all the databases of a server.
+    feeds: Arc<Feeds>,
+}
+
+/// The feeds of a server, they don't belong to a database.
+#[derive(Debug)]
+struct Feeds {
+    /// Feed of every processed command, consumed by MONITOR clients.
+    monitor: broadcast::Sender<String>,
+    /// Pub/sub channels by name. A channel is created by its first subscriber, and removed once a
+    /// message finds no subscriber.
+    channels: Mutex<HashMap<String, broadcast::Sender<Bytes>>>,
+}
+
+/// A part of the key space, with its own lock.
+///
+/// Operations on several keys lock the shards they need in index order, so they can't deadlock.
+/// Read-only operations share the lock, only writes take it exclusively.
+#[derive(Debug, Default)]
+struct Shard {
+    state: RwLock<State>,
+    /// Wakes up the background task purging the shards of this index, in every database.
+    bg_task_notify: Arc<Notify>,
+    /// Copy of [State::used_memory], updated when the shard is unlocked, so the memory used is read
+    /// without locking every shard.
+    used_memory: AtomicUsize,
+}
+
+/// The state of a [Shard] locked with [Shard::write].
+struct ShardWriteGuard<'a> {
+    state: RwLockWriteGuard<'a, State>,
+    /// See [Shard::used_memory].
+    used_memory_copy: &'a AtomicUsize,
+}
+
+/// DB state entry.
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    /// The keys of `entries`, each at the [Entry::slot] of its entry, to pick random keys.
+    keys: Vec<String>,
+    /// Tracks key TTLs.
+    ///
+    /// BTreeSet is a sorted set, so we can get the first element which is the earliest expiration time.
+    /// While highly unlikely, it is possible for two keys to have the same expiration time. So we also store the key name.
+    expirations: BTreeSet<(Instant, String)>,
+    /// Tasks waiting for a key to be written, see [Db::wait_for_key].
+    waiters: HashMap<String, Vec<Arc<Notify>>>,
+    /// Approximate memory used by `entries`, see [EntryValue::size].
+    used_memory: usize,
+}
+
+/// Entry in the key-value store.
+#[derive(Debug)]
+struct Entry {
+    /// Stored value
+    value: EntryValue,
+    /// Instant at which the entry expires and should be removed from the database.
+    /// None means it will never expire.
+    expires_at: Option<Instant>,
+    /// Time of the last access on the [ACCESS_CLOCK], the least recently used keys are evicted first.
+    last_access: AtomicU64,
+    /// Index of the key in [State::keys].
+    slot: usize,
+}
+
+/// A task waiting for a key to be written, see [Db::wait_for_key]. It's removed from
+/// [State::waiters] once dropped.
+struct KeyWaiter {
+    db: Db,
+    key: String,
+    notify: Arc<Notify>,
+}
+
+/// Value of an entry, one variant per Redis type.
+#[derive(Debug, Clone)]
+enum EntryValue {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    /// `Bytes` hashes and compares its content, so members are binary-safe.
+    Set(HashSet<Bytes>),
+}
+
+/// Limits of the compact encodings reported by `OBJECT ENCODING`, the parameters of the same names,
+/// see [crate::config::Params::encoding_limits]. Values are not actually encoded, these only
+/// decide what is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EncodingLimits {
+    /// `list-max-listpack-size`: the most elements of a `listpack` if positive, otherwise its
+    /// largest size, from 4 KiB for -1 to 64 KiB for -5.
+    pub(crate) list_max_listpack_size: i64,
+    /// `set-max-intset-entries`.
+    pub(crate) set_max_intset_entries: usize,
+    /// `set-max-listpack-entries`.
+    pub(crate) set_max_listpack_entries: usize,
+    /// `set-max-listpack-value`, the longest member of a `listpack` set.
+    pub(crate) set_max_listpack_value: usize,
+}
+
+/// Type of the value of a key, as reported by `TYPE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyType {
+    String,
+    List,
+    Set,
+}
+
+/// Error of an operation against a key holding a value of another type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WrongType;
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WRONGTYPE Operation against a key holding the wrong kind of value")
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+impl Drop for KeyWaiter {
+    fn drop(&mut self) {
+        let mut state = self.db.shard(&self.key).write();
+        // Not found once woken, the key's waiters are all removed then.
+        if let Some(waiters) = state.waiters.get_mut(&self.key) {
+            waiters.retain(|notify| !Arc::ptr_eq(notify, &self.notify));
+            if waiters.is_empty() {
+                state.waiters.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl KeyType {
+    /// Name of the type, as reported by `TYPE` and matched by `SCAN TYPE`.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            KeyType::String => "string",
+            KeyType::List => "list",
+            KeyType::Set => "set",
+        }
+    }
+}
+
+impl Entry {
+    /// Create an entry that never expires, its key at `slot` in [State::keys].
+    fn new(value: EntryValue, slot: usize) -> Self {
+        Entry {
+            value,
+            expires_at: None,
+            last_access: AtomicU64::new(ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed)),
+            slot,
+        }
+    }
+
+    /// Record an access to the entry, now.
+    fn touch(&self) {
+        self.last_access
+            .store(ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Check if the entry is past its deadline at `now`.
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|when| when <= now)
+    }
+}
+
+impl EntryValue {
+    /// Approximate memory used by the value: the length of its bytes, without the overhead of the
+    /// allocations.
+    fn size(&self) -> usize {
+        match self {
+            EntryValue::String(data) => data.len(),
+            EntryValue::List(list) => list.iter().map(Bytes::len).sum(),
+            EntryValue::Set(set) => set.iter().map(Bytes::len).sum(),
+        }
+    }
+
+    /// The type of the value, one per variant.
+    fn key_type(&self) -> KeyType {
+        match self {
+            EntryValue::String(_) => KeyType::String,
+            EntryValue::List(_) => KeyType::List,
+            EntryValue::Set(_) => KeyType::Set,
+        }
+    }
+
+    /// Name of the encoding Redis would use for the value, as reported by `OBJECT ENCODING`.
+    fn encoding(&self, limits: &EncodingLimits) -> &'static str {
+        match self {
+            EntryValue::String(data) if is_int(data) => "int",
+            EntryValue::String(data) if data.len() <= EMBSTR_MAX_LEN => "embstr",
+            EntryValue::String(_) => "raw",
+            EntryValue::List(list) => {
+                let fits = match usize::try_from(limits.list_max_listpack_size) {
+                    Ok(entries) => list.len() <= entries,
+                    Err(_) => {
+                        let shift = limits.list_max_listpack_size.unsigned_abs().min(5) - 1;
+                        // A listpack has a 7 bytes header and end, and about 2 bytes per entry.
+                        let size = 7 + list.iter().map(|value| value.len() + 2).sum::<usize>();
+                        size <= 4096 << shift
+                    }
+                };
+                if fits {
+                    "listpack"
+                } else {
+                    "quicklist"
+                }
+            }
+            EntryValue::Set(set) if set.len() <= limits.set_max_intset_entries && set.iter().all(|m| is_int(m)) => {
+                "intset"
+            }
+            EntryValue::Set(set)
+                if set.len() <= limits.set_max_listpack_entries
+                    && set.iter().all(|member| member.len() <= limits.set_max_listpack_value) =>
+            {
+                "listpack"
+            }
+            EntryValue::Set(_) => "hashtable",
+        }
+    }
+
+    fn as_string(&self) -> Result<&Bytes, WrongType> {
+        match self {
+            EntryValue::String(data) => Ok(data),
+            _ => Err(WrongType),
+        }
+    }
+
+    fn into_string(self) -> Result<Bytes, WrongType> {
+        match self {
+            EntryValue::String(data) => Ok(data),
+            _ => Err(WrongType),
+        }
+    }
+
+    fn as_set(&self) -> Result<&HashSet<Bytes>, WrongType> {
+        match self {
+            EntryValue::Set(set) => Ok(set),
+            _ => Err(WrongType),
+        }
+    }
+
+    fn as_set_mut(&mut self) -> Result<&mut HashSet<Bytes>, WrongType> {
+        match self {
+            EntryValue::Set(set) => Ok(set),
+            _ => Err(WrongType),
+        }
+    }
+
+    fn as_list(&self) -> Result<&VecDeque<Bytes>, WrongType> {
+        match self {
+            EntryValue::List(list) => Ok(list),
+            _ => Err(WrongType),
+        }
+    }
+
+    fn as_list_mut(&mut self) -> Result<&mut VecDeque<Bytes>, WrongType> {
+        match self {
+            EntryValue::List(list) => Ok(list),
+            _ => Err(WrongType),
+        }
+    }
+}
+
+impl DbGuard {
+    /// Create `databases` empty databases.
+    ///
+    /// A background task per shard index purges the expired keys of that shard in every database,
+    /// so the number of tasks doesn't grow with `databases`. They stop once the guard is dropped.
+    pub(crate) fn new(databases: usize) -> Self {
+        let feeds = Arc::new(Feeds::default());
+        let purge_notify: Vec<Arc<Notify>> = (0..SHARDS).map(|_| Arc::default()).collect();
+        let dbs: Vec<Db> = (0..databases)
+            .map(|_| Db::with_shared(Shared::with_notify(&purge_notify, feeds.clone())))
+            .collect();
+        let purge_stop = Arc::new(AtomicBool::new(false));
+        for (index, notify) in purge_notify.iter().enumerate() {
+            tokio::spawn(purge_expired_keys(
+                dbs.clone(),
+                index,
+                notify.clone(),
+                purge_stop.clone(),
+            ));
+        }
+        DbGuard {
+            dbs,
+            purge_notify,
+            purge_stop,
+        }
+    }
+
+    /// Get handles to the databases, indexed like `SELECT` does.
+    pub(crate) fn dbs(&self) -> Vec<Db> {
+        self.dbs.clone()
+    }
+}
+
+impl Drop for DbGuard {
+    fn drop(&mut self) {
+        self.purge_stop.store(true, Ordering::Relaxed);
+        for notify in &self.purge_notify {
+            notify.notify_one();
+        }
+    }
 }
 
-/// Create a new `DB` instance. All handlers will share the same instance.
-#[derive(Debug)]
-struct Shared {
-    state: Mutex<State>,
-    bg_task_notify: Notify,
-}
+impl Db {
+    /// A database on its own, whose purge tasks run as long as the runtime.
+    #[cfg(test)]
+    pub(crate) fn new() -> Self {
+        let db = Db::with_shared(Shared::new(SHARDS, Arc::default()));
+        for (index, shard) in db.shared.shards.iter().enumerate() {
+            let notify = shard.bg_task_notify.clone();
+            tokio::spawn(purge_expired_keys(vec![db.clone()], index, notify, Arc::default()));
+        }
+        db
+    }
+
+    fn with_shared(shared: Shared) -> Self {
+        Db {
+            shared: Arc::new(shared),
+            no_touch: false,
+        }
+    }
+
+    /// A handle to the same database, whose reads don't update the access time of keys, for the
+    /// clients in `CLIENT NO-TOUCH` mode. Writes still do.
+    pub(crate) fn without_touch(&self) -> Db {
+        Db {
+            shared: self.shared.clone(),
+            no_touch: true,
+        }
+    }
+
+    /// Record a read of `entry`, unless reads don't count, see [Db::without_touch].
+    fn touch(&self, entry: &Entry) {
+        if !self.no_touch {
+            entry.touch();
+        }
+    }
+
+    /// Index of the shard holding `key`.
+    fn shard_index(&self, key: &str) -> usize {
+        // `DefaultHasher::new` has fixed keys, a key always goes to the same shard.
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shared.shards.len() as u64) as usize
+    }
+
+    /// The shard holding `key`.
+    fn shard(&self, key: &str) -> &Shard {
+        &self.shared.shards[self.shard_index(key)]
+    }
+
+    /// Lock the shards holding `keys` with `lock`, i.e. [Shard::read] or [Shard::write], in index
+    /// order. The result is indexed by shard, `None` for the shards that are not locked.
+    fn lock_shards<'a, 's, G>(
+        &'s self,
+        keys: impl IntoIterator<Item = &'a str>,
+        lock: impl Fn(&'s Shard) -> G,
+    ) -> Vec<Option<G>> {
+        let mut needed = vec![false; self.shared.shards.len()];
+        for key in keys {
+            needed[self.shard_index(key)] = true;
+        }
+        needed
+            .into_iter()
+            .zip(self.shared.shards.iter())
+            .map(|(needed, shard)| needed.then(|| lock(shard)))
+            .collect()
+    }
+
+    /// Lock every shard with `lock`, in index order.
+    fn lock_all<'s, G>(&'s self, lock: impl Fn(&'s Shard) -> G) -> Vec<G> {
+        self.shared.shards.iter().map(lock).collect()
+    }
+
+    /// Set `key` to `value`, expiring after `expire` if any. A previous value of any type is replaced.
+    ///
+    /// The TTL of a previous value is always replaced: with no `expire` the key doesn't expire anymore,
+    /// like a plain `SET` in Redis. Return the previous value if it was a string.
+    // SET itself goes through `set_conditional`, for its options.
+    #[cfg(test)]
+    pub(crate) fn set(&self, key: String, value: Bytes, expire: Option<Duration>) -> Option<Bytes> {
+        // Without `get` a previous value of another type is not an error.
+        self.set_conditional(key, value, Some(expire), false, false, false)
+            .ok()
+            .and_then(|(_, prev)| prev)
+    }
+
+    /// Set `key` to `value`, keeping the TTL of the previous value if any, i.e. `SET ... KEEPTTL`.
+    #[cfg(test)]
+    pub(crate) fn set_keep_ttl(&self, key: String, value: Bytes) -> Option<Bytes> {
+        self.set_conditional(key, value, None, false, false, false)
+            .ok()
+            .and_then(|(_, prev)| prev)
+    }
+
+    /// Set `key` to `value` if it doesn't exist yet, i.e. `SETNX`. Return whether it was set.
+    pub(crate) fn set_nx(&self, key: String, value: Bytes) -> bool {
+        // Without `get` there is no type error, an existing value of any type is kept.
+        self.set_conditional(key, value, Some(None), true, false, false)
+            .is_ok_and(|(set, _)| set)
+    }
+
+    /// Set `key` to `value` and return the previous value, i.e. `GETSET`. The TTL is cleared, like a
+    /// plain `SET`. A previous value of another type is an error and nothing is set.
+    pub(crate) fn getset(&self, key: String, value: Bytes) -> Result<Option<Bytes>, WrongType> {
+        let (_, prev) = self.set_conditional(key, value, Some(None), false, false, true)?;
+        Ok(prev)
+    }
+
+    /// Set several keys at once, holding the locks of all their shards so no client sees part of
+    /// the batch.
+    ///
+    /// Like a plain `SET`, the TTL of the previous values is cleared.
+    pub(crate) fn mset(&self, pairs: Vec<(String, Bytes)>) {
+        let mut shards = self.lock_shards(pairs.iter().map(|(key, _)| key.as_str()), Shard::write);
+        for (key, value) in pairs {
+            let state = shards[self.shard_index(&key)].as_mut().unwrap();
+            state.wake_waiters(&key);
+            state.insert_entry(key, EntryValue::String(value));
+        }
+    }
+
+    /// Set `key` to `value` only if it doesn't exist yet (`nx`) or only if it already exists (`xx`),
+    /// the check and the write happen under the same lock. Return whether the value was set, along
+    /// with the previous value if it was a string, which is returned even when the condition is not
+    /// met.
+    ///
+    /// `expire` is the new TTL, or `None` to keep the TTL of the previous value, i.e. `KEEPTTL`.
+    /// With `get`, i.e. `SET ... GET`, a previous value of another type is an error and nothing is set.
+    pub(crate) fn set_conditional(
+        &self,
+        key: String,
+        value: Bytes,
+        expire: Option<Option<Duration>>,
+        nx: bool,
+        xx: bool,
+        get: bool,
+    ) -> Result<(bool, Option<Bytes>), WrongType> {
+        let shard = self.shard(&key);
+        let mut state = shard.write();
+        let now = Instant::now();
+        let live = state.entries.get(&key).filter(|entry| !entry.is_expired(now));
+        let exists = live.is_some();
+        let prev = match live.map(|entry| entry.value.as_string()) {
+            Some(Ok(data)) => Some(data.clone()),
+            Some(Err(err)) if get => return Err(err),
+            _ => None,
+        };
+        if (nx && exists) || (xx && !exists) {
+            return Ok((false, prev));
+        }
+
+        if expire.is_none() && exists {
+            // Only the value changes, so the expiration index is still valid.
+            state.update_value(&key, EntryValue::String(value), now);
+            state.wake_waiters(&key);
+            return Ok((true, prev));
+        }
+
+        // Drop the previous entry and its expiration, then set the new ones.
+        state.insert_entry(key.clone(), EntryValue::String(value));
+        state.wake_waiters(&key);
+        let notify = state.set_expiry(&key, expire.flatten().map(time_util::deadline));
+
+        // Notify the background task to check the expiration time.
+        // Before notifying, we need to drop the lock to avoid deadlock.
+        drop(state);
+
+        if notify {
+            // Only notify the background task if it needs
+            shard.bg_task_notify.notify_one();
+        }
+        Ok((true, prev))
+    }
+
+    /// Get the value of `key`. A key past its deadline is removed right away, without waiting for
+    /// the purge task.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Bytes>, WrongType> {
+        let state = self.shard(key).read();
+        let Some(entry) = state.entries.get(key) else {
+            return Ok(None);
+        };
+        if !entry.is_expired(Instant::now()) {
+            self.touch(entry);
+            return entry.value.as_string().cloned().map(Some);
+        }
+        drop(state);
+        self.remove_expired(key);
+        Ok(None)
+    }
+
+    /// Remove `key` if it is past its deadline.
+    ///
+    /// Readers only hold the read lock, so they call this once they released it.
+    fn remove_expired(&self, key: &str) {
+        let mut state = self.shard(key).write();
+        // Check again, the key may have been set again in the meantime.
+        if state
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(Instant::now()))
+        {
+            state.remove_entry(key);
+        }
+    }
+
+    /// Get the value of `key`, and change its TTL in the same locked step: `None` leaves it
+    /// unchanged, `Some(None)` removes it and `Some(Some(ttl))` replaces it.
+    pub(crate) fn getex(&self, key: &str, expire: Option<Option<Duration>>) -> Result<Option<Bytes>, WrongType> {
+        let shard = self.shard(key);
+        let mut state = shard.write();
+        let now = Instant::now();
+        let Some(entry) = state.entries.get(key).filter(|entry| !entry.is_expired(now)) else {
+            return Ok(None);
+        };
+        self.touch(entry);
+        let value = entry.value.as_string()?.clone();
+        let notify = match expire {
+            Some(ttl) => state.set_expiry(key, ttl.map(time_util::deadline)),
+            None => false,
+        };
+        drop(state);
+
+        if notify {
+            shard.bg_task_notify.notify_one();
+        }
+        Ok(Some(value))
+    }
+
+    /// Copy the value of `src` to `dst`, along with its deadline. Unless `replace` is set, an
+    /// existing `dst` is left alone. Return whether the value was copied.
+    pub(crate) fn copy(&self, src: &str, dst: &str, replace: bool) -> bool {
+        let mut shards = self.lock_shards([src, dst], Shard::write);
+        let now = Instant::now();
+        let src_state = shards[self.shard_index(src)].as_ref().unwrap();
+        let Some(entry) = src_state.entries.get(src).filter(|entry| !entry.is_expired(now)) else {
+            return false;
+        };
+        let (value, expires_at) = (entry.value.clone(), entry.expires_at);
+        let state = shards[self.shard_index(dst)].as_mut().unwrap();
+        if !replace && state.contains(dst, now) {
+            return false;
+        }
+
+        state.insert_entry(dst.to_string(), value);
+        state.wake_waiters(dst);
+        let notify = state.set_expiry(dst, expires_at);
+        drop(shards);
+
+        // The same deadline as the source, but maybe the earliest one of another shard.
+        if notify {
+            self.shard(dst).bg_task_notify.notify_one();
+        }
+        true
+    }
+
+    /// Remove `key` and return its value, in one locked step so nobody sees the key in between.
+    /// A value of another type is left alone.
+    pub(crate) fn getdel(&self, key: &str) -> Result<Option<Bytes>, WrongType> {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        if let Some(entry) = state.entries.get(key).filter(|entry| !entry.is_expired(now)) {
+            entry.value.as_string()?;
+        }
+        // An expired entry is dropped all the same, but it has no value anymore.
+        state
+            .remove_entry(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| entry.value.into_string())
+            .transpose()
+    }
+
+    /// Get the keys matching the glob `pattern`, leaving out the ones past their deadline.
+    #[cfg(test)]
+    pub(crate) fn keys(&self, pattern: &[u8]) -> Vec<String> {
+        self.keys_limited(pattern, usize::MAX)
+    }
+
+    /// Get up to `limit` keys matching the glob `pattern`, like [Db::keys]. The scan stops once
+    /// `limit` keys are found, so a sample of a large keyspace doesn't block for long.
+    pub(crate) fn keys_limited(&self, pattern: &[u8], limit: usize) -> Vec<String> {
+        let shards = self.lock_all(Shard::read);
+        let now = Instant::now();
+        shards
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .filter(|(key, entry)| !entry.is_expired(now) && glob::matches(pattern, key.as_bytes()))
+            .map(|(key, _)| key.clone())
+            .take(limit)
+            .collect()
+    }
+
+    /// Get up to `count` keys from `cursor` on, for `SCAN`, and the cursor to continue from, 0 once
+    /// all the keys were returned.
+    ///
+    /// Keys are returned in the order of their hash, the cursor is the next hash. Like Redis, a key
+    /// that exists during the whole scan is returned, once. Unlike Redis, each call goes through
+    /// all the keys, only the reply is limited.
+    pub(crate) fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let shards = self.lock_all(Shard::read);
+        let now = Instant::now();
+        let mut found: Vec<(u64, &String)> = shards
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| (scan_position(key), key))
+            .filter(|(position, _)| *position >= cursor)
+            .collect();
+        found.sort_unstable();
+        // Keys with the same hash are returned together, the next cursor is past all of them.
+        let mut end = count.max(1).min(found.len());
+        while end < found.len() && found[end].0 == found[end - 1].0 {
+            end += 1;
+        }
+        let next = if end == found.len() { 0 } else { found[end - 1].0 + 1 };
+        (next, found[..end].iter().map(|(_, key)| (*key).clone()).collect())
+    }
+
+    /// Remove every key.
+    pub(crate) fn flush(&self) {
+        for mut state in self.lock_all(Shard::write) {
+            state.entries.clear();
+            state.keys.clear();
+            state.expirations.clear();
+            state.used_memory = 0;
+        }
+        // No need to notify the background tasks: they find nothing to purge when they wake up,
+        // then wait for the next key with a TTL.
+    }
+
+    /// Count the keys, leaving out the ones past their deadline that are not purged yet.
+    pub(crate) fn len(&self) -> usize {
+        let shards = self.lock_all(Shard::read);
+        let now = Instant::now();
+        shards
+            .iter()
+            .flat_map(|state| state.entries.values())
+            .filter(|entry| !entry.is_expired(now))
+            .count()
+    }
+
+    /// Count the keys, and among them the keys with a TTL. Like [Db::len], the keys past their
+    /// deadline are left out.
+    pub(crate) fn key_counts(&self) -> (usize, usize) {
+        let shards = self.lock_all(Shard::read);
+        let now = Instant::now();
+        let live = shards
+            .iter()
+            .flat_map(|state| state.entries.values())
+            .filter(|entry| !entry.is_expired(now));
+        live.fold((0, 0), |(keys, expires), entry| {
+            (keys + 1, expires + entry.expires_at.is_some() as usize)
+        })
+    }
+
+    /// Get the type of the value stored at `key`, `None` if it doesn't exist.
+    pub(crate) fn key_type(&self, key: &str) -> Option<KeyType> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| entry.value.key_type())
+    }
+
+    /// Get the encoding of the value stored at `key` given `limits`, see [EncodingLimits], `None` if
+    /// it doesn't exist. Like `OBJECT`, this is not an access to the key.
+    pub(crate) fn object_encoding(&self, key: &str, limits: &EncodingLimits) -> Option<&'static str> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| entry.value.encoding(limits))
+    }
+
+    /// Get the length in bytes of the value of `key`, 0 if it doesn't exist.
+    pub(crate) fn strlen(&self, key: &str) -> Result<usize, WrongType> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map_or(Ok(0), |entry| {
+                self.touch(entry);
+                entry.value.as_string().map(Bytes::len)
+            })
+    }
+
+    /// Get the values of several keys at once, from a single snapshot of their shards.
+    ///
+    /// Missing keys, keys past their deadline and keys holding another type than a string are `None`.
+    pub(crate) fn mget(&self, keys: &[String]) -> Vec<Option<Bytes>> {
+        let shards = self.lock_shards(keys.iter().map(String::as_str), Shard::read);
+        let now = Instant::now();
+        keys.iter()
+            .map(|key| {
+                shards[self.shard_index(key)]
+                    .as_ref()
+                    .unwrap()
+                    .entries
+                    .get(key)
+                    .filter(|entry| !entry.is_expired(now))
+                    .and_then(|entry| {
+                        self.touch(entry);
+                        entry.value.as_string().ok().cloned()
+                    })
+            })
+            .collect()
+    }
+
+    /// Add `delta` to the integer stored at `key`, a missing key counts as 0. The TTL is kept.
+    ///
+    /// The whole read-modify-write happens under the state lock, so concurrent updates are not lost.
+    /// Return `None` if the value is not an integer, or the result would overflow.
+    pub(crate) fn incr_by(&self, key: &str, delta: i64) -> Result<Option<i64>, WrongType> {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        let current = match state.entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => {
+                let data = entry.value.as_string()?;
+                let Some(current) = std::str::from_utf8(data).ok().and_then(|s| s.parse::<i64>().ok()) else {
+                    return Ok(None);
+                };
+                current
+            }
+            _ => 0,
+        };
+        let Some(value) = current.checked_add(delta) else {
+            return Ok(None);
+        };
+        state.update_value(key, EntryValue::String(Bytes::from(value.to_string())), now);
+        state.wake_waiters(key);
+        Ok(Some(value))
+    }
+
+    /// Add `delta` to the float stored at `key`, a missing key counts as 0. The TTL is kept.
+    ///
+    /// The result is stored and returned formatted as a decimal string, without trailing zeros.
+    /// Return `None` if the value is not a float, or the result is not finite.
+    pub(crate) fn incr_by_float(&self, key: &str, delta: f64) -> Result<Option<Bytes>, WrongType> {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        let current = match state.entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => {
+                let data = entry.value.as_string()?;
+                let Some(current) = std::str::from_utf8(data).ok().and_then(|s| s.parse::<f64>().ok()) else {
+                    return Ok(None);
+                };
+                current
+            }
+            _ => 0.0,
+        };
+        let value = current + delta;
+        if !value.is_finite() {
+            return Ok(None);
+        }
+        let data = Bytes::from(value.to_string());
+        state.update_value(key, EntryValue::String(data.clone()), now);
+        state.wake_waiters(key);
+        Ok(Some(data))
+    }
+
+    /// Append `bytes` to the value of `key`, a missing key counts as empty. The TTL is kept.
+    ///
+    /// Return the length of the new value, or `None` if it would be longer than `max_len`, then
+    /// nothing is appended.
+    pub(crate) fn append(&self, key: &str, bytes: &[u8], max_len: usize) -> Result<Option<usize>, WrongType> {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        let data = match state.entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => {
+                let current = entry.value.as_string()?;
+                if current.len().saturating_add(bytes.len()) > max_len {
+                    return Ok(None);
+                }
+                let mut data = BytesMut::with_capacity(current.len() + bytes.len());
+                data.extend_from_slice(current);
+                data.extend_from_slice(bytes);
+                data.freeze()
+            }
+            _ if bytes.len() > max_len => return Ok(None),
+            _ => Bytes::copy_from_slice(bytes),
+        };
+        let len = data.len();
+        state.update_value(key, EntryValue::String(data), now);
+        state.wake_waiters(key);
+        Ok(Some(len))
+    }
+
+    /// Overwrite the value of `key` with `bytes` starting at `offset`, padding with zero bytes if
+    /// the value is shorter than `offset`. A missing key counts as empty. The TTL is kept.
+    ///
+    /// Return the length of the new value.
+    pub(crate) fn setrange(&self, key: &str, offset: usize, bytes: &[u8]) -> Result<usize, WrongType> {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        let live = state.entries.get(key).filter(|entry| !entry.is_expired(now));
+        let current = match &live {
+            Some(entry) => &entry.value.as_string()?[..],
+            None => &[][..],
+        };
+        // Like Redis, an empty write doesn't create the key nor pad the value.
+        if bytes.is_empty() {
+            return Ok(current.len());
+        }
+
+        let mut data = BytesMut::from(current);
+        if data.len() < offset + bytes.len() {
+            data.resize(offset + bytes.len(), 0);
+        }
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        let len = data.len();
+        state.update_value(key, EntryValue::String(data.freeze()), now);
+        state.wake_waiters(key);
+        Ok(len)
+    }
+
+    /// Push `values` one by one at the head of the list at `key` (`front`) or at its tail, creating
+    /// the list if needed. Return the length of the list.
+    pub(crate) fn push(&self, key: &str, values: Vec<Bytes>, front: bool) -> Result<usize, WrongType> {
+        self.list_push_capped(key, values, front, usize::MAX)
+    }
+
+    /// Push like [Db::push], then trim the list down to `maxlen` elements from the far end: the
+    /// oldest elements are dropped, so the list is a log of the last `maxlen` ones. Return the
+    /// length of the list once trimmed.
+    pub(crate) fn list_push_capped(
+        &self,
+        key: &str,
+        values: Vec<Bytes>,
+        front: bool,
+        maxlen: usize,
+    ) -> Result<usize, WrongType> {
+        let mut state = self.shard(key).write();
+        if !state.contains(key, Instant::now()) {
+            state.insert_entry(key.to_string(), EntryValue::List(VecDeque::new()));
+        }
+        let entry = state.entries.get_mut(key).unwrap();
+        entry.touch();
+        let list = entry.value.as_list_mut()?;
+        let size: usize = values.iter().map(Bytes::len).sum();
+        for value in values {
+            if front {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
+        }
+        let mut dropped = 0;
+        while list.len() > maxlen.max(1) {
+            let value = if front { list.pop_back() } else { list.pop_front() };
+            dropped += value.map_or(0, |value| value.len());
+        }
+        let len = list.len();
+        state.used_memory = state.used_memory + size - dropped;
+        state.wake_waiters(key);
+        Ok(len)
+    }
+
+    /// Pop an element from the head of the list at `key` (`front`) or from its tail. The key is
+    /// removed along with its last element, like Redis there is no empty list.
+    pub(crate) fn pop(&self, key: &str, front: bool) -> Result<Option<Bytes>, WrongType> {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        let Some(entry) = state.entries.get_mut(key).filter(|entry| !entry.is_expired(now)) else {
+            return Ok(None);
+        };
+        entry.touch();
+        let list = entry.value.as_list_mut()?;
+        let value = if front { list.pop_front() } else { list.pop_back() };
+        let empty = list.is_empty();
+        state.used_memory -= value.as_ref().map_or(0, Bytes::len);
+        if empty {
+            state.remove_entry(key);
+        }
+        state.wake_waiters(key);
+        Ok(value)
+    }
+
+    /// Get the elements of the list at `key` from `start` to `stop`, both included. Negative
+    /// indexes count from the end, -1 being the last element, and out of range indexes are clamped
+    /// like Redis does. A missing key is an empty list.
+    pub(crate) fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>, WrongType> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        let Some(entry) = state.entries.get(key).filter(|entry| !entry.is_expired(now)) else {
+            return Ok(vec![]);
+        };
+        self.touch(entry);
+        let list = entry.value.as_list()?;
+        let len = list.len() as i64;
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+        // Also empty when `start` is past the end, as `stop` is at most the last index.
+        if start > stop {
+            return Ok(vec![]);
+        }
+        Ok(list.range(start as usize..=stop as usize).cloned().collect())
+    }
+
+    /// Get the length of the list at `key`, 0 if it doesn't exist.
+    pub(crate) fn llen(&self, key: &str) -> Result<usize, WrongType> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map_or(Ok(0), |entry| {
+                self.touch(entry);
+                entry.value.as_list().map(VecDeque::len)
+            })
+    }
+
+    /// Add `members` to the set at `key`, creating the set if needed. Return the number of members
+    /// that were not in the set yet.
+    pub(crate) fn sadd(&self, key: &str, members: Vec<Bytes>) -> Result<usize, WrongType> {
+        let mut state = self.shard(key).write();
+        if !state.contains(key, Instant::now()) {
+            state.insert_entry(key.to_string(), EntryValue::Set(HashSet::new()));
+        }
+        let entry = state.entries.get_mut(key).unwrap();
+        entry.touch();
+        let set = entry.value.as_set_mut()?;
+        let (mut added, mut size) = (0, 0);
+        for member in members {
+            let len = member.len();
+            if set.insert(member) {
+                added += 1;
+                size += len;
+            }
+        }
+        state.used_memory += size;
+        state.wake_waiters(key);
+        Ok(added)
+    }
+
+    /// Remove `members` from the set at `key`. Return the number of members that were in the set.
+    /// The key is removed along with its last member, like Redis there is no empty set.
+    pub(crate) fn srem(&self, key: &str, members: &[Bytes]) -> Result<usize, WrongType> {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        let Some(entry) = state.entries.get_mut(key).filter(|entry| !entry.is_expired(now)) else {
+            return Ok(0);
+        };
+        entry.touch();
+        let set = entry.value.as_set_mut()?;
+        let (mut removed, mut size) = (0, 0);
+        for member in members {
+            if set.remove(member) {
+                removed += 1;
+                size += member.len();
+            }
+        }
+        let empty = set.is_empty();
+        state.used_memory -= size;
+        if empty {
+            state.remove_entry(key);
+        }
+        if removed > 0 {
+            state.wake_waiters(key);
+        }
+        Ok(removed)
+    }
+
+    /// Get the members of the set at `key`, in no particular order. A missing key is an empty set.
+    pub(crate) fn smembers(&self, key: &str) -> Result<Vec<Bytes>, WrongType> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map_or(Ok(vec![]), |entry| {
+                self.touch(entry);
+                entry.value.as_set().map(|set| set.iter().cloned().collect())
+            })
+    }
+
+    /// Check if `member` belongs to the set at `key`.
+    pub(crate) fn sismember(&self, key: &str, member: &[u8]) -> Result<bool, WrongType> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map_or(Ok(false), |entry| {
+                self.touch(entry);
+                entry.value.as_set().map(|set| set.contains(member))
+            })
+    }
+
+    /// Set the time to live of an existing `key`, replacing any previous one. Return whether the key existed.
+    pub(crate) fn expire(&self, key: &str, ttl: Duration) -> bool {
+        let shard = self.shard(key);
+        let mut state = shard.write();
+        if !state.contains(key, Instant::now()) {
+            return false;
+        }
+        let notify = state.set_expiry(key, Some(time_util::deadline(ttl)));
+        drop(state);
+
+        if notify {
+            shard.bg_task_notify.notify_one();
+        }
+        true
+    }
+
+    /// Remove the time to live of `key`. Return whether there was one to remove.
+    pub(crate) fn persist(&self, key: &str) -> bool {
+        let mut state = self.shard(key).write();
+        let now = Instant::now();
+        let volatile = state
+            .entries
+            .get(key)
+            .and_then(|entry| entry.expires_at)
+            .is_some_and(|when| when > now);
+        if !volatile {
+            return false;
+        }
+        // Dropping the key from `expirations` keeps the purge task away from it.
+        state.set_expiry(key, None);
+        true
+    }
+
+    /// Get the remaining time to live of `key`: `None` if the key doesn't exist, `Some(None)` if
+    /// it has no expiration.
+    pub(crate) fn ttl(&self, key: &str) -> Option<Option<Duration>> {
+        let state = self.shard(key).read();
+        let now = Instant::now();
+        let entry = state.entries.get(key).filter(|entry| !entry.is_expired(now))?;
+        Some(entry.expires_at.map(|when| when - now))
+    }
+
+    /// Check if `key` exists. A key past its deadline doesn't, even if it's not purged yet.
+    pub(crate) fn exists(&self, key: &str) -> bool {
+        let state = self.shard(key).read();
+        let Some(entry) = state.entries.get(key) else {
+            return false;
+        };
+        if !entry.is_expired(Instant::now()) {
+            return true;
+        }
+        drop(state);
+        self.remove_expired(key);
+        false
+    }
+
+    /// Remove a key, along with its expiration. Return whether the key existed, a key past its
+    /// deadline doesn't.
+    pub(crate) fn del(&self, key: &str) -> bool {
+        let mut state = self.shard(key).write();
+        // No need to notify the background task, it will just wake up earlier than needed.
+        state
+            .remove_entry(key)
+            .is_some_and(|entry| !entry.is_expired(Instant::now()))
+    }
+
+    /// Approximate memory used by the keys and their values, see [EntryValue::size].
+    pub(crate) fn used_memory(&self) -> usize {
+        self.shared
+            .shards
+            .iter()
+            .map(|shard| shard.used_memory.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Find the key to evict: the least recently used among a few keys sampled from each shard.
+    /// Return it along with its access time, `None` if the database is empty.
+    ///
+    /// The samples are picked at random from [State::keys], whatever the number of keys.
+    fn eviction_candidate(&self) -> Option<(u64, String)> {
+        self.shared
+            .shards
+            .iter()
+            .filter_map(|shard| {
+                let state = shard.read();
+                let len = state.keys.len();
+                // Sampled with replacement, unless there are no more keys than samples.
+                let samples: Box<dyn Iterator<Item = &String>> = if len <= EVICTION_SAMPLES {
+                    Box::new(state.keys.iter())
+                } else {
+                    Box::new((0..EVICTION_SAMPLES).map(|_| &state.keys[random() % len]))
+                };
+                samples
+                    .map(|key| (state.entries[key].last_access.load(Ordering::Relaxed), key))
+                    .min()
+                    .map(|(last_access, key)| (last_access, key.clone()))
+            })
+            .min()
+    }
+
+    /// Remove `key` unless it was accessed since `last_access`. Return whether it was removed.
+    fn evict(&self, key: &str, last_access: u64) -> bool {
+        let mut state = self.shard(key).write();
+        let unused = state
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.last_access.load(Ordering::Relaxed) == last_access);
+        if unused {
+            state.remove_entry(key);
+        }
+        unused
+    }
+
+    /// Wait until `key` is next written, whatever the command and the new value.
+    ///
+    /// The waiter is registered when this is called, not when the future is first polled, so a
+    /// write right after this call is not missed. The building block of blocking commands.
+    // No blocking command uses it yet.
+    #[allow(dead_code)]
+    pub(crate) fn wait_for_key(&self, key: &str) -> impl Future<Output = ()> + Send + 'static {
+        let notify = Arc::new(Notify::new());
+        let mut state = self.shard(key).write();
+        state.waiters.entry(key.to_string()).or_default().push(notify.clone());
+        drop(state);
+        // Unregistered once the future is dropped, e.g. on a timeout, if it was not woken.
+        let waiter = KeyWaiter {
+            db: self.clone(),
+            key: key.to_string(),
+            notify,
+        };
+        // `notify_one` stores a permit, so this resolves even if the key was written in between.
+        async move { waiter.notify.notified().await }
+    }
+
+    /// Subscribe to the feed of processed commands.
+    pub(crate) fn monitor(&self) -> broadcast::Receiver<String> {
+        self.shared.feeds.monitor.subscribe()
+    }
+
+    /// Check if any MONITOR client is listening, so the feed line is only built when needed.
+    pub(crate) fn is_monitored(&self) -> bool {
+        self.shared.feeds.monitor.receiver_count() > 0
+    }
+
+    /// Send a line to every MONITOR client.
+    pub(crate) fn publish_monitor(&self, line: String) {
+        // An error only means that nobody is listening anymore.
+        let _ = self.shared.feeds.monitor.send(line);
+    }
+
+    /// Subscribe to the pub/sub `channel`, creating it if needed.
+    pub(crate) fn subscribe(&self, channel: String) -> broadcast::Receiver<Bytes> {
+        let mut channels = self.shared.feeds.channels.lock().unwrap();
+        channels
+            .entry(channel)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Send `message` to the subscribers of `channel`, and return how many they are.
+    pub(crate) fn publish(&self, channel: &str, message: Bytes) -> usize {
+        let mut channels = self.shared.feeds.channels.lock().unwrap();
+        let Some(sender) = channels.get(channel) else {
+            return 0;
+        };
+        match sender.send(message) {
+            Ok(receivers) => receivers,
+            Err(_) => {
+                // The subscribers are all gone.
+                channels.remove(channel);
+                0
+            }
+        }
+    }
+}
+
+/// Position of `key` in the order of [Db::scan].
+fn scan_position(key: &str) -> u64 {
+    // `DefaultHasher::new` has fixed keys, a key keeps its position between calls.
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Check if `data` is an integer in its canonical form, which Redis stores as an `int`.
+fn is_int(data: &[u8]) -> bool {
+    let value = std::str::from_utf8(data).ok().and_then(|s| s.parse::<i64>().ok());
+    value.is_some_and(|value| value.to_string().as_bytes() == data)
+}
+
+/// Approximate memory used by the databases of a server, see [Db::used_memory].
+pub(crate) fn used_memory(dbs: &[Db]) -> usize {
+    dbs.iter().map(Db::used_memory).sum()
+}
+
+/// Evict keys from `dbs` until they use at most `maxmemory` bytes, the least recently used first
+/// whatever their database. This is the `allkeys-lru` policy of Redis, with the same approximation,
+/// see [Db::eviction_candidate].
+pub(crate) fn evict_lru(dbs: &[Db], maxmemory: usize) {
+    while used_memory(dbs) > maxmemory {
+        let candidates = dbs
+            .iter()
+            .filter_map(|db| db.eviction_candidate().map(|(last_access, key)| (last_access, key, db)));
+        let Some((last_access, key, db)) = candidates.min_by_key(|(last_access, ..)| *last_access) else {
+            break;
+        };
+        // A key accessed in the meantime is kept, the next round samples again.
+        db.evict(&key, last_access);
+    }
+}
+
+#[cfg(test)]
+mod test_db {
+    use crate::config::{Config, Params};
+    use crate::db::Shard;
+    use crate::db::{evict_lru, used_memory, Db, DbGuard, EncodingLimits, KeyType, Shared, State, WrongType, SHARDS};
+    use bytes::Bytes;
+    use std::collections::HashSet;
+    use std::sync::{Arc, RwLockReadGuard};
+    use std::time::Duration;
+
+    /// A `Db` without the background tasks, so expired keys are never purged.
+    fn db_without_purge() -> Db {
+        Db {
+            shared: Arc::new(Shared::new(SHARDS, Arc::default())),
+            no_touch: false,
+        }
+    }
+
+    impl Db {
+        /// Read the shard holding `key`.
+        fn state(&self, key: &str) -> RwLockReadGuard<'_, State> {
+            self.shard(key).read()
+        }
+
+        fn check_invariants(&self) {
+            for state in self.lock_all(Shard::read) {
+                state.check_invariants();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_get() {
+        let db = Db::new();
+        db.set("key1".to_string(), Bytes::from("value1"), None);
+        db.set("key2".to_string(), Bytes::from("value2"), Some(Duration::from_secs(1)));
+
+        assert_eq!(db.get("key1"), Ok(Some(Bytes::from("value1"))));
+        assert_eq!(db.get("key2"), Ok(Some(Bytes::from("value2"))));
+    }
+
+    #[tokio::test]
+    async fn test_expire() {
+        let db = Db::new();
+        db.set(
+            "key1".to_string(),
+            Bytes::from("value1"),
+            Some(Duration::from_millis(100)),
+        );
+        db.set(
+            "key2".to_string(),
+            Bytes::from("value2"),
+            Some(Duration::from_millis(200)),
+        );
+
+        assert_eq!(db.get("key1"), Ok(Some(Bytes::from("value1"))));
+        assert_eq!(db.get("key2"), Ok(Some(Bytes::from("value2"))));
+
+        tokio::time::sleep(Duration::from_millis(110)).await;
+
+        assert_eq!(db.get("key1"), Ok(None));
+        assert_eq!(db.get("key2"), Ok(Some(Bytes::from("value2"))));
+
+        tokio::time::sleep(Duration::from_millis(110)).await;
+
+        assert_eq!(db.get("key2"), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_set_clears_ttl() {
+        let db = Db::new();
+        db.set("key1".to_string(), Bytes::from("value1"), Some(Duration::from_secs(10)));
+        db.set("key1".to_string(), Bytes::from("value2"), None);
+
+        let state = db.state("key1");
+        assert_eq!(state.entries["key1"].expires_at, None);
+        assert!(state.expirations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_set_keep_ttl() {
+        let db = Db::new();
+        db.set("key1".to_string(), Bytes::from("value1"), Some(Duration::from_secs(10)));
+        let expires_at = db.state("key1").entries["key1"].expires_at;
+        db.set_keep_ttl("key1".to_string(), Bytes::from("value2"));
+        // A missing key is simply set, without TTL.
+        db.set_keep_ttl("key2".to_string(), Bytes::from("value2"));
+
+        assert_eq!(db.get("key1"), Ok(Some(Bytes::from("value2"))));
+        assert!(expires_at.is_some());
+        assert_eq!(db.state("key1").entries["key1"].expires_at, expires_at);
+        assert_eq!(db.state("key2").entries["key2"].expires_at, None);
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_exists() {
+        let db = db_without_purge();
+        db.set("key1".to_string(), Bytes::from("value1"), None);
+        db.set(
+            "key2".to_string(),
+            Bytes::from("value2"),
+            Some(Duration::from_millis(1)),
+        );
+        assert!(db.exists("key1"));
+        assert!(!db.exists("missing"));
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        // Logically expired, but still stored.
+        assert!(db.state("key2").entries.contains_key("key2"));
+        assert!(!db.exists("key2"));
+        assert!(!db.state("key2").entries.contains_key("key2"));
+    }
+
+    #[tokio::test]
+    async fn test_incr_by() {
+        let db = db_without_purge();
+        assert_eq!(db.incr_by("counter", 1), Ok(Some(1)));
+        assert_eq!(db.incr_by("counter", 1), Ok(Some(2)));
+        assert_eq!(db.incr_by("counter", -5), Ok(Some(-3)));
+        assert_eq!(db.get("counter"), Ok(Some(Bytes::from("-3"))));
+
+        db.set("text".to_string(), Bytes::from("abc"), None);
+        assert_eq!(db.incr_by("text", 1), Ok(None));
+        assert_eq!(db.get("text"), Ok(Some(Bytes::from("abc"))));
+
+        db.set("max".to_string(), Bytes::from(i64::MAX.to_string()), None);
+        assert_eq!(db.incr_by("max", 1), Ok(None));
+        assert_eq!(db.get("max"), Ok(Some(Bytes::from(i64::MAX.to_string()))));
+
+        // The TTL survives the update, but an expired value starts over from 0.
+        db.set("ttl".to_string(), Bytes::from("10"), Some(Duration::from_secs(100)));
+        assert_eq!(db.incr_by("ttl", 1), Ok(Some(11)));
+        assert!(db.state("ttl").entries["ttl"].expires_at.is_some());
+        db.set("gone".to_string(), Bytes::from("10"), Some(Duration::from_millis(1)));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.incr_by("gone", 1), Ok(Some(1)));
+        assert!(db.state("gone").entries["gone"].expires_at.is_none());
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_incr_by_concurrent() {
+        let db = Db::new();
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        db.incr_by("counter", 1).unwrap().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(db.get("counter"), Ok(Some(Bytes::from("800"))));
+    }
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let db = db_without_purge();
+        assert_eq!(db.ttl("missing"), None);
+        db.set("persistent".to_string(), Bytes::from("value"), None);
+        assert_eq!(db.ttl("persistent"), Some(None));
+
+        db.set(
+            "volatile".to_string(),
+            Bytes::from("value"),
+            Some(Duration::from_secs(100)),
+        );
+        let ttl = db.ttl("volatile").unwrap().unwrap();
+        assert!(ttl <= Duration::from_secs(100) && ttl > Duration::from_secs(99));
+
+        // Near expiry the key is still there, then it is gone even though it isn't purged yet.
+        db.set(
+            "short".to_string(),
+            Bytes::from("value"),
+            Some(Duration::from_millis(20)),
+        );
+        assert!(db.ttl("short").unwrap().unwrap() <= Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        assert_eq!(db.ttl("short"), None);
+    }
+
+    #[tokio::test]
+    async fn test_expire_existing_key() {
+        let db = Db::new();
+        assert!(!db.expire("missing", Duration::from_secs(1)));
+
+        db.set("key".to_string(), Bytes::from("value"), None);
+        assert!(db.expire("key", Duration::from_secs(100)));
+        // Re-expiring replaces the old deadline, in the entry and in the expirations.
+        assert!(db.expire("key", Duration::from_millis(20)));
+        assert!(db.ttl("key").unwrap().unwrap() <= Duration::from_millis(20));
+        assert_eq!(db.state("key").expirations.len(), 1);
+        db.check_invariants();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(db.get("key"), Ok(None));
+        assert!(!db.expire("key", Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_persist() {
+        let db = Db::new();
+        assert!(!db.persist("missing"));
+        db.set("persistent".to_string(), Bytes::from("value"), None);
+        assert!(!db.persist("persistent"));
+
+        db.set("key".to_string(), Bytes::from("value"), Some(Duration::from_millis(20)));
+        assert!(db.persist("key"));
+        assert_eq!(db.ttl("key"), Some(None));
+        db.check_invariants();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(db.get("key"), Ok(Some(Bytes::from("value"))));
+    }
+
+    #[tokio::test]
+    async fn test_set_conditional() {
+        let db = db_without_purge();
+        let set = |key: &str, expire, nx, xx| {
+            db.set_conditional(key.to_string(), Bytes::from("new"), Some(expire), nx, xx, false)
+                .unwrap()
+                .0
+        };
+
+        // NX only writes absent keys, XX only existing ones.
+        assert!(!set("key", None, false, true));
+        assert_eq!(db.get("key"), Ok(None));
+        assert!(set("key", None, true, false));
+        assert!(!set("key", None, true, false));
+        assert!(set("key", None, false, true));
+
+        // An expired key is absent, even if it's not purged yet.
+        assert!(set("short", Some(Duration::from_millis(1)), true, false));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!set("short", None, false, true));
+        assert!(set("short", Some(Duration::from_secs(100)), true, false));
+        assert!(db.ttl("short").unwrap().is_some());
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_set_nx() {
+        let db = db_without_purge();
+        assert!(db.set_nx("key".to_string(), Bytes::from("first")));
+        assert!(!db.set_nx("key".to_string(), Bytes::from("second")));
+        assert_eq!(db.get("key"), Ok(Some(Bytes::from("first"))));
+        // A key of another type exists too.
+        db.push("list", vec![Bytes::from("a")], false).unwrap();
+        assert!(!db.set_nx("list".to_string(), Bytes::from("value")));
+        assert_eq!(db.llen("list"), Ok(1));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_getset() {
+        let db = db_without_purge();
+        assert_eq!(db.getset("key".to_string(), Bytes::from("first")), Ok(None));
+        db.expire("key", Duration::from_secs(100));
+        assert_eq!(
+            db.getset("key".to_string(), Bytes::from("second")),
+            Ok(Some(Bytes::from("first")))
+        );
+        assert_eq!(db.get("key"), Ok(Some(Bytes::from("second"))));
+        assert_eq!(db.ttl("key"), Some(None));
+
+        db.push("list", vec![Bytes::from("a")], false).unwrap();
+        assert_eq!(db.getset("list".to_string(), Bytes::from("value")), Err(WrongType));
+        assert_eq!(db.llen("list"), Ok(1));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_set_returns_previous() {
+        let db = Db::new();
+        assert_eq!(db.set("key".to_string(), Bytes::from("one"), None), None);
+        assert_eq!(
+            db.set("key".to_string(), Bytes::from("two"), None),
+            Some(Bytes::from("one"))
+        );
+        assert_eq!(
+            db.set_keep_ttl("key".to_string(), Bytes::from("three")),
+            Some(Bytes::from("two"))
+        );
+
+        // The previous value is returned even if NX prevents the write.
+        let (set, prev) = db
+            .set_conditional("key".to_string(), Bytes::from("four"), Some(None), true, false, false)
+            .unwrap();
+        assert!(!set);
+        assert_eq!(prev, Some(Bytes::from("three")));
+        assert_eq!(db.get("key"), Ok(Some(Bytes::from("three"))));
+    }
+
+    #[tokio::test]
+    async fn test_mget() {
+        let db = db_without_purge();
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        assert_eq!(db.mget(&keys(&["a", "b"])), vec![None, None]);
+
+        db.set("a".to_string(), Bytes::from("1"), None);
+        db.set("b".to_string(), Bytes::from("2"), None);
+        db.set("expired".to_string(), Bytes::from("3"), Some(Duration::from_millis(1)));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(
+            db.mget(&keys(&["b", "missing", "expired", "a"])),
+            vec![Some(Bytes::from("2")), None, None, Some(Bytes::from("1"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mset() {
+        let db = Db::new();
+        db.set("a".to_string(), Bytes::from("old"), Some(Duration::from_secs(100)));
+        db.mset(vec![
+            ("a".to_string(), Bytes::from("1")),
+            ("b".to_string(), Bytes::from("2")),
+            ("b".to_string(), Bytes::from("3")),
+        ]);
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+        assert_eq!(db.ttl("a"), Some(None));
+        // The last value of a repeated key wins.
+        assert_eq!(db.get("b"), Ok(Some(Bytes::from("3"))));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_append() {
+        let db = db_without_purge();
+        assert_eq!(db.append("key", b"Hello", 100), Ok(Some(5)));
+        assert_eq!(db.append("key", b" World", 100), Ok(Some(11)));
+        assert_eq!(db.get("key"), Ok(Some(Bytes::from("Hello World"))));
+
+        // Past the limit, nothing is appended.
+        assert_eq!(db.append("key", b"!", 11), Ok(None));
+        assert_eq!(db.append("key", b"!", 12), Ok(Some(12)));
+        assert_eq!(db.append("new", b"abc", 2), Ok(None));
+        assert!(!db.exists("new"));
+
+        db.set("volatile".to_string(), Bytes::from("a"), Some(Duration::from_secs(100)));
+        assert_eq!(db.append("volatile", b"b", 100), Ok(Some(2)));
+        assert!(db.ttl("volatile").unwrap().is_some());
+
+        // An expired value is not appended to.
+        db.set(
+            "expired".to_string(),
+            Bytes::from("old"),
+            Some(Duration::from_millis(1)),
+        );
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.append("expired", b"new", 100), Ok(Some(3)));
+        assert_eq!(db.ttl("expired"), Some(None));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_strlen() {
+        let db = Db::new();
+        assert_eq!(db.strlen("missing"), Ok(0));
+        db.set("ascii".to_string(), Bytes::from("hello"), None);
+        assert_eq!(db.strlen("ascii"), Ok(5));
+        // Bytes, not characters.
+        db.set("utf8".to_string(), Bytes::from("héllo ✓"), None);
+        assert_eq!(db.strlen("utf8"), Ok(10));
+    }
+
+    #[tokio::test]
+    async fn test_getdel() {
+        let db = db_without_purge();
+        assert_eq!(db.getdel("missing"), Ok(None));
+
+        db.set("key".to_string(), Bytes::from("value"), None);
+        assert_eq!(db.getdel("key"), Ok(Some(Bytes::from("value"))));
+        assert!(!db.exists("key"));
+
+        db.set(
+            "volatile".to_string(),
+            Bytes::from("value"),
+            Some(Duration::from_secs(100)),
+        );
+        assert_eq!(db.getdel("volatile"), Ok(Some(Bytes::from("value"))));
+        assert!(db.state("volatile").expirations.is_empty());
+
+        db.set(
+            "expired".to_string(),
+            Bytes::from("value"),
+            Some(Duration::from_millis(1)),
+        );
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.getdel("expired"), Ok(None));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_getex() {
+        let db = Db::new();
+        assert_eq!(db.getex("missing", Some(Some(Duration::from_secs(1)))), Ok(None));
+        assert!(!db.exists("missing"));
+
+        db.set("key".to_string(), Bytes::from("value"), Some(Duration::from_secs(100)));
+        assert_eq!(db.getex("key", None), Ok(Some(Bytes::from("value"))));
+        assert!(db.ttl("key").unwrap().unwrap() > Duration::from_secs(99));
+
+        assert_eq!(
+            db.getex("key", Some(Some(Duration::from_secs(10)))),
+            Ok(Some(Bytes::from("value")))
+        );
+        assert!(db.ttl("key").unwrap().unwrap() <= Duration::from_secs(10));
+
+        assert_eq!(db.getex("key", Some(None)), Ok(Some(Bytes::from("value"))));
+        assert_eq!(db.ttl("key"), Some(None));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_key_type() {
+        let db = Db::new();
+        assert_eq!(db.key_type("missing"), None);
+        db.set("key".to_string(), Bytes::from("value"), None);
+        assert_eq!(db.key_type("key"), Some(KeyType::String));
+    }
+
+    #[tokio::test]
+    async fn test_len() {
+        let db = db_without_purge();
+        assert_eq!(db.len(), 0);
+        db.set("a".to_string(), Bytes::from("1"), None);
+        db.set("b".to_string(), Bytes::from("2"), Some(Duration::from_secs(100)));
+        db.set("c".to_string(), Bytes::from("3"), Some(Duration::from_millis(1)));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.len(), 2);
+        db.del("a");
+        assert_eq!(db.len(), 1);
+    }
 
-/// DB state entry.
-#[derive(Debug, Default)]
-struct State {
-    entries: HashMap<String, Entry>,
-    /// Tracks key TTLs.
-    ///
-    /// BTreeSet is a sorted set, so we can get the first element which is the earliest expiration time.
-    /// While highly unlikely, it is possible for two keys to have the same expiration time. So we also store the key name.
-    expirations: BTreeSet<(Instant, String)>,
-}
+    #[tokio::test]
+    async fn test_flush() {
+        let db = Db::new();
+        db.set("a".to_string(), Bytes::from("1"), None);
+        db.set("b".to_string(), Bytes::from("2"), Some(Duration::from_millis(20)));
+        db.flush();
+        assert_eq!(db.len(), 0);
+        assert_eq!(db.get("a"), Ok(None));
+        db.check_invariants();
 
-/// Entry in the key-value store.
-#[derive(Debug)]
-struct Entry {
-    /// Stored data
-    data: Bytes,
-    /// Instant at which the entry expires and should be removed from the database.
-    /// None means it will never expire.
-    expires_at: Option<Instant>,
-}
+        // The purge task keeps working afterwards.
+        db.set("c".to_string(), Bytes::from("3"), Some(Duration::from_millis(1)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            db.lock_all(Shard::read)
+                .iter()
+                .map(|state| state.entries.len())
+                .sum::<usize>(),
+            0
+        );
+    }
 
-impl DbGuard {
-    pub(crate) fn new() -> Self {
-        DbGuard { db: Db::new() }
+    #[tokio::test]
+    async fn test_keys() {
+        let db = db_without_purge();
+        db.mset(vec![
+            ("user:1".to_string(), Bytes::from("a")),
+            ("user:2".to_string(), Bytes::from("b")),
+            ("session".to_string(), Bytes::from("c")),
+        ]);
+        db.set("user:3".to_string(), Bytes::from("d"), Some(Duration::from_millis(1)));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let keys = |pattern: &str| {
+            let mut keys = db.keys(pattern.as_bytes());
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys("*"), vec!["session", "user:1", "user:2"]);
+        assert_eq!(keys("user:*"), vec!["user:1", "user:2"]);
+        assert_eq!(keys("user:?"), vec!["user:1", "user:2"]);
+        assert_eq!(keys("user:[2-9]"), vec!["user:2"]);
+        assert!(keys("nothing*").is_empty());
+
+        assert_eq!(db.keys_limited(b"user:*", 1).len(), 1);
+        assert_eq!(db.keys_limited(b"*", 2).len(), 2);
+        assert_eq!(db.keys_limited(b"*", 10).len(), 3);
+        assert!(db.keys_limited(b"*", 0).is_empty());
     }
 
-    /// Get a reference to the `Db` instance.
-    pub(crate) fn db(&self) -> Db {
-        self.db.clone()
+    #[tokio::test]
+    async fn test_incr_by_float() {
+        let db = Db::new();
+        assert_eq!(db.incr_by_float("key", 10.5), Ok(Some(Bytes::from("10.5"))));
+        assert_eq!(db.incr_by_float("key", 0.1), Ok(Some(Bytes::from("10.6"))));
+        // An integral result has no decimal part.
+        assert_eq!(db.incr_by_float("key", -5.6), Ok(Some(Bytes::from("5"))));
+        assert_eq!(db.incr_by("key", 1), Ok(Some(6)));
+
+        db.set("text".to_string(), Bytes::from("abc"), None);
+        assert_eq!(db.incr_by_float("text", 1.0), Ok(None));
+        db.set("huge".to_string(), Bytes::from(f64::MAX.to_string()), None);
+        assert_eq!(db.incr_by_float("huge", f64::MAX), Ok(None));
     }
-}
 
-impl Db {
-    pub(crate) fn new() -> Self {
-        let shared = Arc::new(Shared {
-            state: Mutex::new(State {
-                entries: HashMap::new(),
-                expirations: BTreeSet::new(),
-            }),
-            bg_task_notify: Notify::new(),
-        });
-        // Create a background task to purge expired keys.
-        tokio::spawn(purge_expired_keys(shared.clone()));
-        Db { shared }
-    }
-
-    pub(crate) fn set(&self, key: String, value: Bytes, expire: Option<Duration>) {
-        let mut state = self.shared.state.lock().unwrap();
-        // In addition to reduce the bg task's work, we need to judge this key is the next expiration time.
-        let mut notify = false;
-        let expires_at = expire.map(|d| {
-            let when = Instant::now() + d;
-            // If the new key is the next expiration time, notify the bg task.
-            // First key or earlier than the current next expiration time.
-            notify = state.next_expiration().map(|t| t > when).unwrap_or(true);
-            when
-        });
-        // Insert the entry into the `HashMap`.
-        let prev = state.entries.insert(
-            key.clone(),
-            Entry {
-                data: value,
-                expires_at,
-            },
+    #[tokio::test]
+    async fn test_setrange() {
+        let db = Db::new();
+        assert_eq!(db.setrange("missing", 3, b""), Ok(0));
+        assert!(!db.exists("missing"));
+
+        // Zero padding growth.
+        assert_eq!(db.setrange("key", 2, b"ab"), Ok(4));
+        assert_eq!(db.get("key"), Ok(Some(Bytes::from(&b"\0\0ab"[..]))));
+
+        db.set(
+            "volatile".to_string(),
+            Bytes::from("Hello World"),
+            Some(Duration::from_secs(100)),
         );
+        assert_eq!(db.setrange("volatile", 6, b"Redis"), Ok(11));
+        assert_eq!(db.get("volatile"), Ok(Some(Bytes::from("Hello Redis"))));
+        assert!(db.ttl("volatile").unwrap().is_some());
+        assert_eq!(db.setrange("volatile", 10, b"!!"), Ok(12));
+        assert_eq!(db.get("volatile"), Ok(Some(Bytes::from("Hello Redi!!"))));
+    }
+
+    #[tokio::test]
+    async fn test_copy() {
+        let db = Db::new();
+        assert!(!db.copy("missing", "dst", false));
+        assert!(!db.exists("dst"));
+
+        db.set("src".to_string(), Bytes::from("value"), Some(Duration::from_secs(100)));
+        assert!(db.copy("src", "dst", false));
+        assert_eq!(db.get("dst"), Ok(Some(Bytes::from("value"))));
+        // Same deadline as the source.
+        let expires_at = db.state("src").entries["src"].expires_at;
+        assert_eq!(db.state("dst").entries["dst"].expires_at, expires_at);
+        db.check_invariants();
+
+        db.set("other".to_string(), Bytes::from("other"), None);
+        assert!(!db.copy("other", "dst", false));
+        assert_eq!(db.get("dst"), Ok(Some(Bytes::from("value"))));
+        assert!(db.copy("other", "dst", true));
+        assert_eq!(db.get("dst"), Ok(Some(Bytes::from("other"))));
+        assert_eq!(db.ttl("dst"), Some(None));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_lazy_expiration() {
+        let db = db_without_purge();
+        db.set("get".to_string(), Bytes::from("value"), Some(Duration::from_millis(10)));
+        db.set("del".to_string(), Bytes::from("value"), Some(Duration::from_millis(10)));
+        assert_eq!(db.get("get"), Ok(Some(Bytes::from("value"))));
+
+        // Right past the deadline, with no purge task to remove the keys.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(db.get("get"), Ok(None));
+        assert!(!db.state("get").entries.contains_key("get"));
+        assert!(!db.del("del"));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_del() {
+        let db = Db::new();
+        db.set("key1".to_string(), Bytes::from("value1"), Some(Duration::from_secs(10)));
+        assert!(db.del("key1"));
+        assert!(!db.del("key1"));
+        assert_eq!(db.get("key1"), Ok(None));
+        assert!(db.state("key1").expirations.is_empty());
+    }
 
-        // Previous entry existed, remove it from the expiration queue.
-        if let Some(prev) = prev {
-            if let Some(expires_at) = prev.expires_at {
-                state.expirations.remove(&(expires_at, key.clone()));
+    /// Apply a random mix of writes and check that `entries` and `expirations` never desync.
+    #[tokio::test]
+    async fn test_expiration_index_invariant() {
+        let db = Db::new();
+        // A small LCG is enough to get a reproducible mix of operations.
+        let mut seed: u64 = 42;
+        let mut next = |n: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        for _ in 0..2000 {
+            let key = format!("key{}", next(20));
+            match next(4) {
+                0 => {
+                    db.set(key, Bytes::from("value"), None);
+                }
+                1 => {
+                    let ttl = Duration::from_millis(next(50));
+                    db.set(key, Bytes::from("value"), Some(ttl));
+                }
+                2 => {
+                    db.set_keep_ttl(key, Bytes::from("value"));
+                }
+                _ => {
+                    db.del(&key);
+                }
+            }
+            db.check_invariants();
+            if next(100) == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
             }
         }
+    }
 
-        if let Some(expires_at) = expires_at {
-            state.expirations.insert((expires_at, key));
-        }
+    #[tokio::test]
+    async fn test_key_counts() {
+        let db = db_without_purge();
+        db.set("a".to_string(), Bytes::from("value"), None);
+        db.set("b".to_string(), Bytes::from("value"), Some(Duration::from_secs(10)));
+        db.set("c".to_string(), Bytes::from("value"), Some(Duration::from_millis(1)));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.key_counts(), (2, 1));
+    }
 
-        // Notify the background task to check the expiration time.
-        // Before notifying, we need to drop the lock to avoid deadlock.
-        drop(state);
+    #[tokio::test]
+    async fn test_wait_for_key() {
+        let db = Db::new();
+        let waiting = tokio::spawn(db.wait_for_key("key"));
+        // Writes to other keys don't wake the waiter.
+        db.set("other".to_string(), Bytes::from("value"), None);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
 
-        if notify {
-            // Only notify the background task if it needs
-            self.shared.bg_task_notify.notify_one();
-        }
+        db.set("key".to_string(), Bytes::from("value"), None);
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("the waiter should be woken")
+            .unwrap();
+
+        // Written before the future is polled.
+        let waiting = db.wait_for_key("key");
+        db.append("key", b"more", usize::MAX).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap();
+
+        // Cancelled waiters are removed.
+        let kept = db.wait_for_key("key");
+        drop(db.wait_for_key("key"));
+        assert_eq!(db.state("key").waiters["key"].len(), 1);
+        let timeout = tokio::time::timeout(Duration::from_millis(10), kept).await;
+        assert!(timeout.is_err());
+        assert!(db.state("key").waiters.is_empty());
     }
 
-    pub(crate) fn get(&self, key: &str) -> Option<Bytes> {
-        let state = self.shared.state.lock().unwrap();
-        let entry = state.entries.get(key)?;
-        Some(entry.data.clone())
+    #[tokio::test]
+    async fn test_databases() {
+        let guard = DbGuard::new(2);
+        let dbs = guard.dbs();
+        assert_eq!(dbs.len(), 2);
+        dbs[1].set("key".to_string(), Bytes::from("value"), None);
+        assert_eq!(dbs[0].get("key"), Ok(None));
+        assert_eq!(dbs[1].get("key"), Ok(Some(Bytes::from("value"))));
+
+        // A single feed for the whole server.
+        let mut feed = dbs[0].monitor();
+        dbs[1].publish_monitor("line".to_string());
+        assert_eq!(feed.recv().await.unwrap(), "line");
+        let mut channel = dbs[0].subscribe("channel".to_string());
+        assert_eq!(dbs[1].publish("channel", Bytes::from("message")), 1);
+        assert_eq!(channel.recv().await.unwrap(), Bytes::from("message"));
     }
-}
 
-#[cfg(test)]
-mod test_db {
-    use crate::db::Db;
-    use bytes::Bytes;
-    use std::time::Duration;
+    #[tokio::test]
+    async fn test_publish() {
+        let db = Db::new();
+        assert_eq!(db.publish("channel", Bytes::from("lost")), 0);
+
+        let mut first = db.subscribe("channel".to_string());
+        let mut second = db.subscribe("channel".to_string());
+        assert_eq!(db.publish("channel", Bytes::from("message")), 2);
+        assert_eq!(first.recv().await.unwrap(), Bytes::from("message"));
+        assert_eq!(second.recv().await.unwrap(), Bytes::from("message"));
+
+        // The channel is removed once nobody listens.
+        drop((first, second));
+        assert_eq!(db.publish("channel", Bytes::from("lost")), 0);
+        assert!(db.shared.feeds.channels.lock().unwrap().is_empty());
+    }
 
     #[tokio::test]
-    async fn test_set_get() {
+    async fn test_push_pop() {
         let db = Db::new();
-        db.set("key1".to_string(), Bytes::from("value1"), None);
-        db.set("key2".to_string(), Bytes::from("value2"), Some(Duration::from_secs(1)));
+        let values = |values: &[&str]| values.iter().map(|value| Bytes::from(value.to_string())).collect();
+        assert_eq!(db.push("list", values(&["b", "a"]), true), Ok(2));
+        assert_eq!(db.push("list", values(&["c", "d"]), false), Ok(4));
+        assert_eq!(db.key_type("list"), Some(KeyType::List));
 
-        assert_eq!(db.get("key1").unwrap(), Bytes::from("value1"));
-        assert_eq!(db.get("key2").unwrap(), Bytes::from("value2"));
+        // a b c d
+        assert_eq!(db.pop("list", true), Ok(Some(Bytes::from("a"))));
+        assert_eq!(db.pop("list", false), Ok(Some(Bytes::from("d"))));
+        assert_eq!(db.pop("list", false), Ok(Some(Bytes::from("c"))));
+        assert_eq!(db.pop("list", true), Ok(Some(Bytes::from("b"))));
+        // The key is gone with its last element.
+        assert!(!db.exists("list"));
+        assert_eq!(db.pop("list", true), Ok(None));
     }
 
     #[tokio::test]
-    async fn test_expire() {
+    async fn test_list_push_capped() {
         let db = Db::new();
-        db.set(
-            "key1".to_string(),
-            Bytes::from("value1"),
-            Some(Duration::from_millis(100)),
-        );
-        db.set(
-            "key2".to_string(),
-            Bytes::from("value2"),
-            Some(Duration::from_millis(200)),
+        let values = |values: &[&str]| values.iter().map(|value| Bytes::from(value.to_string())).collect();
+        assert_eq!(db.list_push_capped("log", values(&["a", "b"]), false, 3), Ok(2));
+        // The oldest elements are dropped from the head.
+        assert_eq!(db.list_push_capped("log", values(&["c", "d", "e"]), false, 3), Ok(3));
+        assert_eq!(db.lrange("log", 0, -1), Ok(values(&["c", "d", "e"])));
+        // Pushed at the head, they are dropped from the tail.
+        assert_eq!(db.list_push_capped("log", values(&["b"]), true, 2), Ok(2));
+        assert_eq!(db.lrange("log", 0, -1), Ok(values(&["b", "c"])));
+        db.check_invariants();
+    }
+
+    #[tokio::test]
+    async fn test_lrange() {
+        let db = Db::new();
+        let values = ["a", "b", "c", "d"].map(Bytes::from).to_vec();
+        db.push("list", values.clone(), false).unwrap();
+        let range = |start, stop| db.lrange("list", start, stop).unwrap();
+
+        assert_eq!(range(0, -1), values);
+        assert_eq!(range(1, 2), values[1..=2]);
+        assert_eq!(range(-3, -2), values[1..=2]);
+        // Out of range indexes are clamped.
+        assert_eq!(range(-100, 100), values);
+        assert_eq!(range(2, 100), values[2..]);
+        assert_eq!(range(3, 1), Vec::<Bytes>::new());
+        assert_eq!(range(4, 10), Vec::<Bytes>::new());
+        assert_eq!(range(0, -100), Vec::<Bytes>::new());
+
+        assert_eq!(db.lrange("missing", 0, -1), Ok(vec![]));
+        assert_eq!(db.llen("list"), Ok(4));
+        assert_eq!(db.llen("missing"), Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_set_type() {
+        let db = Db::new();
+        let members = |members: &[&[u8]]| members.iter().map(|member| Bytes::copy_from_slice(member)).collect();
+        // Duplicates are only counted once, binary members included.
+        assert_eq!(db.sadd("set", members(&[b"a", b"b", b"a", b"\0\xff"])), Ok(3));
+        assert_eq!(db.sadd("set", members(&[b"b", b"c"])), Ok(1));
+        assert_eq!(db.key_type("set"), Some(KeyType::Set));
+
+        let mut all = db.smembers("set").unwrap();
+        all.sort();
+        assert_eq!(all, members(&[b"\0\xff", b"a", b"b", b"c"]) as Vec<Bytes>);
+        assert_eq!(db.sismember("set", b"\0\xff"), Ok(true));
+        assert_eq!(db.sismember("set", b"\0"), Ok(false));
+        assert_eq!(db.sismember("missing", b"a"), Ok(false));
+
+        assert_eq!(db.srem("set", &members(&[b"a", b"missing"])), Ok(1));
+        assert_eq!(db.sismember("set", b"a"), Ok(false));
+        // The key is gone with its last member.
+        assert_eq!(db.srem("set", &members(&[b"b", b"c", b"\0\xff"])), Ok(3));
+        assert!(!db.exists("set"));
+        assert_eq!(db.smembers("set"), Ok(vec![]));
+        assert_eq!(db.srem("set", &members(&[b"a"])), Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() {
+        let db = Db::new();
+        db.set("string".to_string(), Bytes::from("1"), None);
+        db.push("list", vec![Bytes::from("1")], true).unwrap();
+
+        assert_eq!(db.push("string", vec![Bytes::from("a")], true), Err(WrongType));
+        assert_eq!(db.pop("string", false), Err(WrongType));
+        assert_eq!(db.lrange("string", 0, -1), Err(WrongType));
+        assert_eq!(db.llen("string"), Err(WrongType));
+        assert_eq!(db.sadd("string", vec![Bytes::from("a")]), Err(WrongType));
+        assert_eq!(db.srem("list", &[Bytes::from("a")]), Err(WrongType));
+        assert_eq!(db.smembers("list"), Err(WrongType));
+        assert_eq!(db.sismember("string", b"a"), Err(WrongType));
+        assert_eq!(db.get("list"), Err(WrongType));
+        assert_eq!(db.incr_by("list", 1), Err(WrongType));
+        assert_eq!(db.append("list", b"a", usize::MAX), Err(WrongType));
+        assert_eq!(db.getdel("list"), Err(WrongType));
+        assert_eq!(db.mget(&["list".to_string()]), vec![None]);
+        assert_eq!(db.key_type("list"), Some(KeyType::List));
+        assert_eq!(
+            WrongType.to_string(),
+            "WRONGTYPE Operation against a key holding the wrong kind of value"
         );
 
-        assert_eq!(db.get("key1").unwrap(), Bytes::from("value1"));
-        assert_eq!(db.get("key2").unwrap(), Bytes::from("value2"));
+        // SET overwrites any type, but with GET it doesn't touch another type.
+        let set = |get| db.set_conditional("list".to_string(), Bytes::from("2"), Some(None), false, false, get);
+        assert_eq!(set(true), Err(WrongType));
+        assert_eq!(db.key_type("list"), Some(KeyType::List));
+        assert_eq!(set(false), Ok((true, None)));
+        assert_eq!(db.get("list"), Ok(Some(Bytes::from("2"))));
+    }
 
-        tokio::time::sleep(Duration::from_millis(110)).await;
+    #[tokio::test]
+    async fn test_scan() {
+        let db = Db::new();
+        assert_eq!(db.scan(0, 10), (0, vec![]));
+        for i in 0..25 {
+            db.set(format!("key{}", i), Bytes::from("value"), None);
+        }
+        // Every key once, in pages of at most `count` keys.
+        let (mut cursor, mut scanned) = (0, vec![]);
+        loop {
+            let (next, keys) = db.scan(cursor, 10);
+            assert!(keys.len() <= 10);
+            scanned.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        scanned.sort();
+        let mut expected: Vec<String> = (0..25).map(|i| format!("key{}", i)).collect();
+        expected.sort();
+        assert_eq!(scanned, expected);
+    }
+
+    #[tokio::test]
+    async fn test_object_encoding() {
+        let db = Db::new();
+        let limits = Params::new(&Config::default()).encoding_limits();
+        let encoding = |key: &str| db.object_encoding(key, &limits);
+        assert_eq!(encoding("missing"), None);
 
-        assert_eq!(db.get("key1"), None);
-        assert_eq!(db.get("key2").unwrap(), Bytes::from("value2"));
+        db.set("int".to_string(), Bytes::from("-12"), None);
+        db.set("padded".to_string(), Bytes::from("012"), None);
+        db.set("long".to_string(), Bytes::from("x".repeat(45)), None);
+        assert_eq!(encoding("int"), Some("int"));
+        assert_eq!(encoding("padded"), Some("embstr"));
+        assert_eq!(encoding("long"), Some("raw"));
 
-        tokio::time::sleep(Duration::from_millis(110)).await;
+        let elements = |n: usize| (0..n).map(|i| Bytes::from(format!("element-{}", i))).collect();
+        db.push("small", elements(10), false).unwrap();
+        db.push("large", elements(1000), false).unwrap();
+        assert_eq!(encoding("small"), Some("listpack"));
+        assert_eq!(encoding("large"), Some("quicklist"));
+        // A positive limit counts the elements.
+        let limits = EncodingLimits {
+            list_max_listpack_size: 5,
+            ..limits
+        };
+        assert_eq!(db.object_encoding("small", &limits), Some("quicklist"));
+
+        db.sadd("ints", vec![Bytes::from("1"), Bytes::from("2")]).unwrap();
+        db.sadd("members", vec![Bytes::from("a"), Bytes::from("1")]).unwrap();
+        db.sadd("many", elements(200)).unwrap();
+        assert_eq!(encoding("ints"), Some("intset"));
+        assert_eq!(encoding("members"), Some("listpack"));
+        assert_eq!(encoding("many"), Some("hashtable"));
+    }
+
+    #[tokio::test]
+    async fn test_used_memory() {
+        let db = db_without_purge();
+        assert_eq!(db.used_memory(), 0);
+        db.set("key".to_string(), Bytes::from("value"), None);
+        assert_eq!(db.used_memory(), 8);
+        db.append("key", b"s", usize::MAX).unwrap();
+        db.setrange("key", 8, b"!").unwrap();
+        assert_eq!(db.used_memory(), 12);
+        db.incr_by("counter", 10).unwrap();
+        assert_eq!(db.used_memory(), 21);
+        db.push("list", vec![Bytes::from("a"), Bytes::from("bc")], false)
+            .unwrap();
+        db.pop("list", true).unwrap();
+        assert_eq!(db.used_memory(), 27);
+        db.sadd("set", vec![Bytes::from("a"), Bytes::from("bc"), Bytes::from("a")])
+            .unwrap();
+        db.srem("set", &[Bytes::from("a")]).unwrap();
+        assert_eq!(db.used_memory(), 32);
+        db.copy("key", "copy", false);
+        assert_eq!(db.used_memory(), 45);
+        db.check_invariants();
+
+        // Removed keys don't count anymore, the last element of a list removes it.
+        db.del("key");
+        db.getdel("copy").unwrap();
+        db.pop("list", false).unwrap();
+        db.srem("set", &[Bytes::from("bc")]).unwrap();
+        assert_eq!(db.used_memory(), 9);
+        db.check_invariants();
+        db.flush();
+        assert_eq!(db.used_memory(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_evict_lru() {
+        let dbs = DbGuard::new(2).dbs();
+        // Each key uses 9 bytes, the oldest keys are key0, then key1...
+        for i in 0..10 {
+            dbs[i % 2].set(format!("key{}", i), Bytes::from("value"), None);
+        }
+        assert_eq!(used_memory(&dbs), 90);
+        // Reads count as accesses, except for the clients in NO-TOUCH mode.
+        dbs[0].get("key0").unwrap();
+        dbs[1].without_touch().get("key1").unwrap();
+        dbs[0].strlen("key2").unwrap();
+
+        evict_lru(&dbs, 50);
+        assert_eq!(used_memory(&dbs), 45);
+        let exists = |i: usize| dbs[i % 2].exists(&format!("key{}", i));
+        let kept: Vec<usize> = (0..10).filter(|&i| exists(i)).collect();
+        assert_eq!(kept, [0, 2, 7, 8, 9]);
+
+        evict_lru(&dbs, 0);
+        assert_eq!(used_memory(&dbs), 0);
+        assert!(dbs.iter().all(|db| db.len() == 0));
+    }
+
+    #[tokio::test]
+    async fn test_eviction_samples() {
+        // A single shard, with more keys than samples.
+        let db = Db::with_shared(Shared::new(1, Arc::default()));
+        for i in 0..100 {
+            db.set(format!("key{}", i), Bytes::from("value"), None);
+        }
+        let candidates: HashSet<String> = (0..20)
+            .filter_map(|_| db.eviction_candidate())
+            .map(|(_, key)| key)
+            .collect();
+        assert!(candidates.len() > 1, "always sampled the same keys: {:?}", candidates);
+        // Removed keys leave the index, the others take their slots.
+        for i in (0..100).step_by(2) {
+            db.del(&format!("key{}", i));
+        }
+        db.check_invariants();
+        for _ in 0..20 {
+            let (_, key) = db.eviction_candidate().unwrap();
+            assert!(db.exists(&key), "{} is not a key anymore", key);
+        }
+    }
 
-        assert_eq!(db.get("key2"), None);
+    #[tokio::test]
+    async fn test_set_huge_expire() {
+        let db = Db::new();
+        db.set("key1".to_string(), Bytes::from("value1"), Some(Duration::MAX));
+        assert_eq!(db.get("key1"), Ok(Some(Bytes::from("value1"))));
     }
 }
 
 impl Shared {
+    #[cfg(test)]
+    fn new(shards: usize, feeds: Arc<Feeds>) -> Self {
+        Shared {
+            shards: (0..shards).map(|_| Shard::default()).collect(),
+            feeds,
+        }
+    }
+
+    /// Shards waking up the purge tasks of `notify`, one per shard index.
+    fn with_notify(notify: &[Arc<Notify>], feeds: Arc<Feeds>) -> Self {
+        let shards = notify.iter().map(|notify| Shard {
+            state: RwLock::default(),
+            bg_task_notify: notify.clone(),
+            used_memory: AtomicUsize::default(),
+        });
+        Shared {
+            shards: shards.collect(),
+            feeds,
+        }
+    }
+}
+
+impl Default for Feeds {
+    fn default() -> Self {
+        Feeds {
+            monitor: broadcast::channel(MONITOR_CAPACITY).0,
+            channels: Mutex::default(),
+        }
+    }
+}
+
+impl Shard {
+    fn read(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap()
+    }
+
+    fn write(&self) -> ShardWriteGuard<'_> {
+        ShardWriteGuard {
+            state: self.state.write().unwrap(),
+            used_memory_copy: &self.used_memory,
+        }
+    }
+
     /// Remove expired keys. And return the next expiration time if any.
     pub(crate) fn purge_expired_keys(&self) -> Option<Instant> {
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.write();
         let now = Instant::now();
-        // This is needed to make the borrow checker happy.
-        // `state.expirations.iter()` borrows `state` immutably, but `state.entries.remove` borrows `state` mutably.
-        // So we need to split the borrow and make sure the mutable borrow is dropped before the immutable borrow.
-        let state = &mut *state;
-        let when = if let Some(&(when, ref key)) = state.expirations.iter().next() {
+        let when = if let Some((when, key)) = state.expirations.first().cloned() {
             if when > now {
                 // No more keys to expire.
                 return Some(when);
             }
-            state.entries.remove(key);
-            state.expirations.remove(&(when, key.clone()));
+            state.remove_entry(&key);
             // Return the next expiration time if any.
             // It's different from the mini-redis, which always returns None.
-            state.expirations.iter().next().map(|x| x.0)
+            state.next_expiration()
         } else {
             None
         };
@@ -193,9 +2284,9 @@ impl Shared {
 
 #[cfg(test)]
 mod test_shared {
-    use crate::db::{Db, Shared};
+    use crate::db::{Db, DbGuard, Shard, Shared, SHARDS};
     use bytes::Bytes;
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
     use std::time::Duration;
     use tokio::time::Instant;
 
@@ -203,27 +2294,15 @@ mod test_shared {
         a <= b + Duration::from_millis(10) && a >= b - Duration::from_millis(10)
     }
 
-    impl Db {
-        fn delete(&self, key: &str) -> Option<Bytes> {
-            let mut state = self.shared.state.lock().unwrap();
-            let entry = state.entries.remove(key)?;
-            if let Some(expires_at) = entry.expires_at {
-                state.expirations.remove(&(expires_at, key.to_string()));
-            }
-            Some(entry.data)
-        }
-    }
-
     #[tokio::test]
     async fn test_purge_expired_keys() {
-        let shared = Arc::new(Shared {
-            state: Mutex::new(crate::db::State {
-                entries: std::collections::HashMap::new(),
-                expirations: std::collections::BTreeSet::new(),
-            }),
-            bg_task_notify: tokio::sync::Notify::new(),
-        });
-        let db = Db { shared: shared.clone() };
+        // A single shard, so both keys are in it.
+        let shared = Arc::new(Shared::new(1, Arc::default()));
+        let db = Db {
+            shared: shared.clone(),
+            no_touch: false,
+        };
+        let shard = &shared.shards[0];
 
         // Insert a key that will expire in 1 second.
         let first_when = Duration::from_secs(1);
@@ -234,57 +2313,287 @@ mod test_shared {
 
         // The first key should expire in 1 second.
         assert!(
-            roughly_equal(shared.purge_expired_keys().unwrap(), Instant::now() + first_when),
+            roughly_equal(shard.purge_expired_keys().unwrap(), Instant::now() + first_when),
             "first key should expire in 1 second"
         );
 
         // delete the first key
-        db.delete("key1");
+        db.del("key1");
 
         assert!(
-            roughly_equal(shared.purge_expired_keys().unwrap(), Instant::now() + second_when),
+            roughly_equal(shard.purge_expired_keys().unwrap(), Instant::now() + second_when),
             "second key should expire in 2 seconds"
         );
         // delete the second key
-        db.delete("key2");
+        db.del("key2");
         // No more keys to expire.
-        assert_eq!(shared.purge_expired_keys(), None);
+        assert_eq!(shard.purge_expired_keys(), None);
+    }
+
+    #[tokio::test]
+    async fn test_keys_spread_over_shards() {
+        let db = Db::new();
+        for i in 0..1000 {
+            db.set(
+                format!("key{}", i),
+                Bytes::from("value"),
+                Some(Duration::from_millis(20)),
+            );
+        }
+        let sizes: Vec<_> = db
+            .lock_all(Shard::read)
+            .iter()
+            .map(|state| state.entries.len())
+            .collect();
+        assert_eq!(sizes.len(), SHARDS);
+        assert!(sizes.iter().all(|&size| size > 0), "some shard is empty: {:?}", sizes);
+        assert_eq!(db.len(), 1000);
+
+        // Every shard purges its own keys.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(db.lock_all(Shard::read).iter().all(|state| state.entries.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_purge_tasks_shared_by_dbs() {
+        let guard = DbGuard::new(16);
+        let dbs = guard.dbs();
+        for (i, db) in dbs.iter().enumerate() {
+            db.set(
+                format!("key{}", i),
+                Bytes::from("value"),
+                Some(Duration::from_millis(20)),
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(dbs.iter().all(|db| db.len() == 0));
+        assert!(dbs
+            .iter()
+            .all(|db| db.lock_all(Shard::read).iter().all(|state| state.entries.is_empty())));
+
+        // Once the guard is dropped, the tasks stop and release the databases.
+        drop(guard);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(dbs.iter().all(|db| Arc::strong_count(&db.shared) == 1));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_parallel_clients() {
+        let db = Db::new();
+        let tasks: Vec<_> = (0..32)
+            .map(|client| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for i in 0..500 {
+                        let key = format!("client{}:{}", client, i % 50);
+                        db.set(key.clone(), Bytes::from(i.to_string()), None);
+                        assert_eq!(db.get(&key), Ok(Some(Bytes::from(i.to_string()))));
+                        db.incr_by("counter", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(db.get("counter"), Ok(Some(Bytes::from((32 * 500).to_string()))));
+        assert_eq!(db.len(), 32 * 50 + 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_parallel_readers() {
+        let db = Db::new();
+        for i in 0..100 {
+            db.set(format!("key{}", i), Bytes::from(i.to_string()), None);
+        }
+        let readers: Vec<_> = (0..32)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for round in 0..100 {
+                        let i = round % 100;
+                        let key = format!("key{}", i);
+                        assert_eq!(db.get(&key), Ok(Some(Bytes::from(i.to_string()))));
+                        assert!(db.exists(&key));
+                        assert_eq!(db.strlen(&key), Ok(i.to_string().len()));
+                        assert_eq!(db.ttl(&key), Some(None));
+                    }
+                })
+            })
+            .collect();
+        // A writer on other keys, in the same shards.
+        for i in 100..200 {
+            db.set(format!("key{}", i), Bytes::from("value"), None);
+        }
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        assert_eq!(db.len(), 200);
     }
 }
 
-async fn purge_expired_keys(shared: Arc<Shared>) {
-    loop {
-        if let Some(when) = shared.purge_expired_keys() {
+/// Purge the expired keys of the shard at `index` in each of `dbs`, until `stop` is set.
+///
+/// `notify` is the `bg_task_notify` of those shards.
+async fn purge_expired_keys(dbs: Vec<Db>, index: usize, notify: Arc<Notify>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        let next = dbs
+            .iter()
+            .filter_map(|db| db.shared.shards[index].purge_expired_keys())
+            .min();
+        if let Some(when) = next {
             // Wait until the next key expires, or notified by someone.
             tokio::select! {
                 _ = time::sleep_until(when) => {},
-                _ = shared.bg_task_notify.notified() => {}
+                _ = notify.notified() => {}
             }
         } else {
             // Wait until notified by someone.
-            shared.bg_task_notify.notified().await;
+            notify.notified().await;
         }
     }
 }
 
+impl Deref for ShardWriteGuard<'_> {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        &self.state
+    }
+}
+
+impl DerefMut for ShardWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+}
+
+impl Drop for ShardWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.used_memory_copy.store(self.state.used_memory, Ordering::Relaxed);
+    }
+}
+
+/// `entries` and `expirations` must always agree, so every change of a key's existence or TTL goes
+/// through `remove_entry` and `set_expiry`.
 impl State {
     fn next_expiration(&self) -> Option<Instant> {
         self.expirations.iter().next().map(|x| x.0)
     }
+
+    /// Check if `key` is stored and not past its deadline at `now`.
+    fn contains(&self, key: &str, now: Instant) -> bool {
+        self.entries.get(key).is_some_and(|entry| !entry.is_expired(now))
+    }
+
+    /// Remove `key` along with its expiration, and return the removed entry.
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        // The last key takes the slot of the removed one.
+        self.keys.swap_remove(entry.slot);
+        if let Some(moved) = self.keys.get(entry.slot) {
+            self.entries.get_mut(moved).unwrap().slot = entry.slot;
+        }
+        if let Some(expires_at) = entry.expires_at {
+            self.expirations.remove(&(expires_at, key.to_string()));
+        }
+        self.used_memory -= key.len() + entry.value.size();
+        Some(entry)
+    }
+
+    /// Insert `key` without TTL, replacing any previous entry.
+    fn insert_entry(&mut self, key: String, value: EntryValue) {
+        self.remove_entry(&key);
+        self.used_memory += key.len() + value.size();
+        let entry = Entry::new(value, self.keys.len());
+        self.keys.push(key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    /// Replace the value of `key` and keep its TTL, unless it's past its deadline at `now`, in which
+    /// case it's inserted like a new key.
+    fn update_value(&mut self, key: &str, value: EntryValue, now: Instant) {
+        match self.entries.get_mut(key).filter(|entry| !entry.is_expired(now)) {
+            Some(entry) => {
+                entry.touch();
+                self.used_memory += value.size();
+                self.used_memory -= std::mem::replace(&mut entry.value, value).size();
+            }
+            None => self.insert_entry(key.to_string(), value),
+        }
+    }
+
+    /// Wake the tasks waiting for `key`, it's being written.
+    fn wake_waiters(&mut self, key: &str) {
+        for waiter in self.waiters.remove(key).into_iter().flatten() {
+            waiter.notify_one();
+        }
+    }
+
+    /// Change the expiration of an existing `key`, `None` means it never expires.
+    ///
+    /// Return whether it's now the earliest expiration, in which case the background task needs to be notified.
+    fn set_expiry(&mut self, key: &str, expires_at: Option<Instant>) -> bool {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return false;
+        };
+        if let Some(prev) = std::mem::replace(&mut entry.expires_at, expires_at) {
+            self.expirations.remove(&(prev, key.to_string()));
+        }
+        let Some(when) = expires_at else {
+            return false;
+        };
+        // First key or earlier than the current next expiration time.
+        let notify = self.next_expiration().map(|t| t > when).unwrap_or(true);
+        self.expirations.insert((when, key.to_string()));
+        notify
+    }
 }
 
 #[cfg(test)]
 mod test_state {
-    use crate::db::State;
-    use std::collections::{BTreeSet, HashMap};
+    use crate::db::{EntryValue, State};
+    use bytes::Bytes;
+    use std::time::Duration;
     use tokio::time::Instant;
 
+    impl State {
+        /// Panic if an expiration has no matching entry, or an entry with a TTL is missing from `expirations`.
+        pub(crate) fn check_invariants(&self) {
+            for (when, key) in &self.expirations {
+                let entry = self.entries.get(key).expect("expiration without entry");
+                assert_eq!(entry.expires_at, Some(*when), "expiration of {} is out of date", key);
+            }
+            for (key, entry) in &self.entries {
+                if let Some(when) = entry.expires_at {
+                    assert!(
+                        self.expirations.contains(&(when, key.clone())),
+                        "{} is missing from expirations",
+                        key
+                    );
+                }
+            }
+            assert_eq!(self.keys.len(), self.entries.len(), "keys are out of date");
+            for (slot, key) in self.keys.iter().enumerate() {
+                assert_eq!(
+                    self.entries.get(key).map(|entry| entry.slot),
+                    Some(slot),
+                    "slot of {} is out of date",
+                    key
+                );
+            }
+            let size: usize = self
+                .entries
+                .iter()
+                .map(|(key, entry)| key.len() + entry.value.size())
+                .sum();
+            assert_eq!(self.used_memory, size, "used memory is out of date");
+        }
+    }
+
     #[test]
     fn test_next_expiration() {
-        let mut state = State {
-            entries: HashMap::new(),
-            expirations: BTreeSet::new(),
-        };
+        let mut state = State::default();
         assert_eq!(state.next_expiration(), None);
 
         let now = Instant::now();
@@ -296,4 +2605,45 @@ mod test_state {
         state.expirations.insert((next_now, "key2".to_string()));
         assert_eq!(state.next_expiration(), Some(now));
     }
+
+    fn state_with(keys: &[&str]) -> State {
+        let mut state = State::default();
+        for key in keys {
+            state.insert_entry(key.to_string(), EntryValue::String(Bytes::from("value")));
+        }
+        state
+    }
+
+    #[test]
+    fn test_set_expiry() {
+        let mut state = state_with(&["key1", "key2"]);
+        let now = Instant::now();
+
+        // The first and any earlier expiration need a notification, later ones don't.
+        assert!(state.set_expiry("key1", Some(now + Duration::from_secs(10))));
+        assert!(!state.set_expiry("key2", Some(now + Duration::from_secs(20))));
+        assert!(state.set_expiry("key2", Some(now + Duration::from_secs(5))));
+        assert_eq!(state.expirations.len(), 2);
+        state.check_invariants();
+
+        assert!(!state.set_expiry("key1", None));
+        assert_eq!(state.next_expiration(), Some(now + Duration::from_secs(5)));
+        state.check_invariants();
+
+        // Missing keys are ignored.
+        assert!(!state.set_expiry("key3", Some(now)));
+        state.check_invariants();
+    }
+
+    #[test]
+    fn test_remove_entry() {
+        let mut state = state_with(&["key1", "key2"]);
+        state.set_expiry("key1", Some(Instant::now()));
+
+        assert!(state.remove_entry("key1").is_some());
+        assert!(state.remove_entry("key1").is_none());
+        assert!(state.remove_entry("key2").is_some());
+        assert!(state.entries.is_empty());
+        assert!(state.expirations.is_empty());
+    }
 }
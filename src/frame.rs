@@ -1,5 +1,6 @@
 use anyhow::anyhow;
 use bytes::{Buf, Bytes};
+use std::borrow::Cow;
 use std::io::Cursor;
 use std::string::FromUtf8Error;
 // These five types are:
@@ -15,16 +16,33 @@ use std::string::FromUtf8Error;
 // 5. Arrays: Start with *, followed by the number of array elements, and then the serialized representation of each element.
 //    for example: *2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n
 
+/// Upper bounds on the frames sent by a client, see [crate::Config::max_bulk_len].
+///
+/// They are checked on the headers, so a client can't make the server buffer or allocate more with a
+/// single header like `$1000000000\r\n`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    /// Longest bulk string, in bytes.
+    pub(crate) max_bulk_len: usize,
+    /// Most elements in an array.
+    pub(crate) max_multibulk_len: usize,
+    /// Most arrays nested in one another, a flat array is 1. Frames are parsed recursively, this
+    /// bounds the stack used by a frame like `*1\r\n*1\r\n*1\r\n...`.
+    pub(crate) max_depth: usize,
+}
+
 /// A frame in the Redis protocol.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Frame {
     Simple(String),
     Error(String),
-    Integer(u64),
+    Integer(i64),
     Bulk(Bytes),
     // Null is a special case of Bulk, which represents a null value.
     Null,
     Array(Vec<Frame>),
+    // An absent array, `*-1\r\n`. It's different from an empty array.
+    NullArray,
 }
 
 #[derive(Debug)]
@@ -37,21 +55,68 @@ pub enum Error {
 }
 
 impl Frame {
-    /// Serialize the frame to a string
-    pub fn serialize(&self) -> String {
+    /// The reply to a command against a key holding another type of value, see [crate::db::WrongType].
+    pub(crate) fn wrong_type() -> Frame {
+        Frame::Error(crate::db::WrongType.to_string())
+    }
+
+    /// The reply to an increment of a value that isn't an integer, or would overflow.
+    pub(crate) fn not_an_integer() -> Frame {
+        Frame::Error("ERR value is not an integer or out of range".to_string())
+    }
+
+    /// The reply to a command that may use more memory, once `maxmemory` is reached and nothing can
+    /// be evicted.
+    pub(crate) fn out_of_memory() -> Frame {
+        Frame::Error("OOM command not allowed when used memory > 'maxmemory'.".to_string())
+    }
+
+    /// The reply to a write that would make a string longer than `proto-max-bulk-len`, see
+    /// [crate::Config::max_bulk_len].
+    pub(crate) fn string_too_long() -> Frame {
+        Frame::Error("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string())
+    }
+
+    /// Serialize the frame to bytes. Bulk payloads are written as is, so they can be any binary data.
+    ///
+    /// This is the reference encoding for the tests, the connection streams the same bytes with
+    /// [crate::connection::Connection::write_frame] rather than building the whole reply first.
+    #[cfg(test)]
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.serialize_into(&mut buf);
+        buf
+    }
+
+    /// Append the encoding of the frame to `buf`, like [Frame::serialize].
+    pub(crate) fn serialize_into(&self, buf: &mut Vec<u8>) {
         match self {
-            Frame::Simple(s) => format!("+{}\r\n", s),
-            Frame::Bulk(b) => format!("${}\r\n{}\r\n", b.len(), String::from_utf8(b.to_vec()).unwrap()),
-            Frame::Error(s) => format!("-{}\r\n", s),
-            Frame::Null => "$-1\r\n".to_string(),
-            Frame::Integer(i) => format!(":{}\r\n", i),
-            // TODO implement serialize for other types
-            _ => panic!("Not implemented"),
+            Frame::Simple(s) => buf.extend_from_slice(format!("+{}\r\n", one_line(s)).as_bytes()),
+            Frame::Bulk(b) => {
+                buf.extend_from_slice(format!("${}\r\n", b.len()).as_bytes());
+                buf.extend_from_slice(b);
+                buf.extend_from_slice(b"\r\n");
+            }
+            Frame::Error(s) => buf.extend_from_slice(format!("-{}\r\n", one_line(s)).as_bytes()),
+            Frame::Null => buf.extend_from_slice(b"$-1\r\n"),
+            Frame::NullArray => buf.extend_from_slice(b"*-1\r\n"),
+            Frame::Integer(i) => buf.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+            Frame::Array(frames) => {
+                buf.extend_from_slice(format!("*{}\r\n", frames.len()).as_bytes());
+                for frame in frames {
+                    frame.serialize_into(buf);
+                }
+            }
         }
     }
 
-    /// check if the frame is valid
-    pub fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+    /// check if the frame is valid, and within `limits`
+    pub(crate) fn check(src: &mut Cursor<&[u8]>, limits: &Limits) -> Result<(), Error> {
+        Frame::check_nested(src, limits, 0)
+    }
+
+    /// Check a frame nested in `depth` arrays.
+    fn check_nested(src: &mut Cursor<&[u8]>, limits: &Limits, depth: usize) -> Result<(), Error> {
         match get_u8(src)? {
             b'+' => {
                 get_line(src)?;
@@ -60,7 +125,7 @@ impl Frame {
                 get_line(src)?;
             }
             b':' => {
-                get_decimal(src)?;
+                get_decimal::<i64>(src)?;
             }
             b'$' => {
                 if b'-' == peek_u8(src)? {
@@ -68,14 +133,22 @@ impl Frame {
                     skip(src, 4)?;
                 } else {
                     // read the length of the bulk string
-                    let len = get_decimal(src)?;
-                    skip(src, len as usize + 2)?;
+                    let len = get_bulk_len(src, limits.max_bulk_len)?;
+                    skip(src, len + 2)?;
                 }
             }
             b'*' => {
-                let len = get_decimal(src)?;
-                for _ in 0..len {
-                    Frame::check(src)?;
+                if b'-' == peek_u8(src)? {
+                    // skip the '-1\r\n'
+                    skip(src, 4)?;
+                } else {
+                    if depth == limits.max_depth {
+                        return Err(Error::Other(anyhow!("protocol error; too deeply nested multibulk")));
+                    }
+                    let len = get_multibulk_len(src, limits.max_multibulk_len)?;
+                    for _ in 0..len {
+                        Frame::check_nested(src, limits, depth + 1)?;
+                    }
                 }
             }
             _ => return Err(Error::Other(anyhow!("Not a known value type"))),
@@ -84,6 +157,9 @@ impl Frame {
     }
 
     /// parse the frame from the buffer
+    ///
+    /// Arrays are parsed recursively, so a frame from an untrusted source must pass [Frame::check]
+    /// first, it bounds the nesting.
     pub fn parse(src: &mut Cursor<&[u8]>) -> Result<Frame, Error> {
         match get_u8(src)? {
             b'+' => {
@@ -97,8 +173,8 @@ impl Frame {
                 Ok(Frame::Error(string))
             }
             b':' => {
-                let len = get_decimal(src)?;
-                Ok(Frame::Integer(len))
+                let num = get_decimal(src)?;
+                Ok(Frame::Integer(num))
             }
             b'$' => {
                 if b'-' == peek_u8(src)? {
@@ -108,8 +184,12 @@ impl Frame {
                     }
                     Ok(Frame::Null)
                 } else {
-                    let len = get_decimal(src)?;
+                    let len: u64 = get_decimal(src)?;
                     let n = len as usize;
+                    // Checked by [Frame::check] first, but a truncated frame must not panic either.
+                    if src.remaining() < n.saturating_add(2) {
+                        return Err(Error::Incomplete);
+                    }
                     let mut buf = vec![0; n];
                     src.copy_to_slice(&mut buf);
                     skip(src, 2)?;
@@ -117,8 +197,16 @@ impl Frame {
                 }
             }
             b'*' => {
-                let len = get_decimal(src)?;
-                let mut frames = Vec::with_capacity(len as usize);
+                if b'-' == peek_u8(src)? {
+                    let line = get_line(src)?;
+                    if line != b"-1" {
+                        return Err(Error::Other(anyhow!("protocol error; invalid frame format")));
+                    }
+                    return Ok(Frame::NullArray);
+                }
+                // Checked by [Frame::check] first, but not trusted to allocate upfront.
+                let len: u64 = get_decimal(src)?;
+                let mut frames = Vec::with_capacity(len.min(1024) as usize);
                 for _ in 0..len {
                     frames.push(Frame::parse(src)?);
                 }
@@ -129,67 +217,168 @@ impl Frame {
     }
 }
 
+/// The content of a simple string or an error, which ends at the first CRLF. Like Redis, line breaks
+/// are replaced with spaces, e.g. in an error echoing an argument, so they can't inject a reply.
+pub(crate) fn one_line(s: &str) -> Cow<'_, str> {
+    if s.contains(['\r', '\n']) {
+        Cow::Owned(s.replace(['\r', '\n'], " "))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
 #[cfg(test)]
 mod test_frame {
     use super::*;
+
+    fn limits() -> Limits {
+        crate::Config::default().frame_limits()
+    }
+
     #[test]
     fn test_serialize_simple_string() {
         let frame = Frame::Simple("OK".to_string());
-        assert_eq!(frame.serialize(), "+OK\r\n");
+        assert_eq!(frame.serialize(), b"+OK\r\n");
     }
 
     #[test]
     fn test_serialize_bulk_string() {
         let frame = Frame::Bulk(Bytes::from("foo".as_bytes()));
-        assert_eq!(frame.serialize(), "$3\r\nfoo\r\n");
+        assert_eq!(frame.serialize(), b"$3\r\nfoo\r\n");
+    }
+
+    #[test]
+    fn test_serialize_binary_bulk_string() {
+        let frame = Frame::Bulk(Bytes::from(vec![0xff, 0xfe, 0x00]));
+        assert_eq!(frame.serialize(), b"$3\r\n\xff\xfe\x00\r\n");
     }
 
     #[test]
     fn test_serialize_error() {
         let frame = Frame::Error("ERR unknown command 'foobar'".to_string());
-        assert_eq!(frame.serialize(), "-ERR unknown command 'foobar'\r\n");
+        assert_eq!(frame.serialize(), b"-ERR unknown command 'foobar'\r\n");
+    }
+
+    #[test]
+    fn test_serialize_line_breaks() {
+        let frame = Frame::Error("ERR unknown subcommand 'y\r\n:42'".to_string());
+        assert_eq!(frame.serialize(), b"-ERR unknown subcommand 'y  :42'\r\n");
+        let frame = Frame::Simple("a\nb".to_string());
+        assert_eq!(frame.serialize(), b"+a b\r\n");
+    }
+
+    #[test]
+    fn test_error_helpers() {
+        // The same wording as Redis, clients match on it.
+        assert_eq!(
+            Frame::wrong_type().serialize(),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(
+            Frame::not_an_integer().serialize(),
+            b"-ERR value is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            Frame::out_of_memory().serialize(),
+            b"-OOM command not allowed when used memory > 'maxmemory'.\r\n"
+        );
+        assert_eq!(
+            Frame::string_too_long().serialize(),
+            b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+        );
     }
 
     #[test]
     fn test_serialize_null() {
         let frame = Frame::Null;
-        assert_eq!(frame.serialize(), "$-1\r\n");
+        assert_eq!(frame.serialize(), b"$-1\r\n");
     }
 
     #[test]
     fn test_serialize_integer() {
         let frame = Frame::Integer(1000);
-        assert_eq!(frame.serialize(), ":1000\r\n");
+        assert_eq!(frame.serialize(), b":1000\r\n");
+        assert_eq!(Frame::Integer(-2).serialize(), b":-2\r\n");
+        assert_eq!(Frame::Integer(i64::MIN).serialize(), b":-9223372036854775808\r\n");
+    }
+
+    #[test]
+    fn test_serialize_array() {
+        let frame = Frame::Array(vec![Frame::Bulk(Bytes::from("foo")), Frame::Integer(5), Frame::Null]);
+        assert_eq!(frame.serialize(), b"*3\r\n$3\r\nfoo\r\n:5\r\n$-1\r\n");
+    }
+
+    #[test]
+    fn test_serialize_nested_array() {
+        let frame = Frame::Array(vec![
+            Frame::Array(vec![Frame::Simple("OK".to_string()), Frame::Array(vec![])]),
+            Frame::NullArray,
+            Frame::Error("ERR oops".to_string()),
+        ]);
+        assert_eq!(frame.serialize(), b"*3\r\n*2\r\n+OK\r\n*0\r\n*-1\r\n-ERR oops\r\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frames = [
+            Frame::Integer(1000),
+            Frame::Error("ERR unknown command 'foobar'".to_string()),
+            Frame::Array(vec![]),
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("foo")),
+                Frame::Array(vec![Frame::Integer(5), Frame::Null]),
+                Frame::Error("ERR oops".to_string()),
+            ]),
+        ];
+        for frame in frames {
+            let serialized = frame.serialize();
+            let mut buf = Cursor::new(&serialized[..]);
+            Frame::check(&mut buf, &limits()).unwrap();
+            assert_eq!(buf.position() as usize, serialized.len());
+            buf.set_position(0);
+            assert_eq!(Frame::parse(&mut buf).unwrap(), frame);
+        }
     }
 
     #[test]
     fn test_check_simple_string() {
         let mut buf = Cursor::new(&b"+OK\r\n"[..]);
-        Frame::check(&mut buf).unwrap();
+        Frame::check(&mut buf, &limits()).unwrap();
     }
 
     #[test]
     fn test_check_bulk_string() {
         let mut buf = Cursor::new(&b"$6\r\nfoobar\r\n"[..]);
-        Frame::check(&mut buf).unwrap();
+        Frame::check(&mut buf, &limits()).unwrap();
     }
 
     #[test]
     fn test_check_error() {
         let mut buf = Cursor::new(&b"-ERR unknown command 'foobar'\r\n"[..]);
-        Frame::check(&mut buf).unwrap();
+        Frame::check(&mut buf, &limits()).unwrap();
     }
 
     #[test]
     fn test_check_null() {
         let mut buf = Cursor::new(&b"$-1\r\n"[..]);
-        Frame::check(&mut buf).unwrap();
+        Frame::check(&mut buf, &limits()).unwrap();
     }
 
     #[test]
     fn test_check_integer() {
         let mut buf = Cursor::new(&b":1000\r\n"[..]);
-        Frame::check(&mut buf).unwrap();
+        Frame::check(&mut buf, &limits()).unwrap();
+    }
+
+    #[test]
+    fn test_check_unterminated_simple_string() {
+        let limits = crate::Config::default().frame_limits();
+        for partial in [&b"+OK"[..], b"+OK\r", b"*2\r\n+OK\r\n+O"] {
+            let mut buf = Cursor::new(partial);
+            assert!(matches!(Frame::check(&mut buf, &limits), Err(Error::Incomplete)));
+            let mut buf = Cursor::new(partial);
+            assert!(matches!(Frame::parse(&mut buf), Err(Error::Incomplete)));
+        }
     }
 
     #[test]
@@ -206,6 +395,15 @@ mod test_frame {
         assert_eq!(frame, Frame::Bulk(Bytes::from("foobar".as_bytes())));
     }
 
+    #[test]
+    fn test_parse_truncated_bulk_string() {
+        // The length says 6 bytes, only 3 are there.
+        let mut buf = Cursor::new(&b"$6\r\nfoo\r\n"[..]);
+        assert!(matches!(Frame::parse(&mut buf), Err(Error::Incomplete)));
+        let mut buf = Cursor::new(&b"*1\r\n$6\r\nfoo"[..]);
+        assert!(matches!(Frame::parse(&mut buf), Err(Error::Incomplete)));
+    }
+
     #[test]
     fn test_parse_error() {
         let mut buf = Cursor::new(&b"-ERR unknown command 'foobar'\r\n"[..]);
@@ -227,6 +425,48 @@ mod test_frame {
         assert_eq!(frame, Frame::Integer(1000));
     }
 
+    #[test]
+    fn test_parse_negative_integer() {
+        for (src, num) in [
+            (&b":-1\r\n"[..], -1),
+            (b":-2\r\n", -2),
+            (b":-9223372036854775808\r\n", i64::MIN),
+        ] {
+            let mut buf = Cursor::new(src);
+            Frame::check(&mut buf, &crate::Config::default().frame_limits()).unwrap();
+            let mut buf = Cursor::new(src);
+            assert_eq!(Frame::parse(&mut buf).unwrap(), Frame::Integer(num));
+        }
+    }
+
+    #[test]
+    fn test_null_array_round_trip() {
+        let frame = Frame::NullArray;
+        assert_eq!(frame.serialize(), b"*-1\r\n");
+        let serialized = frame.serialize();
+        let mut buf = Cursor::new(&serialized[..]);
+        Frame::check(&mut buf, &limits()).unwrap();
+        assert_eq!(buf.position(), 5);
+        buf.set_position(0);
+        assert_eq!(Frame::parse(&mut buf).unwrap(), Frame::NullArray);
+    }
+
+    #[test]
+    fn test_parse_empty_array() {
+        let mut buf = Cursor::new(&b"*0\r\n"[..]);
+        Frame::check(&mut buf, &limits()).unwrap();
+        buf.set_position(0);
+        let frame = Frame::parse(&mut buf).unwrap();
+        assert_eq!(frame, Frame::Array(vec![]));
+        assert_ne!(frame, Frame::NullArray);
+    }
+
+    #[test]
+    fn test_parse_invalid_null_array() {
+        let mut buf = Cursor::new(&b"*-2\r\n"[..]);
+        assert!(matches!(Frame::parse(&mut buf), Err(Error::Other(_))));
+    }
+
     #[test]
     fn test_parse_array() {
         let mut buf = Cursor::new(&b"*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n"[..]);
@@ -239,6 +479,32 @@ mod test_frame {
             ])
         );
     }
+
+    #[test]
+    fn test_parse_mixed_array() {
+        let src = b"*5\r\n+OK\r\n$5\r\nhello\r\n:-42\r\n*2\r\n$1\r\na\r\n*1\r\n-ERR no\r\n$-1\r\n+trailing";
+        let end = src.len() - b"+trailing".len();
+        let mut buf = Cursor::new(&src[..]);
+        Frame::check(&mut buf, &limits()).unwrap();
+        assert_eq!(buf.position() as usize, end);
+
+        buf.set_position(0);
+        let frame = Frame::parse(&mut buf).unwrap();
+        assert_eq!(buf.position() as usize, end);
+        assert_eq!(
+            frame,
+            Frame::Array(vec![
+                Frame::Simple("OK".to_string()),
+                Frame::Bulk(Bytes::from("hello")),
+                Frame::Integer(-42),
+                Frame::Array(vec![
+                    Frame::Bulk(Bytes::from("a")),
+                    Frame::Array(vec![Frame::Error("ERR no".to_string())]),
+                ]),
+                Frame::Null,
+            ])
+        );
+    }
 }
 
 /// skip n bytes from the buffer, the current position is advanced by n.
@@ -303,16 +569,15 @@ mod test_get_u8 {
 /// get a line from the buffer, for example, OK\r\n will return OK
 fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
     let start = src.position() as usize;
-    let mut end = src.get_ref().len();
-    for i in start..end {
+    let end = src.get_ref().len();
+    // The last byte is never looked at on its own, a '\r' there is the first half of a CRLF not received yet.
+    for i in start..end.saturating_sub(1) {
         if src.get_ref()[i] == b'\r' && src.get_ref()[i + 1] == b'\n' {
-            end = i;
-            break;
+            src.set_position(i as u64 + 2);
+            return Ok(&src.get_ref()[start..i]);
         }
     }
-    let line = &src.get_ref()[start..end];
-    src.set_position(end as u64 + 2);
-    Ok(line)
+    Err(Error::Incomplete)
 }
 
 #[cfg(test)]
@@ -323,22 +588,32 @@ mod test_get_line {
         let mut buf = Cursor::new(&b"Hello\r\nWorld"[..]);
         let line = get_line(&mut buf).unwrap();
         assert_eq!(line, b"Hello");
+        assert_eq!(buf.position(), 7);
+    }
+
+    #[test]
+    fn test_get_line_lone_cr() {
+        let mut buf = Cursor::new(&b"Hello\r"[..]);
+        assert!(matches!(get_line(&mut buf), Err(Error::Incomplete)));
+        let mut buf = Cursor::new(&b"\r"[..]);
+        assert!(matches!(get_line(&mut buf), Err(Error::Incomplete)));
+    }
+
+    #[test]
+    fn test_get_line_no_crlf() {
+        let mut buf = Cursor::new(&b"Hello\nWorld"[..]);
+        assert!(matches!(get_line(&mut buf), Err(Error::Incomplete)));
+        let mut buf = Cursor::new(&b""[..]);
+        assert!(matches!(get_line(&mut buf), Err(Error::Incomplete)));
     }
 }
 
 /// Read a new-line terminated decimal
-fn get_decimal(src: &mut Cursor<&[u8]>) -> Result<u64, Error> {
-    let maybe_num = if let Ok(line) = get_line(src) {
-        match String::from_utf8(line.to_vec())?.parse() {
-            Ok(num) => Ok(num),
-            Err(_) => Err(Error::Other(anyhow!("protocol error; invalid number"))),
-        }
-    } else {
-        Err(Error::Incomplete)
-    };
-    match maybe_num {
+fn get_decimal<T: std::str::FromStr>(src: &mut Cursor<&[u8]>) -> Result<T, Error> {
+    let line = get_line(src)?;
+    match String::from_utf8(line.to_vec())?.parse() {
         Ok(num) => Ok(num),
-        Err(e) => Err(e),
+        Err(_) => Err(Error::Other(anyhow!("protocol error; invalid number"))),
     }
 }
 
@@ -348,8 +623,90 @@ mod test_get_decimal {
     #[test]
     fn test_get_decimal() {
         let mut buf = Cursor::new(&b"1000\r\n"[..]);
-        let num = get_decimal(&mut buf).unwrap();
+        let num: u64 = get_decimal(&mut buf).unwrap();
         assert_eq!(num, 1000);
+        // No terminator yet, the number may still have more digits.
+        let mut buf = Cursor::new(&b"1000"[..]);
+        assert!(matches!(get_decimal::<u64>(&mut buf), Err(Error::Incomplete)));
+    }
+}
+
+/// Read the length of an array, rejecting lengths above `max`.
+fn get_multibulk_len(src: &mut Cursor<&[u8]>, max: usize) -> Result<usize, Error> {
+    let len: u64 = get_decimal(src)?;
+    if len > max as u64 {
+        return Err(Error::Other(anyhow!("protocol error; invalid multibulk length")));
+    }
+    Ok(len as usize)
+}
+
+/// Read the length of a bulk string, rejecting lengths above `max`.
+fn get_bulk_len(src: &mut Cursor<&[u8]>, max: usize) -> Result<usize, Error> {
+    let len: u64 = get_decimal(src)?;
+    if len > max as u64 {
+        return Err(Error::Other(anyhow!("protocol error; invalid bulk length")));
+    }
+    Ok(len as usize)
+}
+
+#[cfg(test)]
+mod test_get_multibulk_len {
+    use super::*;
+    #[test]
+    fn test_get_multibulk_len() {
+        let mut buf = Cursor::new(&b"1048576\r\n"[..]);
+        assert_eq!(get_multibulk_len(&mut buf, 1024 * 1024).unwrap(), 1024 * 1024);
+        let mut buf = Cursor::new(&b"1048577\r\n"[..]);
+        assert!(matches!(get_multibulk_len(&mut buf, 1024 * 1024), Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn test_get_bulk_len() {
+        let mut buf = Cursor::new(&b"16\r\n"[..]);
+        assert_eq!(get_bulk_len(&mut buf, 16).unwrap(), 16);
+        let mut buf = Cursor::new(&b"17\r\n"[..]);
+        assert!(matches!(get_bulk_len(&mut buf, 16), Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn test_check_oversized_array() {
+        // The header alone is rejected, without waiting for the elements.
+        let mut buf = Cursor::new(&b"*1000000000\r\n"[..]);
+        match Frame::check(&mut buf, &crate::Config::default().frame_limits()) {
+            Err(Error::Other(err)) => assert_eq!(err.to_string(), "protocol error; invalid multibulk length"),
+            _ => panic!("expected a protocol error"),
+        }
+    }
+
+    #[test]
+    fn test_check_deeply_nested_array() {
+        let limits = crate::Config::default().frame_limits();
+        // Way deeper than the stack could recurse, rejected without a stack overflow.
+        let nested = b"*1\r\n".repeat(200_000);
+        match Frame::check(&mut Cursor::new(&nested[..]), &limits) {
+            Err(Error::Other(err)) => assert_eq!(err.to_string(), "protocol error; too deeply nested multibulk"),
+            _ => panic!("expected a protocol error"),
+        }
+
+        let limits = Limits { max_depth: 2, ..limits };
+        let mut buf = Cursor::new(&b"*1\r\n*1\r\n$2\r\nok\r\n"[..]);
+        assert!(Frame::check(&mut buf, &limits).is_ok());
+        let mut buf = Cursor::new(&b"*1\r\n*1\r\n*0\r\n"[..]);
+        assert!(matches!(Frame::check(&mut buf, &limits), Err(Error::Other(_))));
+        // Only the arrays count, a flat array of scalars is fine at any limit.
+        let limits = Limits { max_depth: 1, ..limits };
+        let mut buf = Cursor::new(&b"*2\r\n:1\r\n$1\r\na\r\n"[..]);
+        assert!(Frame::check(&mut buf, &limits).is_ok());
+    }
+
+    #[test]
+    fn test_check_oversized_bulk() {
+        // The header alone is rejected, without waiting for the payload.
+        let mut buf = Cursor::new(&b"*1\r\n$1000000000\r\n"[..]);
+        match Frame::check(&mut buf, &crate::Config::default().frame_limits()) {
+            Err(Error::Other(err)) => assert_eq!(err.to_string(), "protocol error; invalid bulk length"),
+            _ => panic!("expected a protocol error"),
+        }
     }
 }
 
@@ -0,0 +1,107 @@
+//! Redis-style glob patterns, as used by `KEYS`.
+//!
+//! - `*` matches any sequence of bytes, including an empty one
+//! - `?` matches a single byte
+//! - `[abc]`, `[a-z]` and `[^a]` match a single byte in, or not in, a class
+//! - `\` escapes the next byte, so `\*` only matches `*`
+
+/// Check if `string` matches `pattern`.
+pub(crate) fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last `*`: the position right after it in the pattern, and the next
+    // byte of the string it will have to swallow.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < string.len() {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, s));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p, string[s]),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == string[s]).then_some(p + 2),
+            Some(&b) => (b == string[s]).then_some(p + 1),
+            None => None,
+        };
+        match (next, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            // Let the last `*` swallow one more byte, and retry from there.
+            (None, Some((star_p, star_s))) => {
+                backtrack = Some((star_p, star_s + 1));
+                p = star_p;
+                s = star_s + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    // Only stars can match the empty rest of the string.
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Match `b` against the class starting with the `[` at `pattern[start]`, and return the position
+/// right after the class if it matches. An unterminated class extends to the end of the pattern.
+fn match_class(pattern: &[u8], start: usize, b: u8) -> Option<usize> {
+    let mut p = start + 1;
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+    let mut matched = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            matched |= pattern[p + 1] == b;
+            p += 2;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (lo, hi) = (pattern[p].min(pattern[p + 2]), pattern[p].max(pattern[p + 2]));
+            matched |= (lo..=hi).contains(&b);
+            p += 3;
+        } else {
+            matched |= pattern[p] == b;
+            p += 1;
+        }
+    }
+    // Skip the closing `]`, if any.
+    let end = (p + 1).min(pattern.len());
+    (matched != negate).then_some(end)
+}
+
+#[cfg(test)]
+mod test_glob {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches(b"*", b""));
+        assert!(matches(b"*", b"anything"));
+        assert!(matches(b"user:*", b"user:1"));
+        assert!(!matches(b"user:*", b"users"));
+        assert!(matches(b"*:name", b"user:1:name"));
+        assert!(matches(b"a*b*c", b"axxbyybzc"));
+        assert!(!matches(b"a*b*c", b"axxbyyb"));
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(!matches(b"h?llo", b"hllo"));
+        assert!(!matches(b"", b"a"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"key[0-9]", b"key7"));
+        assert!(!matches(b"key[0-9]", b"keyx"));
+        assert!(matches(b"[\\]]", b"]"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert!(matches(b"a\\*", b"a*"));
+        assert!(!matches(b"a\\*", b"ab"));
+        assert!(matches(b"\\?", b"?"));
+    }
+}
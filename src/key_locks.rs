@@ -0,0 +1,119 @@
+//! Locks on the keys, held by the connection handlers while a command runs.
+//!
+//! A command locks the keys it applies to and `EXEC` locks all of them, so the commands of a
+//! transaction don't interleave with the commands of other clients. Keys hash to a fixed number of
+//! stripes: commands on unrelated keys rarely wait for each other, and commands without keys, e.g.
+//! `PING` or `DEBUG SLEEP`, don't wait at all.
+
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Number of stripes the keys are spread over.
+const STRIPES: usize = 64;
+
+/// The keys a command applies to.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Footprint {
+    /// These keys, none for e.g. `PING`.
+    Keys(Vec<Bytes>),
+    /// The whole key space, e.g. for `KEYS` or `FLUSHALL`.
+    All,
+}
+
+#[derive(Debug)]
+pub(crate) struct KeyLocks {
+    stripes: Box<[RwLock<()>]>,
+}
+
+/// The stripes locked by [KeyLocks::lock], released once dropped.
+pub(crate) struct KeyGuard<'a> {
+    _shared: Vec<RwLockReadGuard<'a, ()>>,
+    _exclusive: Vec<RwLockWriteGuard<'a, ()>>,
+}
+
+impl KeyLocks {
+    pub(crate) fn new() -> Self {
+        KeyLocks {
+            stripes: (0..STRIPES).map(|_| RwLock::default()).collect(),
+        }
+    }
+
+    /// Lock the stripes of `footprint`, shared with the other lockers unless `exclusive`.
+    ///
+    /// The stripes are locked in index order, so lockers can't deadlock.
+    pub(crate) async fn lock(&self, footprint: &Footprint, exclusive: bool) -> KeyGuard<'_> {
+        let indexes: Vec<usize> = match footprint {
+            Footprint::Keys(keys) => {
+                let mut indexes: Vec<usize> = keys.iter().map(stripe).collect();
+                indexes.sort_unstable();
+                indexes.dedup();
+                indexes
+            }
+            Footprint::All => (0..STRIPES).collect(),
+        };
+        let (mut shared, mut locked) = (vec![], vec![]);
+        for index in indexes {
+            if exclusive {
+                locked.push(self.stripes[index].write().await);
+            } else {
+                shared.push(self.stripes[index].read().await);
+            }
+        }
+        KeyGuard {
+            _shared: shared,
+            _exclusive: locked,
+        }
+    }
+}
+
+/// The stripe of `key`.
+fn stripe(key: &Bytes) -> usize {
+    // `DefaultHasher::new` has fixed keys, a key always goes to the same stripe.
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % STRIPES as u64) as usize
+}
+
+#[cfg(test)]
+mod test_key_locks {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn keys(keys: &[&str]) -> Footprint {
+        Footprint::Keys(keys.iter().map(|key| Bytes::from(key.to_string())).collect())
+    }
+
+    #[tokio::test]
+    async fn test_lock_keys() {
+        let locks = KeyLocks::new();
+        let key = "key";
+        let other = (0..)
+            .map(|i| format!("other{}", i))
+            .find(|other| stripe(&Bytes::from(other.clone())) != stripe(&Bytes::from(key)))
+            .unwrap();
+        let other = other.as_str();
+        let _guard = locks.lock(&keys(&[key]), true).await;
+        let wait = Duration::from_millis(10);
+        assert!(timeout(wait, locks.lock(&keys(&[key]), false)).await.is_err());
+        assert!(timeout(wait, locks.lock(&keys(&[other]), true)).await.is_ok());
+        // The same key twice locks its stripe once.
+        assert!(timeout(wait, locks.lock(&keys(&[other, other]), true)).await.is_ok());
+        assert!(timeout(wait, locks.lock(&Footprint::Keys(vec![]), true)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_lock_all() {
+        let locks = KeyLocks::new();
+        let shared = locks.lock(&keys(&["a"]), false).await;
+        let wait = Duration::from_millis(10);
+        assert!(timeout(wait, locks.lock(&keys(&["a", "b"]), false)).await.is_ok());
+        assert!(timeout(wait, locks.lock(&Footprint::All, true)).await.is_err());
+        drop(shared);
+        let _exclusive = locks.lock(&Footprint::All, true).await;
+        assert!(timeout(wait, locks.lock(&keys(&["b"]), false)).await.is_err());
+        assert!(timeout(wait, locks.lock(&Footprint::Keys(vec![]), false)).await.is_ok());
+    }
+}
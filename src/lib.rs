@@ -1,13 +1,22 @@
+mod aof;
+mod client;
 mod cmd;
+mod config;
 mod connection;
 mod db;
 mod frame;
+mod glob;
+mod key_locks;
 mod parse;
 mod server;
+mod shutdown;
+mod stats;
+mod time_util;
 
 use crate::parse::ParseError;
 use anyhow::anyhow;
-pub use server::run;
+pub use config::Config;
+pub use server::{run, run_with_config, run_with_shutdown};
 
 /// Error type for this crate
 ///
@@ -32,5 +41,20 @@ impl From<ParseError> for anyhow::Error {
     }
 }
 
+/// Hooks for the benchmarks in `benches/`, not part of the public API.
+#[doc(hidden)]
+pub mod bench {
+    use crate::cmd::Command;
+    use crate::frame::Frame;
+    use std::io::Cursor;
+
+    /// Parse a RESP encoded command and build the matching command, without applying it.
+    pub fn parse_command(src: &[u8]) -> crate::Result<()> {
+        let frame = Frame::parse(&mut Cursor::new(src))?;
+        Command::from_frame(frame)?;
+        Ok(())
+    }
+}
+
 /// A Result type for this crate
 pub type Result<T> = std::result::Result<T, Error>;
@@ -2,11 +2,15 @@
 
 use crate::frame::Frame;
 use anyhow::anyhow;
+use bytes::Bytes;
 use std::{str, vec};
 
 #[derive(Debug)]
 pub(crate) struct Parse {
     blocks: vec::IntoIter<Frame>,
+    /// Number of blocks returned so far, the command name included.
+    /// It is used to point the client to the offending argument in error messages.
+    consumed: usize,
 }
 #[derive(Debug)]
 pub(crate) enum ParseError {
@@ -26,12 +30,30 @@ impl Parse {
         };
         Ok(Parse {
             blocks: array.into_iter(),
+            consumed: 0,
         })
     }
 
     /// Return the next block
     fn next(&mut self) -> Result<Frame, ParseError> {
-        self.blocks.next().ok_or(ParseError::EndOfStream)
+        let frame = self.blocks.next().ok_or(ParseError::EndOfStream)?;
+        self.consumed += 1;
+        Ok(frame)
+    }
+
+    /// Number of blocks in the frame, the command name included.
+    pub(crate) fn len(&self) -> usize {
+        self.consumed + self.blocks.len()
+    }
+
+    /// Position of the last returned block, the command name is at 0.
+    pub(crate) fn position(&self) -> usize {
+        self.consumed.saturating_sub(1)
+    }
+
+    /// Build a syntax error pointing at the last returned block, e.g. an unknown option.
+    pub(crate) fn syntax_error(&self) -> ParseError {
+        format!("syntax error near argument {}", self.position()).into()
     }
 
     /// Return the next block as a string
@@ -40,23 +62,60 @@ impl Parse {
             Frame::Simple(s) => Ok(s),
             Frame::Bulk(b) => str::from_utf8(&b[..])
                 .map(|s| s.to_string())
-                .map_err(|_| "protocol error; invalid string".into()),
-            frame => Err(format!("protocol error; expected simple or bulk, got {:?}", frame).into()),
+                .map_err(|_| format!("protocol error; invalid string at argument {}", self.position()).into()),
+            frame => Err(format!(
+                "protocol error; expected simple or bulk at argument {}, got {:?}",
+                self.position(),
+                frame
+            )
+            .into()),
+        }
+    }
+
+    /// Return all the remaining blocks as strings, e.g. the keys of a variadic command
+    pub(crate) fn remaining_strings(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut strings = Vec::with_capacity(self.blocks.len());
+        while self.blocks.len() > 0 {
+            strings.push(self.next_string()?);
         }
+        Ok(strings)
     }
 
-    // /// Return the next block as raw bytes
-    // pub(crate) fn next_bytes(&mut self) -> Result<Bytes, ParseError> {
-    //     match self.next()? {
-    //         Frame::Bulk(b) => Ok(b),
-    //         frame => Err(format!("protocol error; expected bulk, got {:?}", frame).into()),
-    //     }
-    // }
+    /// Return all the remaining blocks as raw bytes, e.g. the values of a variadic command
+    pub(crate) fn remaining_bytes(&mut self) -> Result<Vec<Bytes>, ParseError> {
+        let mut values = Vec::with_capacity(self.blocks.len());
+        while self.blocks.len() > 0 {
+            values.push(self.next_bytes()?);
+        }
+        Ok(values)
+    }
+
+    /// Return the next block as raw bytes
+    pub(crate) fn next_bytes(&mut self) -> Result<Bytes, ParseError> {
+        match self.next()? {
+            Frame::Simple(s) => Ok(Bytes::from(s)),
+            Frame::Bulk(b) => Ok(b),
+            frame => Err(format!(
+                "protocol error; expected simple or bulk at argument {}, got {:?}",
+                self.position(),
+                frame
+            )
+            .into()),
+        }
+    }
 
     /// Return the next block as an integer
     pub(crate) fn next_int(&mut self) -> Result<u64, ParseError> {
         let s = self.next_string()?;
-        s.parse::<u64>().map_err(|_| "protocol error; invalid number".into())
+        s.parse::<u64>()
+            .map_err(|_| format!("protocol error; invalid number at argument {}", self.position()).into())
+    }
+
+    /// Return the next block as a signed integer, e.g. a negative index
+    pub(crate) fn next_signed_int(&mut self) -> Result<i64, ParseError> {
+        let s = self.next_string()?;
+        s.parse::<i64>()
+            .map_err(|_| format!("protocol error; invalid number at argument {}", self.position()).into())
     }
 
     /// Check if there are any remaining blocks
@@ -104,11 +163,40 @@ mod test_parse {
         let block = parse.next();
         assert!(block.is_ok());
         // err can not impl PartialEq
-        match parse.next() {
-            Ok(_) => assert!(false),
-            Err(ParseError::EndOfStream) => assert!(true),
-            Err(_) => assert!(false),
+        assert!(matches!(parse.next(), Err(ParseError::EndOfStream)));
+    }
+
+    #[test]
+    fn test_remaining_strings() {
+        let frame = Frame::Array(vec![
+            Frame::Bulk("DEL".into()),
+            Frame::Bulk("foo".into()),
+            Frame::Simple("bar".into()),
+        ]);
+        let mut parse = Parse::new(frame).unwrap();
+        parse.next_string().unwrap();
+        assert_eq!(parse.remaining_strings().unwrap(), vec!["foo", "bar"]);
+        assert!(parse.remaining_strings().unwrap().is_empty());
+        assert!(parse.finish().is_ok());
+    }
+
+    #[test]
+    fn test_error_position() {
+        let frame = Frame::Array(vec![
+            Frame::Bulk("GETRANGE".into()),
+            Frame::Bulk("foo".into()),
+            Frame::Bulk("abc".into()),
+        ]);
+        let mut parse = Parse::new(frame).unwrap();
+        assert_eq!(parse.next_string().unwrap(), "GETRANGE");
+        assert_eq!(parse.position(), 0);
+        parse.next_string().unwrap();
+        match parse.next_signed_int() {
+            Err(ParseError::Other(err)) => assert_eq!(err.to_string(), "protocol error; invalid number at argument 2"),
+            _ => panic!("expected an invalid number error"),
         }
+        let err: crate::Error = parse.syntax_error().into();
+        assert_eq!(err.to_string(), "syntax error near argument 2");
     }
 }
 
@@ -129,10 +217,7 @@ mod test_from_string_trait {
     #[test]
     fn test_from_string() {
         let error = create_parse_error();
-        match error {
-            ParseError::Other(_) => assert!(true),
-            _ => assert!(false),
-        }
+        assert!(matches!(error, ParseError::Other(_)));
     }
 }
 
@@ -153,9 +238,6 @@ mod test_from_str_trait {
     #[test]
     fn test_from_str() {
         let error = create_parse_error();
-        match error {
-            ParseError::Other(_) => assert!(true),
-            _ => assert!(false),
-        }
+        assert!(matches!(error, ParseError::Other(_)));
     }
 }
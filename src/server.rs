@@ -1,7 +1,23 @@
-use crate::cmd::Command;
-use crate::connection::Connection;
+use crate::aof::{self, Aof};
+use crate::client::Client;
+use crate::cmd::{self, Command, Transaction};
+use crate::config::{Config, Params};
+use crate::connection::{self, Connection};
 use crate::db::{Db, DbGuard};
+use crate::frame::Frame;
+use crate::key_locks::{Footprint, KeyLocks};
+use crate::shutdown::Shutdown;
+use crate::stats::Stats;
+use socket2::{SockRef, TcpKeepalive};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::time;
+use tracing::{debug, error, info, instrument, warn};
 
 /// Server listener state. Created in the [run] function.
 /// It is used to accept new connections, and some other server-wide tasks,
@@ -10,55 +26,494 @@ use tokio::net::{TcpListener, TcpStream};
 struct Server {
     listener: TcpListener,
     db_guard: DbGuard,
+    config: Config,
+    stats: Arc<Stats>,
+    /// See [Params], shared by all the connections.
+    params: Arc<Params>,
+    /// See [Config::aof_path], shared by all the connections.
+    aof: Option<Arc<Aof>>,
+    /// Locked on the keys of a command while it runs, and on all the keys while the commands of a
+    /// transaction run, so they don't interleave with the commands of other clients.
+    key_locks: Arc<KeyLocks>,
+    /// Limits the number of connections, a permit is held by each `Handler` task.
+    limit_connections: Arc<Semaphore>,
+    /// Tells every connection to shut down, each `Handler` holds a receiver.
+    notify_shutdown: broadcast::Sender<()>,
+    /// Cloned into every `Handler`. Once all the clones are dropped, the receiving end knows all
+    /// the connections are done.
+    shutdown_complete_tx: mpsc::Sender<()>,
 }
 
 #[derive(Debug)]
 struct Handler {
-    db: Db,
+    /// All the databases, the client picks one with `SELECT`.
+    dbs: Vec<Db>,
+    stats: Arc<Stats>,
+    params: Arc<Params>,
+    /// Where the write commands are logged, if enabled.
+    aof: Option<Arc<Aof>>,
+    /// See [Server::key_locks].
+    key_locks: Arc<KeyLocks>,
     connection: Connection,
+    /// State of the client connected to this handler.
+    client: Client,
+    /// Whether the client may run commands, it must send `AUTH` first if `requirepass` is set.
+    authenticated: bool,
+    /// Fires when the server shuts down, checked between commands.
+    shutdown: Shutdown,
+    /// See [Config::idle_timeout].
+    idle_timeout: Option<Duration>,
+    /// The commands queued since `MULTI`, if the client is in a transaction.
+    transaction: Option<Transaction>,
+    /// Dropped along with the handler, see [Server::shutdown_complete_tx].
+    _shutdown_complete: mpsc::Sender<()>,
 }
 
+/// Run the server with the default [Config].
 pub async fn run(listener: TcpListener) {
+    run_with_config(listener, Config::default()).await
+}
+
+/// Run the server until the process exits.
+pub async fn run_with_config(listener: TcpListener, config: Config) {
+    run_with_shutdown(listener, config, std::future::pending::<()>()).await
+}
+
+/// Run the server until `shutdown` completes, e.g. `tokio::signal::ctrl_c()`.
+///
+/// Then the server stops accepting connections, and returns once every connection is closed.
+/// Connections finish the command they are running, if any.
+pub async fn run_with_shutdown(listener: TcpListener, config: Config, shutdown: impl Future) {
+    let db_guard = DbGuard::new(config.databases);
+    let params = Arc::new(Params::new(&config));
+    let aof = match &config.aof_path {
+        Some(path) => match Aof::open(path, &db_guard.dbs(), &params, &config.frame_limits()).await {
+            Ok(aof) => Some(Arc::new(aof)),
+            Err(err) => {
+                // Serving without the data, or without logging the writes, would lose data.
+                error!(path = %path.display(), cause = ?err, "failed to load the append-only file");
+                return;
+            }
+        },
+        None => None,
+    };
+    if let Some(aof) = &aof {
+        tokio::spawn(aof::sync_periodically(Arc::downgrade(aof)));
+    }
+    let (notify_shutdown, _) = broadcast::channel(1);
+    let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
     let mut server = Server {
         listener,
-        db_guard: DbGuard::new(),
+        db_guard,
+        stats: Arc::new(Stats::new()),
+        params,
+        aof,
+        key_locks: Arc::new(KeyLocks::new()),
+        limit_connections: Arc::new(Semaphore::new(config.max_connections)),
+        config,
+        notify_shutdown,
+        shutdown_complete_tx,
     };
 
-    server.run().await;
+    tokio::select! {
+        _ = server.run() => {}
+        _ = shutdown => {}
+    }
+
+    let Server {
+        listener,
+        notify_shutdown,
+        shutdown_complete_tx,
+        aof,
+        ..
+    } = server;
+    // Stop accepting, then tell the connections to close and wait until they are all gone.
+    drop(listener);
+    drop(notify_shutdown);
+    drop(shutdown_complete_tx);
+    let _ = shutdown_complete_rx.recv().await;
+    // Nothing is written anymore, the last writes are not left to the periodic sync.
+    if let Some(Err(err)) = aof.map(|aof| aof.sync()) {
+        error!(cause = ?err, "failed to sync the append-only file");
+    }
 }
 
 impl Server {
     async fn run(&mut self) {
         loop {
-            let stream = self.accept().await;
+            // Wait for a connection to close if the limit is reached. The semaphore is never closed.
+            let permit = self.limit_connections.clone().acquire_owned().await.unwrap();
+            let (stream, addr) = self.accept().await;
+            debug!(peer = %addr, "accepted connection");
             let mut handler = Handler {
-                db: self.db_guard.db(),
-                connection: Connection::new(stream),
+                dbs: self.db_guard.dbs(),
+                stats: self.stats.clone(),
+                params: self.params.clone(),
+                aof: self.aof.clone(),
+                key_locks: self.key_locks.clone(),
+                connection: Connection::new(stream, self.config.frame_limits()),
+                client: Client::new(addr),
+                authenticated: self.params.requirepass().is_none(),
+                shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
+                idle_timeout: self.config.idle_timeout,
+                transaction: None,
+                _shutdown_complete: self.shutdown_complete_tx.clone(),
             };
             tokio::spawn(async move {
+                handler.stats.client_connected();
                 if let Err(err) = handler.run().await {
-                    eprintln!("Error: {:?}", err);
+                    error!(peer = %addr, cause = ?err, "connection error");
                 }
+                handler.stats.client_disconnected();
+                // Let another connection in, once this one is done.
+                drop(permit);
             });
         }
     }
 
-    async fn accept(&mut self) -> TcpStream {
+    async fn accept(&mut self) -> (TcpStream, SocketAddr) {
         // TODO handle error
-        self.listener.accept().await.unwrap().0
+        let (stream, addr) = self.listener.accept().await.unwrap();
+        if let Err(err) = self.configure(&stream) {
+            warn!(peer = %addr, cause = ?err, "failed to set socket options");
+        }
+        (stream, addr)
+    }
+
+    /// Set the socket options of an accepted connection.
+    fn configure(&self, stream: &TcpStream) -> io::Result<()> {
+        // Replies are small, don't let Nagle's algorithm delay them.
+        stream.set_nodelay(true)?;
+        if let Some(interval) = self.config.tcp_keepalive {
+            let keepalive = TcpKeepalive::new().with_time(interval).with_interval(interval);
+            SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
     }
 }
 
 impl Handler {
+    /// Serve the client until it disconnects, or the server shuts down.
+    ///
+    /// Everything logged while serving the client is in the `connection` span, tagged with its address.
+    #[instrument(name = "connection", skip(self), fields(peer = %self.client.addr()))]
     async fn run(&mut self) -> crate::Result<()> {
-        loop {
-            let maybe_frame = self.connection.read_frame().await?;
+        while !self.shutdown.is_shutdown() {
+            // Pipelined commands are already buffered, their replies are only flushed once they are
+            // all applied, before waiting for the client.
+            let read = match self.connection.read_buffered_frame() {
+                Ok(Some(frame)) => Ok(Some(Some(frame))),
+                Ok(None) => {
+                    self.connection.flush().await?;
+                    tokio::select! {
+                        read = read_frame(&mut self.connection, self.idle_timeout) => read,
+                        // The server is shutting down, the connection is between two commands.
+                        _ = self.shutdown.recv() => return Ok(()),
+                    }
+                }
+                Err(err) => Err(err),
+            };
+            let maybe_frame = match read {
+                Ok(Some(maybe_frame)) => maybe_frame,
+                Ok(None) => {
+                    info!(idle_timeout = ?self.idle_timeout.unwrap_or_default(), "closing idle connection");
+                    return Ok(());
+                }
+                // The client went away, treat it as a normal close.
+                Err(err) if connection::is_disconnect(&err) => {
+                    debug!("client disconnected");
+                    return Ok(());
+                }
+                Err(err) => {
+                    // The stream can't be trusted after a protocol error, tell the client why and close it.
+                    let _ = self.connection.write_frame(&Frame::Error(format!("ERR {}", err))).await;
+                    return Err(err);
+                }
+            };
             let frame = match maybe_frame {
                 Some(frame) => frame,
-                None => return Ok(()),
+                None => {
+                    debug!("client closed the connection");
+                    return Ok(());
+                }
+            };
+            cmd::feed_monitors(&self.dbs[self.client.db], &frame, &self.client);
+            // The write commands are logged as received, the frame is consumed to build the command.
+            let logged = self.aof.is_some().then(|| frame.clone());
+            let footprint = cmd::footprint(&frame);
+            let cmd = match Command::from_frame(frame) {
+                Ok(cmd) => cmd,
+                Err(err) => {
+                    // The command is invalid, but the stream is fine, so keep serving the client.
+                    debug!(cause = %err, "invalid command");
+                    if let Some(transaction) = &mut self.transaction {
+                        transaction.fail();
+                    }
+                    self.connection
+                        .feed_frame(&Frame::Error(format!("ERR {}", err)))
+                        .await?;
+                    continue;
+                }
             };
-            let cmd = Command::from_frame(frame);
-            cmd?.apply(&self.db, &mut self.connection).await?;
+            if let Command::Quit(cmd) = cmd {
+                // Even in a transaction, or before AUTH. The replies fed so far are sent along.
+                let reply = cmd.apply().await?;
+                self.connection.write_frame(&reply).await?;
+                debug!("client quit");
+                return Ok(());
+            }
+            if !self.authenticated && !matches!(cmd, Command::Auth(_)) {
+                self.connection
+                    .feed_frame(&Frame::Error("NOAUTH Authentication required.".to_string()))
+                    .await?;
+                continue;
+            }
+            let logged = logged.filter(|_| cmd.is_write());
+            self.apply(cmd, logged, footprint).await?;
+            if std::mem::take(&mut self.client.was_reset) {
+                // RESET logs the client out.
+                self.authenticated = self.params.requirepass().is_none();
+            }
+            if self.client.closing {
+                return Ok(());
+            }
+        }
+        // Shutting down, the replies already fed are still sent.
+        self.connection.flush().await?;
+        Ok(())
+    }
+
+    /// Apply `cmd`, or queue it if the client is in a transaction. `frame` is the command to log to
+    /// the append-only file once applied, if any, and `footprint` the keys it applies to.
+    async fn apply(&mut self, cmd: Command, frame: Option<Frame>, footprint: Footprint) -> crate::Result<()> {
+        let reply = match cmd {
+            Command::Multi(_) if self.transaction.is_some() => {
+                Frame::Error("ERR MULTI calls can not be nested".to_string())
+            }
+            Command::Multi(_) => {
+                self.transaction = Some(Transaction::default());
+                Frame::Simple("OK".to_string())
+            }
+            Command::Exec(_) => match self.transaction.take().map(Transaction::into_commands) {
+                Some(Ok(commands)) => return self.exec(commands).await,
+                Some(Err(reply)) => reply,
+                None => Frame::Error("ERR EXEC without MULTI".to_string()),
+            },
+            Command::Discard(_) => match self.transaction.take() {
+                Some(_) => Frame::Simple("OK".to_string()),
+                None => Frame::Error("ERR DISCARD without MULTI".to_string()),
+            },
+            Command::Auth(cmd) => cmd.apply(&self.params, &mut self.authenticated).await?,
+            // Not queued, it discards the transaction along with the rest of the connection state.
+            Command::Reset(_) => {
+                self.transaction = None;
+                return self.run_command(cmd, frame, footprint).await;
+            }
+            cmd => match &mut self.transaction {
+                Some(transaction) => transaction.queue(cmd, frame),
+                None => return self.run_command(cmd, frame, footprint).await,
+            },
+        };
+        self.connection.feed_frame(&reply).await?;
+        Ok(())
+    }
+
+    /// Run the commands of a transaction in order, and reply with their replies in one array. No
+    /// command of another client runs in the meantime.
+    async fn exec(&mut self, commands: Vec<(Command, Option<Frame>)>) -> crate::Result<()> {
+        let key_locks = self.key_locks.clone();
+        let exclusive = key_locks.lock(&Footprint::All, true).await;
+        let mut replies = Vec::with_capacity(commands.len());
+        for (cmd, frame) in commands {
+            replies.push(self.execute(cmd, frame).await?);
+        }
+        // The other clients don't wait for the replies to be sent.
+        drop(exclusive);
+        self.connection.feed_frame(&Frame::Array(replies)).await?;
+        Ok(())
+    }
+
+    async fn run_command(&mut self, cmd: Command, frame: Option<Frame>, footprint: Footprint) -> crate::Result<()> {
+        if cmd.is_connection_bound() {
+            return cmd
+                .apply(
+                    &self.dbs,
+                    &self.stats,
+                    &mut self.connection,
+                    &mut self.client,
+                    &mut self.shutdown,
+                )
+                .await;
+        }
+        // A command without keys, e.g. `DEBUG SLEEP`, doesn't wait for a transaction, nor holds it up.
+        // A logged write holds its keys until appended, so the file has the writes of a key in the
+        // order they were applied.
+        let exclusive = self.aof.is_some() && cmd.is_write();
+        let key_locks = self.key_locks.clone();
+        let guard = key_locks.lock(&footprint, exclusive).await;
+        let reply = self.execute(cmd, frame).await?;
+        drop(guard);
+        // Flushed once the commands of a pipeline are all applied.
+        self.connection.feed_frame(&reply).await?;
+        Ok(())
+    }
+
+    /// Execute `cmd` and return its reply. A write is logged to the append-only file before the
+    /// reply is sent, unless it failed.
+    async fn execute(&mut self, cmd: Command, frame: Option<Frame>) -> crate::Result<Frame> {
+        // The database the command applies to, before a SELECT changes it.
+        let db = self.client.db;
+        let reply = cmd
+            .execute(&self.dbs, &self.stats, &self.params, &mut self.client)
+            .await?;
+        if let (Some(aof), Some(frame)) = (&self.aof, frame) {
+            // A failed write, e.g. against a key of another type, changed nothing.
+            if !matches!(reply, Frame::Error(_)) {
+                if let Err(err) = aof.append(db, &frame) {
+                    // The write is applied but won't survive a restart, the client is told so.
+                    error!(cause = ?err, "failed to write to the append-only file");
+                    return Ok(Frame::Error(format!("MISCONF Errors writing to the AOF file: {}", err)));
+                }
+            }
+        }
+        Ok(reply)
+    }
+}
+
+/// Read a frame, giving up once `idle_timeout` elapsed. `Ok(None)` means the timeout elapsed.
+async fn read_frame(
+    connection: &mut Connection,
+    idle_timeout: Option<Duration>,
+) -> crate::Result<Option<Option<Frame>>> {
+    match idle_timeout {
+        Some(idle_timeout) => match time::timeout(idle_timeout, connection.read_frame()).await {
+            Ok(read) => read.map(Some),
+            Err(_elapsed) => Ok(None),
+        },
+        None => connection.read_frame().await.map(Some),
+    }
+}
+
+#[cfg(test)]
+mod test_server {
+    use super::*;
+    use std::time::Duration;
+
+    async fn server(config: Config) -> Server {
+        Server {
+            listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            db_guard: DbGuard::new(config.databases),
+            stats: Arc::new(Stats::new()),
+            params: Arc::new(Params::new(&config)),
+            aof: None,
+            key_locks: Arc::new(KeyLocks::new()),
+            limit_connections: Arc::new(Semaphore::new(config.max_connections)),
+            config,
+            notify_shutdown: broadcast::channel(1).0,
+            shutdown_complete_tx: mpsc::channel(1).0,
         }
     }
+
+    #[tokio::test]
+    async fn test_accept_socket_options() {
+        let mut server = server(Config {
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            ..Config::default()
+        })
+        .await;
+        let _client = TcpStream::connect(server.listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = server.accept().await;
+        assert!(stream.nodelay().unwrap());
+        let socket = SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_accept_without_keepalive() {
+        let mut server = server(Config {
+            tcp_keepalive: None,
+            ..Config::default()
+        })
+        .await;
+        let _client = TcpStream::connect(server.listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = server.accept().await;
+        assert!(stream.nodelay().unwrap());
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod test_handler {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A handler serving `client`, the sender shuts the handler down when it's used or dropped.
+    async fn handler_pair() -> (Handler, TcpStream, broadcast::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        let (notify_shutdown, _) = broadcast::channel(1);
+        let handler = Handler {
+            dbs: DbGuard::new(1).dbs(),
+            stats: Arc::new(Stats::new()),
+            params: Arc::new(Params::new(&Config::default())),
+            aof: None,
+            key_locks: Arc::new(KeyLocks::new()),
+            connection: Connection::new(stream, Config::default().frame_limits()),
+            client: Client::new(addr),
+            authenticated: true,
+            shutdown: Shutdown::new(notify_shutdown.subscribe()),
+            idle_timeout: None,
+            transaction: None,
+            _shutdown_complete: mpsc::channel(1).0,
+        };
+        (handler, client, notify_shutdown)
+    }
+
+    #[tokio::test]
+    async fn test_client_closed_mid_frame() {
+        let (mut handler, mut client, _notify_shutdown) = handler_pair().await;
+        client.write_all(b"*1\r\n$4\r\nPI").await.unwrap();
+        drop(client);
+        assert!(
+            handler.run().await.is_ok(),
+            "a reset should not be reported as an error"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_multibulk() {
+        let (mut handler, mut client, _notify_shutdown) = handler_pair().await;
+        client.write_all(b"*1000000000\r\n$4\r\nPING\r\n").await.unwrap();
+        assert!(handler.run().await.is_err());
+        drop(handler);
+
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).await.unwrap();
+        assert_eq!(reply, "-ERR protocol error; invalid multibulk length\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_between_commands() {
+        let (mut handler, mut client, notify_shutdown) = handler_pair().await;
+        let task = tokio::spawn(async move { handler.run().await });
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let mut reply = [0; 7];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+
+        drop(notify_shutdown);
+        assert!(task.await.unwrap().is_ok());
+        // The handler is gone, and the connection with it.
+        assert_eq!(client.read(&mut reply).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_client_closed_between_frames() {
+        let (mut handler, client, _notify_shutdown) = handler_pair().await;
+        drop(client);
+        assert!(handler.run().await.is_ok());
+    }
 }
@@ -0,0 +1,51 @@
+use tokio::sync::broadcast;
+
+/// Listens for the server shutdown signal.
+///
+/// The signal is sent once, on a `broadcast` channel shared by every connection. Once it has been
+/// received, [Shutdown::is_shutdown] stays `true`.
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    is_shutdown: bool,
+    notify: broadcast::Receiver<()>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(notify: broadcast::Receiver<()>) -> Self {
+        Shutdown {
+            is_shutdown: false,
+            notify,
+        }
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.is_shutdown
+    }
+
+    /// Wait for the shutdown signal, return immediately if it was already received.
+    pub(crate) async fn recv(&mut self) {
+        if self.is_shutdown {
+            return;
+        }
+        // Dropping the sender counts as a signal too, so there's no error to handle.
+        let _ = self.notify.recv().await;
+        self.is_shutdown = true;
+    }
+}
+
+#[cfg(test)]
+mod test_shutdown {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recv() {
+        let (tx, rx) = broadcast::channel(1);
+        let mut shutdown = Shutdown::new(rx);
+        assert!(!shutdown.is_shutdown());
+        drop(tx);
+        shutdown.recv().await;
+        assert!(shutdown.is_shutdown());
+        // Already received, returns right away.
+        shutdown.recv().await;
+    }
+}
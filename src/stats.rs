@@ -0,0 +1,83 @@
+//! Server-wide statistics, reported by INFO.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub(crate) struct Stats {
+    started_at: Instant,
+    connected_clients: AtomicUsize,
+    /// Number of calls of each command, by name.
+    command_calls: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Self {
+        Stats {
+            started_at: Instant::now(),
+            connected_clients: AtomicUsize::new(0),
+            command_calls: Mutex::default(),
+        }
+    }
+
+    /// Time since the server started.
+    pub(crate) fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub(crate) fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    /// Count a new client, until the matching [Stats::client_disconnected].
+    pub(crate) fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Count a call of the command `name`.
+    pub(crate) fn record_call(&self, name: &'static str) {
+        *self.command_calls.lock().unwrap().entry(name).or_default() += 1;
+    }
+
+    /// Number of calls of each command called at least once, sorted by name.
+    pub(crate) fn command_calls(&self) -> Vec<(&'static str, u64)> {
+        let mut calls: Vec<_> = self
+            .command_calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .collect();
+        calls.sort_unstable();
+        calls
+    }
+}
+
+#[cfg(test)]
+mod test_stats {
+    use super::*;
+
+    #[test]
+    fn test_connected_clients() {
+        let stats = Stats::new();
+        stats.client_connected();
+        stats.client_connected();
+        stats.client_disconnected();
+        assert_eq!(stats.connected_clients(), 1);
+    }
+
+    #[test]
+    fn test_command_calls() {
+        let stats = Stats::new();
+        stats.record_call("set");
+        stats.record_call("get");
+        stats.record_call("set");
+        assert_eq!(stats.command_calls(), vec![("get", 1), ("set", 2)]);
+    }
+}
@@ -0,0 +1,87 @@
+//! Conversions between the monotonic clock used for expirations and Unix time.
+//!
+//! Clients can send any timestamp or TTL, so all conversions saturate instead of panicking on
+//! out-of-range values.
+
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Deadlines further than this are clamped, it is long enough to mean "never" while staying
+/// representable by `Instant` on every platform.
+pub(crate) const MAX_TTL: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// An `Instant` and the Unix time in milliseconds captured together the first time it's needed.
+///
+/// All conversions go through this single pair, so they are consistent with each other.
+fn anchor() -> (Instant, i64) {
+    static ANCHOR: OnceLock<(Instant, i64)> = OnceLock::new();
+    *ANCHOR.get_or_init(|| {
+        let unix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        (Instant::now(), millis(unix))
+    })
+}
+
+/// Convert a duration to milliseconds, saturating at `i64::MAX`.
+fn millis(d: Duration) -> i64 {
+    i64::try_from(d.as_millis()).unwrap_or(i64::MAX)
+}
+
+/// The instant at which something living for `ttl` expires, clamped to [MAX_TTL].
+pub(crate) fn deadline(ttl: Duration) -> Instant {
+    Instant::now() + ttl.min(MAX_TTL)
+}
+
+/// Convert a Unix time in milliseconds to an `Instant`.
+///
+/// Returns `None` if the time is not in the future, i.e. a key with this deadline is already expired.
+pub(crate) fn unix_ms_to_instant(ms: i64) -> Option<Instant> {
+    let now = instant_to_unix_ms(Instant::now());
+    if ms <= now {
+        return None;
+    }
+    Some(deadline(Duration::from_millis(ms.saturating_sub(now) as u64)))
+}
+
+/// Convert an `Instant` to a Unix time in milliseconds.
+pub(crate) fn instant_to_unix_ms(when: Instant) -> i64 {
+    let (instant, unix_ms) = anchor();
+    if when >= instant {
+        unix_ms.saturating_add(millis(when - instant))
+    } else {
+        unix_ms.saturating_sub(millis(instant - when))
+    }
+}
+
+#[cfg(test)]
+mod test_time_util {
+    use super::*;
+
+    fn unix_ms_now() -> i64 {
+        millis(SystemTime::now().duration_since(UNIX_EPOCH).unwrap())
+    }
+
+    #[test]
+    fn test_far_future() {
+        let when = unix_ms_to_instant(i64::MAX).unwrap();
+        assert!(when > Instant::now() + Duration::from_secs(50 * 365 * 24 * 60 * 60));
+        assert!(instant_to_unix_ms(when) > unix_ms_now());
+        // A huge TTL does not overflow either.
+        assert!(deadline(Duration::MAX) > Instant::now());
+    }
+
+    #[test]
+    fn test_past() {
+        assert_eq!(unix_ms_to_instant(unix_ms_now() - 1000), None);
+        assert_eq!(unix_ms_to_instant(0), None);
+        assert_eq!(unix_ms_to_instant(i64::MIN), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let ms = unix_ms_now() + 10_000;
+        let when = unix_ms_to_instant(ms).unwrap();
+        assert!((instant_to_unix_ms(when) - ms).abs() <= 5);
+        assert!((instant_to_unix_ms(Instant::now()) - unix_ms_now()).abs() <= 5);
+    }
+}
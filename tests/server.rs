@@ -0,0 +1,1929 @@
+use my_redis::{run, run_with_config, run_with_shutdown, Config};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Start a server on a random port and return its address.
+async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(run(listener));
+    addr
+}
+
+/// Read a single `\r\n` terminated line from the server.
+async fn read_line(stream: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    line
+}
+
+async fn connect(addr: SocketAddr) -> BufReader<TcpStream> {
+    BufReader::new(TcpStream::connect(addr).await.unwrap())
+}
+
+/// Send a command, encoded as an array of bulk strings.
+async fn send(stream: &mut BufReader<TcpStream>, args: &[&str]) {
+    let mut buf = format!("*{}\r\n", args.len());
+    for arg in args {
+        buf.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    stream.write_all(buf.as_bytes()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_monitor() {
+    let addr = start_server().await;
+    let mut monitor = connect(addr).await;
+    monitor.write_all(b"*1\r\n$7\r\nMONITOR\r\n").await.unwrap();
+    assert_eq!(read_line(&mut monitor).await, "+OK\r\n");
+
+    let mut client = connect(addr).await;
+    client
+        .write_all(b"*3\r\n$3\r\nset\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+
+    let line = read_line(&mut monitor).await;
+    assert!(line.starts_with('+'), "{}", line);
+    let client_addr = client.get_ref().local_addr().unwrap();
+    assert!(
+        line.ends_with(&format!("[0 {}] \"set\" \"foo\" \"bar\"\r\n", client_addr)),
+        "{}",
+        line
+    );
+}
+
+#[tokio::test]
+async fn test_set_absolute_expire() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    // Far in the future, the key is kept.
+    client
+        .write_all(b"*5\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$4\r\nPXAT\r\n$19\r\n9223372036854775807\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    client.write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n").await.unwrap();
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "bar\r\n");
+
+    // In the past, the key expires right away.
+    client
+        .write_all(b"*5\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$4\r\nEXAT\r\n$1\r\n1\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    client.write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n").await.unwrap();
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+}
+
+#[tokio::test]
+async fn test_client_info() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    client
+        .write_all(b"*3\r\n$6\r\nCLIENT\r\n$7\r\nSETNAME\r\n$5\r\nmyapp\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+
+    client.write_all(b"*2\r\n$6\r\nCLIENT\r\n$2\r\nID\r\n").await.unwrap();
+    let id = read_line(&mut client).await;
+    let id = id.trim_start_matches(':').trim_end();
+
+    client.write_all(b"*2\r\n$6\r\nCLIENT\r\n$4\r\nINFO\r\n").await.unwrap();
+    assert!(read_line(&mut client).await.starts_with('$'));
+    let info = read_line(&mut client).await;
+    assert!(info.starts_with(&format!("id={} ", id)), "{}", info);
+    assert!(info.contains(" name=myapp "), "{}", info);
+    // The line ends with a newline inside the bulk string.
+    assert_eq!(read_line(&mut client).await, "\r\n");
+
+    client
+        .write_all(b"*3\r\n$6\r\nCLIENT\r\n$8\r\nNO-EVICT\r\n$2\r\non\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    client
+        .write_all(b"*2\r\n$6\r\nCLIENT\r\n$7\r\nUNPAUSE\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+}
+
+#[tokio::test]
+async fn test_wrong_number_of_arguments() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    client.write_all(b"*1\r\n$3\r\nGET\r\n").await.unwrap();
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR wrong number of arguments for 'get' command\r\n"
+    );
+    client
+        .write_all(b"*3\r\n$4\r\nPING\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR wrong number of arguments for 'ping' command\r\n"
+    );
+
+    // The connection is still usable.
+    client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+}
+
+#[tokio::test]
+async fn test_binary_value() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    client
+        .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$2\r\n\xff\xfe\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    client.write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").await.unwrap();
+    let mut reply = [0u8; 8];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"$2\r\n\xff\xfe\r\n");
+}
+
+#[tokio::test]
+async fn test_del() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    for key in ["a", "b", "c"] {
+        send(&mut client, &["SET", key, "value"]).await;
+        assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    }
+
+    // Present keys.
+    send(&mut client, &["DEL", "a", "b"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    // Absent keys.
+    send(&mut client, &["DEL", "a", "missing"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    // A mix, a key listed twice is only deleted once.
+    send(&mut client, &["DEL", "c", "missing", "c"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+
+    send(&mut client, &["GET", "c"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+}
+
+#[tokio::test]
+async fn test_exists() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "a", "value"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+
+    send(&mut client, &["EXISTS", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    // Duplicates are counted each time.
+    send(&mut client, &["EXISTS", "a", "a", "missing"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    send(&mut client, &["EXISTS", "missing"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_incr_decr() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["INCR", "counter"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["DECR", "counter"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    send(&mut client, &["DECR", "counter"]).await;
+    assert_eq!(read_line(&mut client).await, ":-1\r\n");
+
+    send(&mut client, &["SET", "text", "abc"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["INCR", "text"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR value is not an integer or out of range\r\n"
+    );
+
+    send(&mut client, &["SET", "max", "9223372036854775807"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["INCR", "max"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR value is not an integer or out of range\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_ttl() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["TTL", "missing"]).await;
+    assert_eq!(read_line(&mut client).await, ":-2\r\n");
+
+    send(&mut client, &["SET", "persistent", "value"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["PTTL", "persistent"]).await;
+    assert_eq!(read_line(&mut client).await, ":-1\r\n");
+
+    send(&mut client, &["SET", "volatile", "value", "EX", "100"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["TTL", "volatile"]).await;
+    assert_eq!(read_line(&mut client).await, ":100\r\n");
+    send(&mut client, &["PTTL", "volatile"]).await;
+    let pttl: i64 = read_line(&mut client)
+        .await
+        .trim_start_matches(':')
+        .trim_end()
+        .parse()
+        .unwrap();
+    assert!(pttl > 99_000 && pttl <= 100_000);
+}
+
+#[tokio::test]
+async fn test_expire() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["EXPIRE", "missing", "10"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+
+    send(&mut client, &["SET", "key", "value"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["EXPIRE", "key", "100"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":100\r\n");
+
+    send(&mut client, &["PEXPIRE", "key", "20"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    send(&mut client, &["GET", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+
+    send(&mut client, &["SET", "key", "value"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["EXPIRE", "key", "9223372036854775807"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR invalid expire time in 'expire' command\r\n"
+    );
+    // A negative TTL deletes the key.
+    send(&mut client, &["EXPIRE", "key", "-1"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["EXISTS", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_expire_at() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap();
+
+    send(
+        &mut client,
+        &["EXPIREAT", "missing", &(now.as_secs() + 100).to_string()],
+    )
+    .await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+
+    send(&mut client, &["SET", "key", "value"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["EXPIREAT", "key", &(now.as_secs() + 100).to_string()]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert!(["100", "99"]
+        .map(|ttl| format!(":{}\r\n", ttl))
+        .contains(&read_line(&mut client).await));
+
+    // In the past, the key is deleted.
+    send(&mut client, &["PEXPIREAT", "key", &(now.as_millis() - 1).to_string()]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["EXISTS", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+
+    send(&mut client, &["EXPIREAT", "key", "9223372036854775807"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR invalid expire time in 'expireat' command\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_persist() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "key", "value", "PX", "20"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["PERSIST", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["PERSIST", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    send(&mut client, &["GET", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "value\r\n");
+}
+
+#[tokio::test]
+async fn test_set_nx_xx() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "key", "value", "XX"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    send(&mut client, &["SET", "key", "value", "NX", "PX", "20"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "key", "other", "NX"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+
+    // Once the key expired, NX can set it again.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    send(&mut client, &["SET", "key", "other", "NX"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "key", "last", "XX"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["GET", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "$4\r\n");
+    assert_eq!(read_line(&mut client).await, "last\r\n");
+}
+
+#[tokio::test]
+async fn test_set_get() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "key", "one", "GET"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    send(&mut client, &["SET", "key", "two", "GET"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "one\r\n");
+
+    // NX doesn't overwrite the existing key, but the old value is still returned.
+    send(&mut client, &["SET", "key", "three", "NX", "GET"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "two\r\n");
+    send(&mut client, &["SET", "key", "three", "XX", "GET"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "two\r\n");
+
+    // XX on a missing key doesn't set anything and has no old value.
+    send(&mut client, &["SET", "missing", "value", "XX", "GET"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    send(&mut client, &["SET", "missing", "value", "NX", "GET"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    send(&mut client, &["GET", "missing"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "value\r\n");
+}
+
+#[tokio::test]
+async fn test_set_keep_ttl() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "key", "one", "PX", "10000"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "key", "two", "KEEPTTL"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":10\r\n");
+
+    // Without KEEPTTL the deadline is cleared.
+    send(&mut client, &["SET", "key", "three"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":-1\r\n");
+}
+
+#[tokio::test]
+async fn test_mget() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["MGET", "a", "b"]).await;
+    assert_eq!(read_line(&mut client).await, "*2\r\n");
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+
+    send(&mut client, &["SET", "a", "1"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "b", "2"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["MGET", "b", "a"]).await;
+    assert_eq!(read_line(&mut client).await, "*2\r\n");
+    assert_eq!(read_line(&mut client).await, "$1\r\n");
+    assert_eq!(read_line(&mut client).await, "2\r\n");
+    assert_eq!(read_line(&mut client).await, "$1\r\n");
+    assert_eq!(read_line(&mut client).await, "1\r\n");
+
+    send(&mut client, &["MGET"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR wrong number of arguments for 'mget' command\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_mset() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "a", "old"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["MSET", "a", "1", "b", "2"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["MGET", "a", "b"]).await;
+    assert_eq!(read_line(&mut client).await, "*2\r\n");
+    assert_eq!(read_line(&mut client).await, "$1\r\n");
+    assert_eq!(read_line(&mut client).await, "1\r\n");
+    assert_eq!(read_line(&mut client).await, "$1\r\n");
+    assert_eq!(read_line(&mut client).await, "2\r\n");
+
+    send(&mut client, &["MSET", "a", "1", "b"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR wrong number of arguments for 'mset' command\r\n"
+    );
+    send(&mut client, &["GET", "a"]).await;
+    assert_eq!(read_line(&mut client).await, "$1\r\n");
+    assert_eq!(read_line(&mut client).await, "1\r\n");
+}
+
+#[tokio::test]
+async fn test_append() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["APPEND", "key", "Hello"]).await;
+    assert_eq!(read_line(&mut client).await, ":5\r\n");
+    send(&mut client, &["EXPIRE", "key", "100"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["APPEND", "key", " World"]).await;
+    assert_eq!(read_line(&mut client).await, ":11\r\n");
+    send(&mut client, &["GET", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "$11\r\n");
+    assert_eq!(read_line(&mut client).await, "Hello World\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":100\r\n");
+}
+
+#[tokio::test]
+async fn test_strlen() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["STRLEN", "missing"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    send(&mut client, &["SET", "key", "héllo"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["STRLEN", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":6\r\n");
+}
+
+#[tokio::test]
+async fn test_getdel() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "key", "value", "EX", "100"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["GETDEL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "value\r\n");
+    send(&mut client, &["GETDEL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    send(&mut client, &["EXISTS", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_getex() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "key", "value", "EX", "100"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["GETEX", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "value\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":100\r\n");
+
+    send(&mut client, &["GETEX", "key", "EX", "10"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "value\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":10\r\n");
+
+    send(&mut client, &["GETEX", "key", "PERSIST"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "value\r\n");
+    send(&mut client, &["TTL", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":-1\r\n");
+
+    // The key is kept.
+    send(&mut client, &["GETEX", "key", "EX", "0"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR invalid expire time in 'getex' command\r\n"
+    );
+    send(&mut client, &["EXISTS", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+}
+
+#[tokio::test]
+async fn test_type() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["TYPE", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "+none\r\n");
+    send(&mut client, &["SET", "key", "value"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["TYPE", "key"]).await;
+    assert_eq!(read_line(&mut client).await, "+string\r\n");
+}
+
+#[tokio::test]
+async fn test_object_encoding() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["OBJECT", "ENCODING", "list"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    let elements: Vec<String> = (0..10).map(|i| format!("element-{}", i)).collect();
+    let mut args = vec!["RPUSH", "list"];
+    args.extend(elements.iter().map(String::as_str));
+    send(&mut client, &args).await;
+    assert_eq!(read_line(&mut client).await, ":10\r\n");
+    send(&mut client, &["OBJECT", "ENCODING", "list"]).await;
+    assert_eq!(read_bulk(&mut client).await, "listpack");
+
+    // The limits are read from the parameters.
+    send(&mut client, &["CONFIG", "SET", "list-max-listpack-size", "5"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["OBJECT", "ENCODING", "list"]).await;
+    assert_eq!(read_bulk(&mut client).await, "quicklist");
+
+    send(&mut client, &["OBJECT", "FREQ", "list"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR unknown subcommand 'freq'. Try OBJECT HELP.\r\n"
+    );
+}
+
+/// Send a SCAN command, and read the next cursor along with the keys, sorted.
+async fn scan(stream: &mut BufReader<TcpStream>, args: &[&str]) -> (String, Vec<String>) {
+    send(stream, args).await;
+    assert_eq!(read_line(stream).await, "*2\r\n");
+    let cursor = read_bulk(stream).await;
+    let mut keys = read_bulk_array(stream).await;
+    keys.sort();
+    (cursor, keys)
+}
+
+#[tokio::test]
+async fn test_scan() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["MSET", "string1", "a", "string2", "b"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["RPUSH", "list1", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["LPUSH", "list2", "b"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["SADD", "set", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+
+    assert_eq!(
+        scan(&mut client, &["SCAN", "0", "TYPE", "list"]).await,
+        ("0".to_string(), vec!["list1".to_string(), "list2".to_string()])
+    );
+    assert_eq!(
+        scan(&mut client, &["SCAN", "0", "MATCH", "string*", "COUNT", "100"]).await,
+        ("0".to_string(), vec!["string1".to_string(), "string2".to_string()])
+    );
+    assert_eq!(
+        scan(&mut client, &["SCAN", "0", "TYPE", "hash"]).await,
+        ("0".to_string(), vec![])
+    );
+
+    send(&mut client, &["SCAN", "0", "TYPE", "nothing"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR unknown type name 'nothing'\r\n");
+    send(&mut client, &["SCAN", "x"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR invalid cursor\r\n");
+    send(&mut client, &["SCAN", "0", "COUNT"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR syntax error near argument 2\r\n");
+}
+
+#[tokio::test]
+async fn test_dbsize() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["MSET", "a", "1", "b", "2", "c", "3"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["PEXPIRE", "c", "1"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    send(&mut client, &["DBSIZE"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+
+    send(&mut client, &["DBSIZE", "extra"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR wrong number of arguments for 'dbsize' command\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_flushdb() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["MSET", "a", "1", "b", "2"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["FLUSHDB"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["DBSIZE"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    send(&mut client, &["GET", "a"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+}
+
+#[tokio::test]
+async fn test_keys() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["MSET", "user:1", "a", "session", "b"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["KEYS", "user:*"]).await;
+    assert_eq!(read_line(&mut client).await, "*1\r\n");
+    assert_eq!(read_line(&mut client).await, "$6\r\n");
+    assert_eq!(read_line(&mut client).await, "user:1\r\n");
+    send(&mut client, &["KEYS", "nothing*"]).await;
+    assert_eq!(read_line(&mut client).await, "*0\r\n");
+}
+
+#[tokio::test]
+async fn test_keys_limit() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    for i in 0..100 {
+        send(&mut client, &["SET", &format!("key{}", i), "value"]).await;
+        assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    }
+    send(&mut client, &["KEYS", "*", "LIMIT", "5"]).await;
+    assert_eq!(read_line(&mut client).await, "*5\r\n");
+    for _ in 0..5 {
+        assert!(read_line(&mut client).await.starts_with('$'));
+        assert!(read_line(&mut client).await.starts_with("key"));
+    }
+    send(&mut client, &["KEYS", "key9*", "limit", "0"]).await;
+    assert_eq!(read_line(&mut client).await, "*11\r\n");
+    for _ in 0..22 {
+        read_line(&mut client).await;
+    }
+    send(&mut client, &["KEYS", "*", "COUNT", "5"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR syntax error near argument 2\r\n");
+    send(&mut client, &["KEYS", "*", "LIMIT"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR syntax error near argument 2\r\n");
+}
+
+#[tokio::test]
+async fn test_incrby() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["INCRBY", "counter", "10"]).await;
+    assert_eq!(read_line(&mut client).await, ":10\r\n");
+    send(&mut client, &["INCRBY", "counter", "-15"]).await;
+    assert_eq!(read_line(&mut client).await, ":-5\r\n");
+    send(&mut client, &["DECRBY", "counter", "-7"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    send(&mut client, &["INCRBY", "counter", "x"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR value is not an integer or out of range\r\n"
+    );
+
+    send(&mut client, &["INCRBYFLOAT", "counter", "0.5"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "2.5\r\n");
+    send(&mut client, &["INCRBYFLOAT", "counter", "-3.5"]).await;
+    assert_eq!(read_line(&mut client).await, "$2\r\n");
+    assert_eq!(read_line(&mut client).await, "-1\r\n");
+
+    send(&mut client, &["SET", "text", "abc"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["INCRBYFLOAT", "text", "1"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR value is not a valid float or the result is not finite\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_setrange_getrange() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SETRANGE", "key", "6", "Redis"]).await;
+    assert_eq!(read_line(&mut client).await, ":11\r\n");
+    send(&mut client, &["SETRANGE", "key", "0", "Hello"]).await;
+    assert_eq!(read_line(&mut client).await, ":11\r\n");
+    send(&mut client, &["GETRANGE", "key", "0", "4"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "Hello\r\n");
+    send(&mut client, &["GETRANGE", "key", "-5", "-1"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "Redis\r\n");
+    send(&mut client, &["GETRANGE", "key", "5", "5"]).await;
+    assert_eq!(read_line(&mut client).await, "$1\r\n");
+    assert_eq!(read_line(&mut client).await, "\0\r\n");
+
+    send(&mut client, &["SETRANGE", "key", "-1", "x"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR offset is out of range\r\n");
+    send(&mut client, &["SETRANGE", "key", "536870912", "x"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+    );
+    // The end of the range overflows, the connection is still served.
+    send(&mut client, &["SETRANGE", "key", "18446744073709551615", "x"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+    );
+    send(&mut client, &["STRLEN", "key"]).await;
+    assert_eq!(read_line(&mut client).await, ":11\r\n");
+}
+
+#[tokio::test]
+async fn test_copy() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["COPY", "missing", "dst"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+
+    send(&mut client, &["SET", "src", "value", "EX", "100"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["COPY", "src", "dst"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["TTL", "dst"]).await;
+    assert_eq!(read_line(&mut client).await, ":100\r\n");
+
+    send(&mut client, &["SET", "src", "new"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["COPY", "src", "dst"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    send(&mut client, &["COPY", "src", "dst", "REPLACE"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["GET", "dst"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "new\r\n");
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(run_with_shutdown(listener, Config::default(), shutdown));
+
+    let mut client = connect(addr).await;
+    send(&mut client, &["PING"]).await;
+    assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+
+    trigger.send(()).unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(5), server)
+        .await
+        .expect("the server should stop")
+        .unwrap();
+    // The idle connection was closed, and no new one is accepted.
+    assert_eq!(read_line(&mut client).await, "");
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config {
+        max_connections: 2,
+        ..Config::default()
+    };
+    tokio::spawn(run_with_shutdown(listener, config, std::future::pending::<()>()));
+
+    let mut first = connect(addr).await;
+    let mut second = connect(addr).await;
+    for client in [&mut first, &mut second] {
+        send(client, &["PING"]).await;
+        assert_eq!(read_line(client).await, "+PONG\r\n");
+    }
+
+    // The connection is queued by the OS, but not served.
+    let mut third = connect(addr).await;
+    send(&mut third, &["PING"]).await;
+    let waiting = tokio::time::timeout(std::time::Duration::from_millis(100), read_line(&mut third)).await;
+    assert!(waiting.is_err(), "the third client should not be served yet");
+
+    drop(first);
+    let reply = tokio::time::timeout(std::time::Duration::from_secs(5), read_line(&mut third)).await;
+    assert_eq!(reply.unwrap(), "+PONG\r\n");
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config {
+        idle_timeout: Some(std::time::Duration::from_millis(100)),
+        ..Config::default()
+    };
+    tokio::spawn(run_with_shutdown(listener, config, std::future::pending::<()>()));
+
+    // An active client is kept.
+    let mut active = connect(addr).await;
+    for _ in 0..3 {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        send(&mut active, &["PING"]).await;
+        assert_eq!(read_line(&mut active).await, "+PONG\r\n");
+    }
+
+    // A silent one is closed by the server.
+    let mut silent = connect(addr).await;
+    let closed = tokio::time::timeout(std::time::Duration::from_secs(5), read_line(&mut silent)).await;
+    assert_eq!(closed.unwrap(), "");
+}
+
+#[tokio::test]
+async fn test_unknown_command_keeps_connection() {
+    let addr = start_server().await;
+    let mut stream = connect(addr).await;
+
+    send(&mut stream, &["FOO", "bar"]).await;
+    assert_eq!(read_line(&mut stream).await, "-ERR unknown command 'foo'\r\n");
+
+    // The connection is still served.
+    send(&mut stream, &["PING"]).await;
+    assert_eq!(read_line(&mut stream).await, "+PONG\r\n");
+}
+
+#[tokio::test]
+async fn test_command() {
+    let addr = start_server().await;
+    let mut stream = connect(addr).await;
+
+    // Sent by redis-cli when it connects.
+    send(&mut stream, &["COMMAND", "DOCS"]).await;
+    assert_eq!(read_line(&mut stream).await, "*0\r\n");
+    send(&mut stream, &["COMMAND", "DOCS", "get", "set"]).await;
+    assert_eq!(read_line(&mut stream).await, "*0\r\n");
+    send(&mut stream, &["COMMAND"]).await;
+    assert_eq!(read_line(&mut stream).await, "*0\r\n");
+
+    send(&mut stream, &["COMMAND", "COUNT"]).await;
+    let count = read_line(&mut stream).await;
+    assert!(count.starts_with(':'), "{}", count);
+    assert!(count.trim_end()[1..].parse::<i64>().unwrap() > 0);
+
+    send(&mut stream, &["COMMAND", "FOO"]).await;
+    assert_eq!(
+        read_line(&mut stream).await,
+        "-ERR unknown subcommand 'foo'. Try COMMAND HELP.\r\n"
+    );
+
+    send(&mut stream, &["COMMAND", "GETKEYS", "MSET", "a", "1", "b", "2"]).await;
+    assert_eq!(read_line(&mut stream).await, "*2\r\n");
+    assert_eq!(read_line(&mut stream).await, "$1\r\n");
+    assert_eq!(read_line(&mut stream).await, "a\r\n");
+    assert_eq!(read_line(&mut stream).await, "$1\r\n");
+    assert_eq!(read_line(&mut stream).await, "b\r\n");
+    send(&mut stream, &["COMMAND", "GETKEYS", "PING"]).await;
+    assert_eq!(
+        read_line(&mut stream).await,
+        "-ERR The command has no key arguments\r\n"
+    );
+    send(&mut stream, &["COMMAND", "GETKEYS"]).await;
+    assert_eq!(
+        read_line(&mut stream).await,
+        "-ERR wrong number of arguments for 'command|getkeys' command\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_ping() {
+    let addr = start_server().await;
+    let mut stream = connect(addr).await;
+
+    send(&mut stream, &["PING"]).await;
+    assert_eq!(read_line(&mut stream).await, "+PONG\r\n");
+
+    send(&mut stream, &["PING", "hello world"]).await;
+    assert_eq!(read_line(&mut stream).await, "$11\r\n");
+    assert_eq!(read_line(&mut stream).await, "hello world\r\n");
+}
+
+#[tokio::test]
+async fn test_echo() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["ECHO", "hello"]).await;
+    assert_eq!(read_line(&mut client).await, "$5\r\n");
+    assert_eq!(read_line(&mut client).await, "hello\r\n");
+
+    send(&mut client, &["ECHO", ""]).await;
+    assert_eq!(read_line(&mut client).await, "$0\r\n");
+    assert_eq!(read_line(&mut client).await, "\r\n");
+
+    client
+        .write_all(b"*2\r\n$4\r\nECHO\r\n$4\r\n\x00\r\n\xff\r\n")
+        .await
+        .unwrap();
+    let mut reply = [0u8; 10];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"$4\r\n\x00\r\n\xff\r\n");
+
+    for args in [&["ECHO"][..], &["ECHO", "a", "b"]] {
+        send(&mut client, args).await;
+        assert_eq!(
+            read_line(&mut client).await,
+            "-ERR wrong number of arguments for 'echo' command\r\n"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_frame_limits() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config {
+        max_bulk_len: 16,
+        max_multibulk_len: 4,
+        ..Config::default()
+    };
+    tokio::spawn(run_with_shutdown(listener, config, std::future::pending::<()>()));
+
+    // Within the limits.
+    let mut client = connect(addr).await;
+    send(&mut client, &["SET", "key", "0123456789abcdef"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+
+    // Only the header of the bulk string is sent, the server doesn't wait for the rest.
+    client
+        .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$1000000000\r\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR protocol error; invalid bulk length\r\n"
+    );
+    assert_eq!(read_line(&mut client).await, "", "the connection should be closed");
+
+    let mut client = connect(addr).await;
+    client.write_all(b"*5\r\n").await.unwrap();
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR protocol error; invalid multibulk length\r\n"
+    );
+    assert_eq!(read_line(&mut client).await, "", "the connection should be closed");
+}
+
+#[tokio::test]
+async fn test_inline_command() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    client.write_all(b"SET foo bar\r\nGET foo\r\nPING\r\n").await.unwrap();
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "bar\r\n");
+    assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+}
+
+#[tokio::test]
+async fn test_select() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SELECT", "1"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["EXISTS", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+
+    send(&mut client, &["SELECT", "0"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+
+    // Another client starts in db 0.
+    let mut other = connect(addr).await;
+    send(&mut other, &["SELECT", "1"]).await;
+    assert_eq!(read_line(&mut other).await, "+OK\r\n");
+    send(&mut other, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut other).await, "$3\r\n");
+    assert_eq!(read_line(&mut other).await, "bar\r\n");
+
+    for index in ["16", "-1"] {
+        send(&mut client, &["SELECT", index]).await;
+        assert_eq!(read_line(&mut client).await, "-ERR DB index is out of range\r\n");
+    }
+    send(&mut client, &["SELECT", "one"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR value is not an integer or out of range\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_flushall() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    for db in ["0", "1"] {
+        send(&mut client, &["SELECT", db]).await;
+        assert_eq!(read_line(&mut client).await, "+OK\r\n");
+        send(&mut client, &["SET", "foo", "bar"]).await;
+        assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    }
+
+    send(&mut client, &["FLUSHALL"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+
+    for db in ["0", "1"] {
+        send(&mut client, &["SELECT", db]).await;
+        assert_eq!(read_line(&mut client).await, "+OK\r\n");
+        send(&mut client, &["DBSIZE"]).await;
+        assert_eq!(read_line(&mut client).await, ":0\r\n");
+    }
+}
+
+/// Read a bulk string reply, e.g. the report of INFO.
+async fn read_bulk(stream: &mut BufReader<TcpStream>) -> String {
+    let header = read_line(stream).await;
+    let len: usize = header.trim_end().strip_prefix('$').unwrap().parse().unwrap();
+    let mut buf = vec![0; len + 2];
+    stream.read_exact(&mut buf).await.unwrap();
+    buf.truncate(len);
+    String::from_utf8(buf).unwrap()
+}
+
+#[tokio::test]
+async fn test_info() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "baz", "qux", "EX", "100"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+
+    send(&mut client, &["INFO"]).await;
+    let info = read_bulk(&mut client).await;
+    assert!(info.starts_with("# Server\r\n"), "{}", info);
+    assert!(info.contains("\r\nuptime_in_seconds:"), "{}", info);
+    assert!(info.contains("\r\nconnected_clients:1\r\n"), "{}", info);
+    assert!(info.contains("\r\ndb0:keys=2,expires=1\r\n"), "{}", info);
+    // Empty databases are left out.
+    assert!(!info.contains("db1:"), "{}", info);
+
+    send(&mut client, &["INFO", "Clients"]).await;
+    assert_eq!(read_bulk(&mut client).await, "# Clients\r\nconnected_clients:1\r\n");
+    send(&mut client, &["INFO", "nothing"]).await;
+    assert_eq!(read_bulk(&mut client).await, "");
+}
+
+#[tokio::test]
+async fn test_info_commandstats() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    for _ in 0..2 {
+        send(&mut client, &["SET", "foo", "bar"]).await;
+        assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    }
+    // Counted across connections.
+    let mut other = connect(addr).await;
+    for _ in 0..3 {
+        send(&mut other, &["GET", "foo"]).await;
+        assert_eq!(read_line(&mut other).await, "$3\r\n");
+        assert_eq!(read_line(&mut other).await, "bar\r\n");
+    }
+    send(&mut other, &["NOSUCHCOMMAND"]).await;
+    read_line(&mut other).await;
+
+    send(&mut client, &["INFO", "commandstats"]).await;
+    assert_eq!(
+        read_bulk(&mut client).await,
+        "# Commandstats\r\ncmdstat_get:calls=3\r\ncmdstat_set:calls=2\r\n"
+    );
+
+    // Not part of the default sections.
+    send(&mut client, &["INFO"]).await;
+    assert!(!read_bulk(&mut client).await.contains("cmdstat_"));
+    send(&mut client, &["INFO", "all"]).await;
+    assert!(read_bulk(&mut client).await.contains("cmdstat_info:calls=2\r\n"));
+}
+
+/// Read an array reply of bulk strings.
+async fn read_bulk_array(stream: &mut BufReader<TcpStream>) -> Vec<String> {
+    let header = read_line(stream).await;
+    let len: usize = header.trim_end().strip_prefix('*').unwrap().parse().unwrap();
+    let mut items = vec![];
+    for _ in 0..len {
+        items.push(read_bulk(stream).await);
+    }
+    items
+}
+
+#[tokio::test]
+async fn test_error_line_breaks() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    // An echoed argument can't end the error early and inject a reply.
+    for command in ["CLIENT", "COMMAND", "CONFIG", "DEBUG"] {
+        send(&mut client, &[command, "y\r\n:42"]).await;
+        assert!(
+            read_line(&mut client)
+                .await
+                .starts_with("-ERR unknown subcommand 'y  :42'"),
+            "{}",
+            command
+        );
+        send(&mut client, &["PING"]).await;
+        assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+    }
+}
+
+#[tokio::test]
+async fn test_config() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["CONFIG", "GET", "maxmemory"]).await;
+    assert_eq!(read_bulk_array(&mut client).await, ["maxmemory", "0"]);
+    send(&mut client, &["CONFIG", "GET", "maxmemory*"]).await;
+    assert_eq!(
+        read_bulk_array(&mut client).await,
+        ["maxmemory", "0", "maxmemory-policy", "noeviction"]
+    );
+    send(&mut client, &["CONFIG", "GET", "nothing"]).await;
+    assert_eq!(read_line(&mut client).await, "*0\r\n");
+
+    send(&mut client, &["CONFIG", "SET", "maxmemory", "100mb"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["CONFIG", "GET", "MAXMEMORY"]).await;
+    assert_eq!(read_bulk_array(&mut client).await, ["maxmemory", "100mb"]);
+
+    // Nothing is set if a parameter is unknown.
+    send(&mut client, &["CONFIG", "SET", "save", "60 1", "nothing", "1"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR Unknown option or number of arguments for CONFIG SET - 'nothing'\r\n"
+    );
+    send(&mut client, &["CONFIG", "GET", "save"]).await;
+    assert_eq!(read_bulk_array(&mut client).await, ["save", ""]);
+    // Nor if a value is invalid.
+    send(&mut client, &["CONFIG", "SET", "save", "", "maxmemory", "lots"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR CONFIG SET failed (possibly related to argument 'maxmemory') - argument must be a memory value\r\n"
+    );
+    send(&mut client, &["CONFIG", "SET", "maxmemory-policy", "volatile-lru"]).await;
+    assert!(read_line(&mut client)
+        .await
+        .starts_with("-ERR CONFIG SET failed (possibly related to argument 'maxmemory-policy')"));
+}
+
+#[tokio::test]
+async fn test_maxmemory() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    send(
+        &mut client,
+        &["CONFIG", "SET", "maxmemory", "50", "maxmemory-policy", "allkeys-lru"],
+    )
+    .await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+
+    // Each key uses 9 bytes, so only the 5 most recently used fit. key0 is read all along.
+    for i in 0..10 {
+        send(&mut client, &["SET", &format!("key{}", i), "value"]).await;
+        assert_eq!(read_line(&mut client).await, "+OK\r\n");
+        send(&mut client, &["GET", "key0"]).await;
+        assert_eq!(read_bulk(&mut client).await, "value");
+    }
+    send(&mut client, &["DBSIZE"]).await;
+    assert_eq!(read_line(&mut client).await, ":5\r\n");
+    send(&mut client, &["EXISTS", "key0", "key1", "key6", "key7", "key8", "key9"]).await;
+    assert_eq!(read_line(&mut client).await, ":5\r\n");
+
+    // Without eviction, writes are refused once over the limit, the others are still served.
+    send(
+        &mut client,
+        &["CONFIG", "SET", "maxmemory", "20", "maxmemory-policy", "noeviction"],
+    )
+    .await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-OOM command not allowed when used memory > 'maxmemory'.\r\n"
+    );
+    send(&mut client, &["GET", "key9"]).await;
+    assert_eq!(read_bulk(&mut client).await, "value");
+    send(&mut client, &["DEL", "key0", "key6", "key7", "key8"]).await;
+    assert_eq!(read_line(&mut client).await, ":4\r\n");
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+}
+
+#[tokio::test]
+async fn test_pub_sub() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+    let mut publisher = connect(addr).await;
+
+    send(&mut publisher, &["PUBLISH", "news", "nobody"]).await;
+    assert_eq!(read_line(&mut publisher).await, ":0\r\n");
+
+    send(&mut subscriber, &["SUBSCRIBE", "news", "sports"]).await;
+    for (channel, count) in [("news", 1), ("sports", 2)] {
+        assert_eq!(read_line(&mut subscriber).await, "*3\r\n");
+        assert_eq!(read_bulk(&mut subscriber).await, "subscribe");
+        assert_eq!(read_bulk(&mut subscriber).await, channel);
+        assert_eq!(read_line(&mut subscriber).await, format!(":{}\r\n", count));
+    }
+
+    send(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+    assert_eq!(read_line(&mut publisher).await, ":1\r\n");
+    assert_eq!(read_bulk_array(&mut subscriber).await, ["message", "news", "hello"]);
+
+    // Only the pub/sub commands are accepted.
+    send(&mut subscriber, &["PING"]).await;
+    assert_eq!(read_bulk_array(&mut subscriber).await, ["pong", ""]);
+    send(&mut subscriber, &["GET", "foo"]).await;
+    assert!(read_line(&mut subscriber)
+        .await
+        .starts_with("-ERR Can't execute 'get': only (P|S)SUBSCRIBE"));
+
+    send(&mut subscriber, &["UNSUBSCRIBE", "news"]).await;
+    assert_eq!(read_line(&mut subscriber).await, "*3\r\n");
+    assert_eq!(read_bulk(&mut subscriber).await, "unsubscribe");
+    assert_eq!(read_bulk(&mut subscriber).await, "news");
+    assert_eq!(read_line(&mut subscriber).await, ":1\r\n");
+    send(&mut publisher, &["PUBLISH", "news", "lost"]).await;
+    assert_eq!(read_line(&mut publisher).await, ":0\r\n");
+
+    // Out of the subscribed mode once no channel is left.
+    send(&mut subscriber, &["UNSUBSCRIBE"]).await;
+    assert_eq!(read_line(&mut subscriber).await, "*3\r\n");
+    assert_eq!(read_bulk(&mut subscriber).await, "unsubscribe");
+    assert_eq!(read_bulk(&mut subscriber).await, "sports");
+    assert_eq!(read_line(&mut subscriber).await, ":0\r\n");
+    send(&mut subscriber, &["PING"]).await;
+    assert_eq!(read_line(&mut subscriber).await, "+PONG\r\n");
+}
+
+#[tokio::test]
+async fn test_quit_subscribed() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+    send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+    assert_eq!(read_line(&mut subscriber).await, "*3\r\n");
+    assert_eq!(read_bulk(&mut subscriber).await, "subscribe");
+    assert_eq!(read_bulk(&mut subscriber).await, "news");
+    assert_eq!(read_line(&mut subscriber).await, ":1\r\n");
+
+    send(&mut subscriber, &["QUIT"]).await;
+    assert_eq!(read_line(&mut subscriber).await, "+OK\r\n");
+    let mut rest = vec![];
+    subscriber.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty(), "{:?}", String::from_utf8_lossy(&rest));
+}
+
+#[tokio::test]
+async fn test_slow_subscriber() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+    let mut publisher = connect(addr).await;
+    send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+    assert_eq!(read_line(&mut subscriber).await, "*3\r\n");
+    assert_eq!(read_bulk(&mut subscriber).await, "subscribe");
+    assert_eq!(read_bulk(&mut subscriber).await, "news");
+    assert_eq!(read_line(&mut subscriber).await, ":1\r\n");
+
+    // The subscriber doesn't read, it is dropped once its messages pile up.
+    let message = "x".repeat(16 * 1024);
+    let mut published = 0;
+    loop {
+        send(&mut publisher, &["PUBLISH", "news", &message]).await;
+        if read_line(&mut publisher).await == ":0\r\n" {
+            break;
+        }
+        published += 1;
+        assert!(published < 100_000, "the subscriber was never dropped");
+    }
+    let mut received = vec![];
+    subscriber.read_to_end(&mut received).await.unwrap();
+    assert!(received.len() < published * message.len());
+}
+
+#[tokio::test]
+async fn test_list() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["LPUSH", "list", "b", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    send(&mut client, &["RPUSH", "list", "c"]).await;
+    assert_eq!(read_line(&mut client).await, ":3\r\n");
+    for (command, value) in [("LPOP", "a"), ("RPOP", "c"), ("RPOP", "b")] {
+        send(&mut client, &[command, "list"]).await;
+        assert_eq!(read_bulk(&mut client).await, value);
+    }
+    send(&mut client, &["LPOP", "list"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    send(&mut client, &["EXISTS", "list"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+
+    // A string is not a list, and the other way around.
+    let wrong_type = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["RPUSH", "foo", "a"]).await;
+    assert_eq!(read_line(&mut client).await, wrong_type);
+    send(&mut client, &["LPOP", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, wrong_type);
+    send(&mut client, &["RPUSH", "list", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["GET", "list"]).await;
+    assert_eq!(read_line(&mut client).await, wrong_type);
+    send(&mut client, &["TYPE", "list"]).await;
+    assert_eq!(read_line(&mut client).await, "+list\r\n");
+}
+
+#[tokio::test]
+async fn test_lrange() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["RPUSH", "list", "a", "b", "c"]).await;
+    assert_eq!(read_line(&mut client).await, ":3\r\n");
+    send(&mut client, &["LRANGE", "list", "0", "-1"]).await;
+    assert_eq!(read_bulk_array(&mut client).await, ["a", "b", "c"]);
+    send(&mut client, &["LRANGE", "list", "-2", "100"]).await;
+    assert_eq!(read_bulk_array(&mut client).await, ["b", "c"]);
+    send(&mut client, &["LRANGE", "list", "2", "1"]).await;
+    assert_eq!(read_line(&mut client).await, "*0\r\n");
+    send(&mut client, &["LLEN", "list"]).await;
+    assert_eq!(read_line(&mut client).await, ":3\r\n");
+    send(&mut client, &["LLEN", "missing"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+
+    let wrong_type = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["LRANGE", "foo", "0", "-1"]).await;
+    assert_eq!(read_line(&mut client).await, wrong_type);
+    send(&mut client, &["LLEN", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, wrong_type);
+}
+
+#[tokio::test]
+async fn test_set_type() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SADD", "set", "a", "b", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    send(&mut client, &["SADD", "set", "b", "c"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["SMEMBERS", "set"]).await;
+    let mut members = read_bulk_array(&mut client).await;
+    members.sort();
+    assert_eq!(members, ["a", "b", "c"]);
+    send(&mut client, &["SISMEMBER", "set", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["SREM", "set", "a", "d"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["SISMEMBER", "set", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    send(&mut client, &["TYPE", "set"]).await;
+    assert_eq!(read_line(&mut client).await, "+set\r\n");
+
+    let wrong_type = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    send(&mut client, &["RPUSH", "list", "a"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["SADD", "list", "a"]).await;
+    assert_eq!(read_line(&mut client).await, wrong_type);
+    send(&mut client, &["LPOP", "set"]).await;
+    assert_eq!(read_line(&mut client).await, wrong_type);
+}
+
+#[tokio::test]
+async fn test_reset() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["SELECT", "1"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["RESET"]).await;
+    assert_eq!(read_line(&mut client).await, "+RESET\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+
+    // Out of the subscribed mode, without any unsubscribe reply.
+    send(&mut client, &["SUBSCRIBE", "news"]).await;
+    assert_eq!(read_line(&mut client).await, "*3\r\n");
+    assert_eq!(read_bulk(&mut client).await, "subscribe");
+    assert_eq!(read_bulk(&mut client).await, "news");
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["RESET"]).await;
+    assert_eq!(read_line(&mut client).await, "+RESET\r\n");
+    send(&mut client, &["PING"]).await;
+    assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+    let mut publisher = connect(addr).await;
+    send(&mut publisher, &["PUBLISH", "news", "lost"]).await;
+    assert_eq!(read_line(&mut publisher).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_transaction() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    send(&mut client, &["MULTI"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    // Not run yet.
+    let mut other = connect(addr).await;
+    send(&mut other, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut other).await, "$-1\r\n");
+
+    send(&mut client, &["EXEC"]).await;
+    assert_eq!(read_line(&mut client).await, "*2\r\n");
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    assert_eq!(read_bulk(&mut client).await, "bar");
+    send(&mut client, &["EXEC"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR EXEC without MULTI\r\n");
+
+    send(&mut client, &["MULTI"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["DEL", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    send(&mut client, &["DISCARD"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["EXISTS", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+
+    // A rejected command aborts the whole transaction.
+    send(&mut client, &["MULTI"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["DEL", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    send(&mut client, &["GET"]).await;
+    assert!(read_line(&mut client)
+        .await
+        .starts_with("-ERR wrong number of arguments"));
+    send(&mut client, &["EXEC"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-EXECABORT Transaction discarded because of previous errors.\r\n"
+    );
+    send(&mut client, &["EXISTS", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+}
+
+#[tokio::test]
+async fn test_transaction_isolated() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    let mut other = connect(addr).await;
+    send(&mut client, &["MULTI"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    for args in [&["SET", "foo", "1"][..], &["DEBUG", "SLEEP", "0.2"], &["GET", "foo"]] {
+        send(&mut client, args).await;
+        assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    }
+    send(&mut client, &["EXEC"]).await;
+
+    // Sent while the transaction sleeps, it waits for the transaction to end.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    send(&mut other, &["SET", "foo", "2"]).await;
+    assert_eq!(read_line(&mut client).await, "*3\r\n");
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    assert_eq!(read_bulk(&mut client).await, "1");
+    assert_eq!(read_line(&mut other).await, "+OK\r\n");
+}
+
+#[tokio::test]
+async fn test_sleep_with_pending_exec() {
+    let addr = start_server().await;
+    let mut sleeper = connect(addr).await;
+    let mut client = connect(addr).await;
+    let mut other = connect(addr).await;
+    send(&mut sleeper, &["DEBUG", "SLEEP", "1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    // Neither the transaction nor the commands sent after it wait for the sleeping client.
+    let start = std::time::Instant::now();
+    send(&mut client, &["MULTI"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "foo", "1"]).await;
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    send(&mut client, &["EXEC"]).await;
+    assert_eq!(read_line(&mut client).await, "*1\r\n");
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut other, &["PING"]).await;
+    assert_eq!(read_line(&mut other).await, "+PONG\r\n");
+    assert!(
+        start.elapsed() < std::time::Duration::from_millis(500),
+        "{:?}",
+        start.elapsed()
+    );
+    assert_eq!(read_line(&mut sleeper).await, "+OK\r\n");
+}
+
+#[tokio::test]
+async fn test_pipeline() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    // All the commands in one write, before reading any reply.
+    client.write_all(&b"*1\r\n$4\r\nPING\r\n".repeat(100)).await.unwrap();
+    let start = std::time::Instant::now();
+    for _ in 0..100 {
+        assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+    }
+    assert!(
+        start.elapsed() < std::time::Duration::from_secs(1),
+        "{:?}",
+        start.elapsed()
+    );
+    send(&mut client, &["ECHO", "done"]).await;
+    assert_eq!(read_line(&mut client).await, "$4\r\n");
+    assert_eq!(read_line(&mut client).await, "done\r\n");
+}
+
+#[tokio::test]
+async fn test_aof() {
+    let path = std::env::temp_dir().join(format!("my-redis-{}.aof", nanoid::nanoid!()));
+    let config = Config {
+        aof_path: Some(path.clone()),
+        ..Config::default()
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(run_with_shutdown(listener, config.clone(), shutdown));
+    let mut client = connect(addr).await;
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SET", "gone", "soon"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["DEL", "gone"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    // A failed write is not logged, it would fail the replay.
+    send(&mut client, &["INCR", "foo"]).await;
+    assert!(read_line(&mut client).await.starts_with("-ERR"));
+    send(&mut client, &["SELECT", "1"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["MULTI"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["RPUSH", "list", "a", "b"]).await;
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    send(&mut client, &["SET", "foo", "baz"]).await;
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    send(&mut client, &["EXEC"]).await;
+    assert_eq!(read_line(&mut client).await, "*2\r\n");
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    drop(client);
+    trigger.send(()).unwrap();
+    server.await.unwrap();
+
+    // The data is back after a restart.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (_trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(run_with_shutdown(listener, config, shutdown));
+    let mut client = connect(addr).await;
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "bar\r\n");
+    send(&mut client, &["EXISTS", "gone"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    send(&mut client, &["SELECT", "1"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "baz\r\n");
+    send(&mut client, &["LLEN", "list"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    send(&mut client, &["CONFIG", "GET", "appendonly"]).await;
+    assert_eq!(read_line(&mut client).await, "*2\r\n");
+    read_line(&mut client).await;
+    read_line(&mut client).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "yes\r\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_aof_concurrent_writes() {
+    let path = std::env::temp_dir().join(format!("my-redis-{}.aof", nanoid::nanoid!()));
+    let config = Config {
+        aof_path: Some(path.clone()),
+        ..Config::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(run_with_shutdown(listener, config.clone(), shutdown));
+
+    // Two clients append to the same key at once, the file must log the appends in the order
+    // they were applied.
+    let mut writers = vec![];
+    for suffix in ["a", "b"] {
+        let mut client = connect(addr).await;
+        writers.push(tokio::spawn(async move {
+            let mut pipeline = vec![];
+            for _ in 0..200 {
+                pipeline
+                    .extend_from_slice(format!("*3\r\n$6\r\nAPPEND\r\n$3\r\nkey\r\n$1\r\n{}\r\n", suffix).as_bytes());
+            }
+            client.write_all(&pipeline).await.unwrap();
+            for _ in 0..200 {
+                assert!(read_line(&mut client).await.starts_with(':'));
+            }
+        }));
+    }
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    let mut client = connect(addr).await;
+    send(&mut client, &["GET", "key"]).await;
+    let before = read_bulk(&mut client).await;
+    drop(client);
+    trigger.send(()).unwrap();
+    server.await.unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (_trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(run_with_shutdown(listener, config, shutdown));
+    let mut client = connect(addr).await;
+    send(&mut client, &["GET", "key"]).await;
+    assert_eq!(read_bulk(&mut client).await, before);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_auth() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config {
+        requirepass: Some("secret".to_string()),
+        ..Config::default()
+    };
+    tokio::spawn(run_with_config(listener, config));
+    let mut monitor = connect(addr).await;
+    send(&mut monitor, &["MONITOR"]).await;
+    assert_eq!(read_line(&mut monitor).await, "-NOAUTH Authentication required.\r\n");
+
+    let mut client = connect(addr).await;
+    send(&mut client, &["SET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "-NOAUTH Authentication required.\r\n");
+    send(&mut client, &["AUTH", "wrong"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR invalid password\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "-NOAUTH Authentication required.\r\n");
+    send(&mut client, &["AUTH", "secret"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["PING"]).await;
+    assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+
+    // The password is not shown to the monitors.
+    send(&mut monitor, &["AUTH", "secret"]).await;
+    assert_eq!(read_line(&mut monitor).await, "+OK\r\n");
+    send(&mut monitor, &["MONITOR"]).await;
+    assert_eq!(read_line(&mut monitor).await, "+OK\r\n");
+    send(&mut client, &["AUTH", "secret"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    let line = read_line(&mut monitor).await;
+    assert!(line.ends_with("\"AUTH\" \"(redacted)\"\r\n"), "{}", line);
+
+    // RESET logs the client out.
+    send(&mut client, &["RESET"]).await;
+    assert_eq!(read_line(&mut client).await, "+RESET\r\n");
+    send(&mut client, &["PING"]).await;
+    assert_eq!(read_line(&mut client).await, "-NOAUTH Authentication required.\r\n");
+
+    // Also in the subscribed mode.
+    send(&mut client, &["AUTH", "secret"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["SUBSCRIBE", "channel"]).await;
+    for line in ["*3\r\n", "$9\r\n", "subscribe\r\n", "$7\r\n", "channel\r\n", ":1\r\n"] {
+        assert_eq!(read_line(&mut client).await, line);
+    }
+    send(&mut client, &["RESET"]).await;
+    assert_eq!(read_line(&mut client).await, "+RESET\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "-NOAUTH Authentication required.\r\n");
+}
+
+#[tokio::test]
+async fn test_auth_without_password() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    send(&mut client, &["PING"]).await;
+    assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+    send(&mut client, &["AUTH", "secret"]).await;
+    assert!(read_line(&mut client)
+        .await
+        .starts_with("-ERR AUTH <password> called without any password configured"));
+}
+
+#[tokio::test]
+async fn test_quit() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    send(&mut client, &["MULTI"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    // Pipelined, the replies of the commands before QUIT are sent, the ones after are not run.
+    client
+        .write_all(b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nQUIT\r\n*1\r\n$4\r\nPING\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_line(&mut client).await, "+QUEUED\r\n");
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    let mut rest = vec![];
+    client.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty(), "{:?}", String::from_utf8_lossy(&rest));
+}
+
+#[tokio::test]
+async fn test_debug_sleep() {
+    let addr = start_server().await;
+    let mut sleeper = connect(addr).await;
+    let start = std::time::Instant::now();
+    send(&mut sleeper, &["DEBUG", "SLEEP", "0.2"]).await;
+    // The other connections are served in the meantime.
+    let mut client = connect(addr).await;
+    send(&mut client, &["PING"]).await;
+    assert_eq!(read_line(&mut client).await, "+PONG\r\n");
+    assert!(start.elapsed() < std::time::Duration::from_millis(200));
+    assert_eq!(read_line(&mut sleeper).await, "+OK\r\n");
+    assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+
+    send(&mut client, &["DEBUG", "SLEEP", "-1"]).await;
+    assert_eq!(read_line(&mut client).await, "-ERR value is not a valid float\r\n");
+    send(&mut client, &["DEBUG", "JMAP"]).await;
+    assert_eq!(read_line(&mut client).await, "+OK\r\n");
+    send(&mut client, &["DEBUG", "NOPE"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR unknown subcommand 'nope'. Try DEBUG HELP.\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_setnx() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    send(&mut client, &["SETNX", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["SETNX", "foo", "baz"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "bar\r\n");
+}
+
+#[tokio::test]
+async fn test_getset() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    send(&mut client, &["GETSET", "foo", "bar"]).await;
+    assert_eq!(read_line(&mut client).await, "$-1\r\n");
+    send(&mut client, &["EXPIRE", "foo", "100"]).await;
+    assert_eq!(read_line(&mut client).await, ":1\r\n");
+    send(&mut client, &["GETSET", "foo", "baz"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "bar\r\n");
+    // Like SET, the TTL is cleared.
+    send(&mut client, &["TTL", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, ":-1\r\n");
+    send(&mut client, &["GET", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, "$3\r\n");
+    assert_eq!(read_line(&mut client).await, "baz\r\n");
+}
+
+#[tokio::test]
+async fn test_set_invalid_expire() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    send(&mut client, &["SET", "foo", "bar", "PX", "0"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR invalid expire time in 'set' command\r\n"
+    );
+    send(&mut client, &["SET", "foo", "bar", "EX", "-1"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR invalid expire time in 'set' command\r\n"
+    );
+    send(&mut client, &["SET", "foo", "bar", "PX", "9223372036854775807"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR invalid expire time in 'set' command\r\n"
+    );
+    // Nothing was set.
+    send(&mut client, &["EXISTS", "foo"]).await;
+    assert_eq!(read_line(&mut client).await, ":0\r\n");
+}
+
+#[tokio::test]
+async fn test_max_bulk_len_writes() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config {
+        max_bulk_len: 8,
+        ..Config::default()
+    };
+    tokio::spawn(run_with_config(listener, config));
+    let mut client = connect(addr).await;
+    send(&mut client, &["APPEND", "key", "Hello"]).await;
+    assert_eq!(read_line(&mut client).await, ":5\r\n");
+    send(&mut client, &["APPEND", "key", "World"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+    );
+    send(&mut client, &["SETRANGE", "key", "5", "!!!!"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+    );
+    send(&mut client, &["SETRANGE", "key", "5", "!!!"]).await;
+    assert_eq!(read_line(&mut client).await, ":8\r\n");
+    // An empty write changes nothing, whatever the offset.
+    send(&mut client, &["SETRANGE", "key", "100", ""]).await;
+    assert_eq!(read_line(&mut client).await, ":8\r\n");
+}
+
+#[tokio::test]
+async fn test_push_capped() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    for i in 0..10 {
+        send(&mut client, &["RPUSHCAPPED", "log", "3", &i.to_string()]).await;
+        assert_eq!(read_line(&mut client).await, format!(":{}\r\n", (i + 1).min(3)));
+    }
+    // The oldest elements were dropped.
+    send(&mut client, &["LRANGE", "log", "0", "-1"]).await;
+    assert_eq!(read_bulk_array(&mut client).await, ["7", "8", "9"]);
+    send(&mut client, &["LPUSHCAPPED", "log", "2", "new"]).await;
+    assert_eq!(read_line(&mut client).await, ":2\r\n");
+    send(&mut client, &["LRANGE", "log", "0", "-1"]).await;
+    assert_eq!(read_bulk_array(&mut client).await, ["new", "7"]);
+
+    send(&mut client, &["RPUSHCAPPED", "log", "0", "x"]).await;
+    assert_eq!(
+        read_line(&mut client).await,
+        "-ERR maxlen is out of range, must be positive\r\n"
+    );
+}